@@ -0,0 +1,286 @@
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+use crate::errors::NeoError;
+
+// CIDR网段，单个IP视为/32或/128
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// 地址是否属于该网段（IPv4映射的IPv6地址按IPv4处理）
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+            v4 => v4,
+        };
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => prefix_eq(
+                u32::from(net) as u128,
+                u32::from(ip) as u128,
+                self.prefix,
+                32,
+            ),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_eq(u128::from(net), u128::from(ip), self.prefix, 128)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn prefix_eq(net: u128, ip: u128, prefix: u8, bits: u8) -> bool {
+    if prefix == 0 {
+        return true;
+    }
+    let shift = bits - prefix;
+    net >> shift == ip >> shift
+}
+
+impl FromStr for Cidr {
+    type Err = NeoError;
+
+    fn from_str(s: &str) -> Result<Self, NeoError> {
+        let invalid = || NeoError::Other(format!("Invalid CIDR: {}", s));
+        let (addr, prefix) = match s.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s.trim(), None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p.parse::<u8>().map_err(|_| invalid())?,
+            None => max,
+        };
+        if prefix > max {
+            return Err(invalid());
+        }
+        Ok(Cidr { addr, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// 解析网段列表
+#[cfg_attr(not(feature = "config-file"), allow(dead_code))]
+pub fn parse_cidrs<S: AsRef<str>>(items: &[S]) -> Result<Vec<Cidr>, NeoError> {
+    items.iter().map(|s| s.as_ref().parse()).collect()
+}
+
+// CONNECT目标访问控制
+//
+// 先检查拒绝列表；允许列表非空时，目标必须命中允许列表。端口同理。
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Acl {
+    pub allow: Vec<Cidr>,
+    pub deny: Vec<Cidr>,
+    pub allow_ports: Vec<u16>,
+    pub deny_ports: Vec<u16>,
+    /// 允许发起CONNECT的客户端证书身份，为空时不限制
+    pub allow_identities: Vec<String>,
+    /// 允许访问的客户端网段，在解码请求前检查，为空时不限制
+    pub allow_clients: Vec<Cidr>,
+    /// 可信的反向代理，只有来自这些地址的请求才采信转发头
+    pub trusted_proxies: Vec<Cidr>,
+}
+
+impl Acl {
+    /// 检查目标地址，拒绝时返回原因
+    pub fn check(&self, target: &SocketAddr) -> Result<(), &'static str> {
+        let ip = target.ip();
+        let port = target.port();
+        if self.deny.iter().any(|c| c.contains(ip)) || self.deny_ports.contains(&port) {
+            return Err("Target denied by ACL");
+        }
+        if !self.allow.is_empty() && !self.allow.iter().any(|c| c.contains(ip)) {
+            return Err("Target not allowed by ACL");
+        }
+        if !self.allow_ports.is_empty() && !self.allow_ports.contains(&port) {
+            return Err("Target port not allowed by ACL");
+        }
+        Ok(())
+    }
+
+    /// 检查客户端证书身份，拒绝时返回原因
+    pub fn check_identity(&self, identity: Option<&str>) -> Result<(), &'static str> {
+        if self.allow_identities.is_empty() {
+            return Ok(());
+        }
+        match identity {
+            Some(identity) if self.allow_identities.iter().any(|i| i == identity) => Ok(()),
+            _ => Err("Client identity not allowed by ACL"),
+        }
+    }
+
+    /// 检查客户端地址，拒绝时返回原因
+    pub fn check_client(&self, client: Option<IpAddr>) -> Result<(), &'static str> {
+        if self.allow_clients.is_empty() {
+            return Ok(());
+        }
+        match client {
+            Some(ip) if self.allow_clients.iter().any(|c| c.contains(ip)) => Ok(()),
+            _ => Err("Client address not allowed by ACL"),
+        }
+    }
+
+    /// 确定真实的客户端地址
+    ///
+    /// 连接来自可信代理时，从右向左跳过转发链中的可信代理，第一个不可信的地址即客户端；
+    /// 优先使用Forwarded头，没有时使用X-Forwarded-For。转发头中没有端口时端口为0。
+    pub fn client_addr(
+        &self,
+        remote: SocketAddr,
+        forwarded: Option<&str>,
+        x_forwarded_for: Option<&str>,
+    ) -> SocketAddr {
+        if !self.is_trusted_proxy(remote.ip()) {
+            return remote;
+        }
+        let chain: Vec<Option<SocketAddr>> = match (forwarded, x_forwarded_for) {
+            (Some(forwarded), _) => forwarded
+                .split(',')
+                .map(|element| {
+                    element.split(';').find_map(|pair| {
+                        let (key, value) = pair.split_once('=')?;
+                        key.trim().eq_ignore_ascii_case("for").then_some(value)
+                    })
+                })
+                .map(|node| node.and_then(parse_node))
+                .collect(),
+            (None, Some(list)) => list.split(',').map(parse_node).collect(),
+            (None, None) => return remote,
+        };
+        let mut client = remote;
+        for node in chain.into_iter().rev() {
+            // 无法识别的地址（unknown、混淆标识等）不再继续向左追溯
+            let Some(addr) = node else { break };
+            client = addr;
+            if !self.is_trusted_proxy(addr.ip()) {
+                break;
+            }
+        }
+        client
+    }
+
+    fn is_trusted_proxy(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|c| c.contains(ip))
+    }
+}
+
+// 解析转发头中的节点：IP、IP:端口、[IPv6]或[IPv6]:端口，可带引号
+fn parse_node(node: &str) -> Option<SocketAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(SocketAddr::new(ip, 0));
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr);
+    }
+    let ip = node.strip_prefix('[')?.strip_suffix(']')?;
+    ip.parse().ok().map(|ip| SocketAddr::new(ip, 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 测试网段解析与匹配
+    #[test]
+    fn test_cidr() {
+        let net: Cidr = "10.1.0.0/16".parse().expect("Parse failed");
+        assert!(net.contains("10.1.2.3".parse().unwrap()));
+        assert!(!net.contains("10.2.0.1".parse().unwrap()));
+        assert!(net.contains("::ffff:10.1.0.9".parse().unwrap()));
+
+        let v6: Cidr = "fd00::/8".parse().expect("Parse failed");
+        assert!(v6.contains("fd12::1".parse().unwrap()));
+        assert!(!v6.contains("10.1.2.3".parse().unwrap()));
+
+        let host: Cidr = "192.168.1.1".parse().expect("Parse failed");
+        assert_eq!(host.to_string(), "192.168.1.1/32");
+        assert!(
+            "0.0.0.0/0"
+                .parse::<Cidr>()
+                .unwrap()
+                .contains("8.8.8.8".parse().unwrap())
+        );
+
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("example.com/8".parse::<Cidr>().is_err());
+    }
+
+    // 测试拒绝优先、允许列表和端口限制
+    #[test]
+    fn test_acl_check() {
+        let acl = Acl {
+            allow: parse_cidrs(&["10.0.0.0/8"]).unwrap(),
+            deny: parse_cidrs(&["10.0.0.1"]).unwrap(),
+            allow_ports: vec![80, 443],
+            deny_ports: vec![],
+            allow_identities: vec!["alice".to_string()],
+            allow_clients: vec![],
+            trusted_proxies: vec![],
+        };
+        assert!(acl.check(&"10.2.3.4:80".parse().unwrap()).is_ok());
+        assert!(acl.check(&"10.0.0.1:80".parse().unwrap()).is_err());
+        assert!(acl.check(&"192.168.0.1:80".parse().unwrap()).is_err());
+        assert!(acl.check(&"10.2.3.4:22".parse().unwrap()).is_err());
+
+        assert!(Acl::default().check(&"1.2.3.4:25".parse().unwrap()).is_ok());
+
+        assert!(acl.check_identity(Some("alice")).is_ok());
+        assert!(acl.check_identity(Some("bob")).is_err());
+        assert!(acl.check_identity(None).is_err());
+        assert!(Acl::default().check_identity(None).is_ok());
+    }
+
+    // 测试客户端白名单和可信代理的转发头处理
+    #[test]
+    fn test_client_addr() {
+        let acl = Acl {
+            allow_clients: parse_cidrs(&["203.0.113.0/24", "2001:db8::/32"]).unwrap(),
+            trusted_proxies: parse_cidrs(&["10.0.0.0/8"]).unwrap(),
+            ..Acl::default()
+        };
+        let proxy: SocketAddr = "10.0.0.2:5000".parse().unwrap();
+        let direct: SocketAddr = "198.51.100.7:5000".parse().unwrap();
+        let client = |remote, forwarded, xff| acl.client_addr(remote, forwarded, xff).to_string();
+
+        // 不可信来源的转发头被忽略
+        assert_eq!(
+            client(direct, None, Some("203.0.113.9")),
+            "198.51.100.7:5000"
+        );
+        assert_eq!(client(proxy, None, None), "10.0.0.2:5000");
+        // 从右向左跳过可信代理，伪造的最左侧地址不被采信
+        assert_eq!(
+            client(proxy, None, Some("203.0.113.66, 198.51.100.1, 10.1.1.1")),
+            "198.51.100.1:0"
+        );
+        assert_eq!(
+            client(
+                proxy,
+                Some(r#"for=203.0.113.5;proto=https, for="[2001:db8::1]:4711""#),
+                Some("198.51.100.1")
+            ),
+            "[2001:db8::1]:4711"
+        );
+        assert_eq!(client(proxy, Some("for=unknown"), None), "10.0.0.2:5000");
+
+        assert!(
+            acl.check_client(Some("203.0.113.5".parse().unwrap()))
+                .is_ok()
+        );
+        assert!(acl.check_client(Some("10.0.0.2".parse().unwrap())).is_err());
+        assert!(acl.check_client(None).is_err());
+        assert!(Acl::default().check_client(None).is_ok());
+    }
+}
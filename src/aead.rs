@@ -0,0 +1,401 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use chacha20poly1305::aead::{Aead as _, KeyInit, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use hkdf::Hkdf;
+use rand::RngCore;
+use sha2::Sha256;
+
+use crate::errors::NeoError;
+use crate::rotation::Rotation;
+
+/// XChaCha20-Poly1305 的随机数长度（192位，随机生成即可视为唯一）
+pub const NONCE_LEN: usize = 24;
+/// Poly1305 认证标签长度
+pub const TAG_LEN: usize = 16;
+/// 帧头部的密钥标识长度
+pub const KEY_ID_LEN: usize = 8;
+
+// 密钥标识，全零表示由预共享密码派生的密钥
+pub type KeyId = [u8; KEY_ID_LEN];
+pub const PSK_KEY_ID: KeyId = [0; KEY_ID_LEN];
+
+/// 最多保留的会话密钥数量，超出后淘汰最久未使用的密钥
+const MAX_SESSION_KEYS: usize = 1024;
+/// 会话密钥闲置超过该时间后失效，客户端需重新握手
+const SESSION_KEY_IDLE: Duration = Duration::from_secs(30 * 60);
+
+const KDF_SALT: &[u8] = b"neorust-aead-salt";
+const KDF_INFO: &[u8] = b"neorust-aead-v1";
+
+// AEAD帧封装：key_id(8) || nonce(24) || ciphertext || tag(16)
+// key_id 作为附加认证数据参与校验
+#[derive(Clone)]
+pub struct Aead {
+    cipher: XChaCha20Poly1305,
+}
+
+impl Aead {
+    /// 使用32字节密钥创建实例
+    pub fn new(key: &[u8; 32]) -> Self {
+        Aead {
+            cipher: XChaCha20Poly1305::new(Key::from_slice(key)),
+        }
+    }
+
+    /// 从配置的密码派生密钥（HKDF-SHA256）
+    pub fn from_password(password: &[u8]) -> Self {
+        Self::new(&derive_key(password))
+    }
+
+    /// 加密并认证载荷，每条消息使用新的随机nonce
+    pub fn seal(&self, key_id: &KeyId, plaintext: &[u8]) -> Vec<u8> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::rng().fill_bytes(&mut nonce);
+
+        // 仅在输入超出算法上限（约256GB）时失败，这里不可能出现
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad: key_id,
+                },
+            )
+            .expect("AEAD encryption failed");
+
+        let mut frame = Vec::with_capacity(KEY_ID_LEN + NONCE_LEN + ciphertext.len());
+        frame.extend_from_slice(key_id);
+        frame.extend_from_slice(&nonce);
+        frame.extend_from_slice(&ciphertext);
+        frame
+    }
+
+    /// 校验并解密帧，认证失败的消息一律拒绝
+    pub fn open(&self, frame: &[u8]) -> Result<Vec<u8>, NeoError> {
+        if frame.len() < KEY_ID_LEN + NONCE_LEN + TAG_LEN {
+            return Err(NeoError::AuthFailed);
+        }
+        let (key_id, rest) = frame.split_at(KEY_ID_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: key_id,
+                },
+            )
+            .map_err(|_| NeoError::AuthFailed)
+    }
+}
+
+/// 读取帧头部的密钥标识
+pub fn frame_key_id(frame: &[u8]) -> Option<KeyId> {
+    frame.get(..KEY_ID_LEN)?.try_into().ok()
+}
+
+/// 密码 -> 32字节密钥
+pub fn derive_key(password: &[u8]) -> [u8; 32] {
+    let hk = Hkdf::<Sha256>::new(Some(KDF_SALT), password);
+    let mut key = [0u8; 32];
+    hk.expand(KDF_INFO, &mut key)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    key
+}
+
+// 解开请求帧所用的密钥，响应需用同一密钥封装
+#[derive(Clone)]
+pub struct FrameKey {
+    pub id: KeyId,
+    aead: Aead,
+}
+
+impl FrameKey {
+    /// 是否为预共享密码派生的密钥
+    pub fn pre_shared(&self) -> bool {
+        self.id == PSK_KEY_ID
+    }
+}
+
+// 会话密钥及其最后一次成功解开请求的时间
+#[derive(Default)]
+struct SessionKeys {
+    keys: HashMap<KeyId, (Aead, Instant)>,
+}
+
+impl SessionKeys {
+    fn remove_idle(&mut self, now: Instant) {
+        self.keys
+            .retain(|_, (_, used)| now.duration_since(*used) < SESSION_KEY_IDLE);
+    }
+}
+
+// 密钥存储：预共享密码派生的密钥（当前密码和轮换前的旧密码） + 每个客户端协商出的会话密钥
+#[derive(Clone, Default)]
+pub struct KeyStore {
+    psk: Option<Rotation<Aead>>,
+    sessions: Arc<Mutex<SessionKeys>>,
+}
+
+impl KeyStore {
+    /// 使用配置的密码创建，启用后所有请求都必须是AEAD帧
+    pub fn with_password(password: &[u8]) -> Self {
+        KeyStore {
+            psk: Some(Rotation::new("key", Aead::from_password(password))),
+            sessions: Arc::default(),
+        }
+    }
+
+    /// 同时接受轮换前的旧密码，直到not_after
+    pub fn with_previous(mut self, password: &[u8], not_after: SystemTime) -> Self {
+        self.psk = self
+            .psk
+            .map(|psk| psk.with_previous(Aead::from_password(password), not_after));
+        self
+    }
+
+    /// 沿用另一份密钥存储中已协商的会话密钥
+    pub fn with_sessions_of(mut self, other: &KeyStore) -> Self {
+        self.sessions = Arc::clone(&other.sessions);
+        self
+    }
+
+    /// 是否启用了AEAD封装层
+    pub fn enabled(&self) -> bool {
+        self.psk.is_some()
+    }
+
+    /// 按帧头的密钥标识选择密钥并解开帧；未启用时原样返回
+    pub fn open(&self, frame: Vec<u8>) -> Result<(Vec<u8>, Option<FrameKey>), NeoError> {
+        let Some(psk) = &self.psk else {
+            return Ok((frame, None));
+        };
+
+        let id = frame_key_id(&frame).ok_or(NeoError::AuthFailed)?;
+        if id == PSK_KEY_ID {
+            return self.open_psk(psk, &frame);
+        }
+        self.open_session(id, &frame, Instant::now())
+    }
+
+    // 闲置过久的会话密钥不再接受；只有成功解开的请求才刷新使用时间，伪造的帧不能延长其有效期
+    fn open_session(
+        &self,
+        id: KeyId,
+        frame: &[u8],
+        now: Instant,
+    ) -> Result<(Vec<u8>, Option<FrameKey>), NeoError> {
+        let aead = {
+            let mut sessions = self.sessions.lock().expect("key store poisoned");
+            match sessions.keys.get(&id) {
+                Some((aead, used)) if now.duration_since(*used) < SESSION_KEY_IDLE => aead.clone(),
+                Some(_) => {
+                    sessions.keys.remove(&id);
+                    return Err(NeoError::AuthFailed);
+                }
+                None => return Err(NeoError::AuthFailed),
+            }
+        };
+
+        let payload = aead.open(frame)?;
+        if let Some((_, used)) = self
+            .sessions
+            .lock()
+            .expect("key store poisoned")
+            .keys
+            .get_mut(&id)
+        {
+            *used = (*used).max(now);
+        }
+        Ok((payload, Some(FrameKey { id, aead })))
+    }
+
+    // 当前密码解不开时依次尝试未到期的旧密码，响应使用同一密码
+    fn open_psk(
+        &self,
+        psk: &Rotation<Aead>,
+        frame: &[u8],
+    ) -> Result<(Vec<u8>, Option<FrameKey>), NeoError> {
+        psk.try_each(|aead| {
+            let payload = aead.open(frame)?;
+            let (id, aead) = (PSK_KEY_ID, aead.clone());
+            Ok((payload, Some(FrameKey { id, aead })))
+        })
+    }
+
+    /// 使用请求所用的密钥封装响应
+    pub fn seal(&self, payload: Vec<u8>, key: Option<&FrameKey>) -> Vec<u8> {
+        match key {
+            Some(key) => key.aead.seal(&key.id, &payload),
+            None => payload,
+        }
+    }
+
+    /// 保存会话密钥并分配随机的非零标识
+    ///
+    /// 密钥数量达到上限时先清除闲置过久的密钥，仍然已满则淘汰最久未使用的密钥。
+    pub fn insert(&self, aead: Aead) -> KeyId {
+        let now = Instant::now();
+        let mut sessions = self.sessions.lock().expect("key store poisoned");
+        let mut id = PSK_KEY_ID;
+        while id == PSK_KEY_ID || sessions.keys.contains_key(&id) {
+            rand::rng().fill_bytes(&mut id);
+        }
+
+        if sessions.keys.len() >= MAX_SESSION_KEYS {
+            sessions.remove_idle(now);
+        }
+        if sessions.keys.len() >= MAX_SESSION_KEYS
+            && let Some(oldest) = sessions
+                .keys
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(id, _)| *id)
+        {
+            sessions.keys.remove(&oldest);
+        }
+        sessions.keys.insert(id, (aead, now));
+        id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 测试加解密往返
+    #[test]
+    fn test_seal_open_roundtrip() {
+        let aead = Aead::from_password(b"password");
+        let frame = aead.seal(&PSK_KEY_ID, b"blv payload");
+
+        assert_eq!(
+            frame.len(),
+            KEY_ID_LEN + NONCE_LEN + b"blv payload".len() + TAG_LEN
+        );
+        assert_eq!(frame_key_id(&frame), Some(PSK_KEY_ID));
+        assert_eq!(aead.open(&frame).expect("Open failed"), b"blv payload");
+    }
+
+    // 测试每条消息的nonce不同
+    #[test]
+    fn test_unique_nonces() {
+        let aead = Aead::from_password(b"password");
+        let a = aead.seal(&PSK_KEY_ID, b"same");
+        let b = aead.seal(&PSK_KEY_ID, b"same");
+
+        assert_ne!(
+            a[KEY_ID_LEN..KEY_ID_LEN + NONCE_LEN],
+            b[KEY_ID_LEN..KEY_ID_LEN + NONCE_LEN]
+        );
+        assert_ne!(a, b);
+    }
+
+    // 测试篡改、截断、改写密钥标识和错误密钥均被拒绝
+    #[test]
+    fn test_reject_tampered() {
+        let aead = Aead::from_password(b"password");
+        let mut frame = aead.seal(&PSK_KEY_ID, b"blv payload");
+
+        let mut relabeled = frame.clone();
+        relabeled[0] = 1;
+        assert!(matches!(aead.open(&relabeled), Err(NeoError::AuthFailed)));
+
+        let other = Aead::from_password(b"other");
+        assert!(matches!(other.open(&frame), Err(NeoError::AuthFailed)));
+
+        assert!(matches!(aead.open(&frame[..10]), Err(NeoError::AuthFailed)));
+
+        let last = frame.len() - 1;
+        frame[last] ^= 0x01;
+        assert!(matches!(aead.open(&frame), Err(NeoError::AuthFailed)));
+    }
+
+    // 测试每个会话密钥按标识独立校验，且不能冒充预共享密钥
+    #[test]
+    fn test_key_store() {
+        let store = KeyStore::with_password(b"password");
+        let session = Aead::new(&[7; 32]);
+        let id = store.insert(session.clone());
+        assert_ne!(id, PSK_KEY_ID);
+
+        let (payload, key) = store
+            .open(session.seal(&id, b"blv payload"))
+            .expect("Open failed");
+        assert_eq!(payload, b"blv payload");
+        let response = store.seal(b"reply".to_vec(), key.as_ref());
+        assert_eq!(session.open(&response).expect("Open failed"), b"reply");
+
+        assert!(store.open(session.seal(&PSK_KEY_ID, b"forged")).is_err());
+        assert!(
+            store
+                .open(Aead::new(&[8; 32]).seal(&id, b"forged"))
+                .is_err()
+        );
+        let psk = Aead::from_password(b"password");
+        assert!(store.open(psk.seal(&PSK_KEY_ID, b"ok")).is_ok());
+    }
+
+    // 测试会话密钥闲置过久后失效，使用中的密钥保持有效
+    #[test]
+    fn test_session_key_idle() {
+        let store = KeyStore::with_password(b"password");
+        let session = Aead::new(&[7; 32]);
+        let id = store.insert(session.clone());
+        let now = Instant::now();
+
+        let frame = session.seal(&id, b"blv payload");
+        let later = now + SESSION_KEY_IDLE / 2;
+        assert!(store.open_session(id, &frame, later).is_ok());
+        assert!(
+            store
+                .open_session(id, &frame, later + SESSION_KEY_IDLE / 2)
+                .is_ok()
+        );
+
+        // 伪造的帧不刷新使用时间
+        let forged = Aead::new(&[8; 32]).seal(&id, b"forged");
+        let idle = later + SESSION_KEY_IDLE * 2;
+        assert!(
+            store
+                .open_session(id, &forged, idle - SESSION_KEY_IDLE / 2)
+                .is_err()
+        );
+        assert!(store.open_session(id, &frame, idle).is_err());
+        assert!(store.open(frame).is_err());
+    }
+
+    // 测试轮换后旧密码在到期前仍可使用，且响应使用同一密码
+    #[test]
+    fn test_previous_keys() {
+        use std::time::Duration;
+
+        let now = SystemTime::now();
+        let store = KeyStore::with_password(b"new")
+            .with_previous(b"old", now + Duration::from_secs(3600))
+            .with_previous(b"expired", now - Duration::from_secs(1));
+
+        let old = Aead::from_password(b"old");
+        let (payload, key) = store
+            .open(old.seal(&PSK_KEY_ID, b"blv payload"))
+            .expect("Open failed");
+        assert_eq!(payload, b"blv payload");
+        let response = store.seal(b"reply".to_vec(), key.as_ref());
+        assert_eq!(old.open(&response).expect("Open failed"), b"reply");
+
+        let new = Aead::from_password(b"new");
+        assert!(store.open(new.seal(&PSK_KEY_ID, b"ok")).is_ok());
+        let expired = Aead::from_password(b"expired");
+        assert!(store.open(expired.seal(&PSK_KEY_ID, b"late")).is_err());
+
+        // 重新加载后已有的会话密钥仍然有效
+        let session = Aead::new(&[7; 32]);
+        let id = store.insert(session.clone());
+        let reloaded = KeyStore::with_password(b"new").with_sessions_of(&store);
+        assert!(reloaded.open(session.seal(&id, b"session")).is_ok());
+    }
+}
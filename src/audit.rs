@@ -0,0 +1,378 @@
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::commands::Peer;
+use crate::errors::NeoError;
+use crate::log::{self, json_escape, timestamp};
+use crate::metrics::Totals;
+use crate::session::Session;
+
+const MAX_SIZE: u64 = 10 * 1024 * 1024;
+const KEEP: usize = 5;
+
+// 审计日志参数
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditOptions {
+    /// 日志文件路径，未配置时不记录
+    pub path: Option<PathBuf>,
+    /// 单个文件的最大字节数，超出后轮转，0表示不轮转
+    pub max_size: u64,
+    /// 保留的历史文件个数
+    pub keep: usize,
+}
+
+impl Default for AuditOptions {
+    fn default() -> Self {
+        AuditOptions {
+            path: None,
+            max_size: MAX_SIZE,
+            keep: KEEP,
+        }
+    }
+}
+
+// 审计事件
+pub enum Event<'a> {
+    Connect {
+        peer: &'a Peer,
+        mark: &'a str,
+        target: &'a str,
+        /// 失败时为错误信息
+        result: Result<(), String>,
+    },
+    Disconnect {
+        peer: &'a Peer,
+        mark: &'a str,
+        session: &'a Session,
+    },
+    /// 请求在分发前被拒绝，例如客户端地址不在白名单或认证失败
+    Reject { peer: &'a Peer, reason: &'a str },
+    /// DISCONNECT以外的会话关闭，reason为idle（空闲回收）、target（目标关闭连接）、replaced（同一mark重新CONNECT）、
+    /// control（控制套接字）、window（活动时间窗口外）或进程退出的原因
+    Evict {
+        mark: &'a str,
+        session: &'a Session,
+        reason: &'a str,
+    },
+    /// 进程退出前的最后一条记录，附带启动以来的累计统计
+    Shutdown { reason: &'a str, totals: &'a Totals },
+}
+
+impl Event<'_> {
+    /// 序列化为一行JSON（不含换行）
+    fn to_json(&self, ts: &str) -> String {
+        let mut fields = vec![("ts", quote(ts))];
+        match self {
+            Event::Connect {
+                peer,
+                mark,
+                target,
+                result,
+            } => {
+                fields.push(("event", quote("connect")));
+                push_peer(&mut fields, peer);
+                fields.push(("mark", quote(mark)));
+                fields.push(("target", quote(target)));
+                match result {
+                    Ok(()) => fields.push(("result", quote("ok"))),
+                    Err(e) => {
+                        fields.push(("result", quote("fail")));
+                        fields.push(("error", quote(e)));
+                    }
+                }
+            }
+            Event::Disconnect {
+                peer,
+                mark,
+                session,
+            } => {
+                fields.push(("event", quote("disconnect")));
+                push_peer(&mut fields, peer);
+                fields.push(("mark", quote(mark)));
+                push_session(&mut fields, session);
+            }
+            Event::Reject { peer, reason } => {
+                fields.push(("event", quote("reject")));
+                push_peer(&mut fields, peer);
+                fields.push(("reason", quote(reason)));
+            }
+            Event::Evict {
+                mark,
+                session,
+                reason,
+            } => {
+                fields.push(("event", quote("evict")));
+                fields.push(("mark", quote(mark)));
+                fields.push(("reason", quote(reason)));
+                push_session(&mut fields, session);
+            }
+            Event::Shutdown { reason, totals } => {
+                fields.push(("event", quote("shutdown")));
+                fields.push(("reason", quote(reason)));
+                fields.push(("requests", totals.requests.to_string()));
+                fields.push(("connects_ok", totals.connects_ok.to_string()));
+                fields.push(("connects_failed", totals.connects_failed.to_string()));
+                fields.push(("bytes_forwarded", totals.bytes_forwarded.to_string()));
+                fields.push(("bytes_read", totals.bytes_read.to_string()));
+                fields.push(("decode_failures", totals.decode_failures.to_string()));
+            }
+        }
+
+        let body: Vec<String> = fields
+            .iter()
+            .map(|(name, value)| format!("\"{}\":{}", name, value))
+            .collect();
+        format!("{{{}}}", body.join(","))
+    }
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", json_escape(s))
+}
+
+fn push_peer(fields: &mut Vec<(&'static str, String)>, peer: &Peer) {
+    if let Some(client) = peer.addr {
+        fields.push(("client", quote(&client.to_string())));
+    }
+    if let Some(identity) = &peer.identity {
+        fields.push(("identity", quote(identity)));
+    }
+    if let Some(tenant) = &peer.tenant {
+        fields.push(("tenant", quote(tenant)));
+    }
+}
+
+fn push_session(fields: &mut Vec<(&'static str, String)>, session: &Session) {
+    let stats = session.stats();
+    if let Some(client) = stats.client
+        && !fields.iter().any(|(name, _)| *name == "client")
+    {
+        fields.push(("client", quote(&client.to_string())));
+    }
+    if let Some(identity) = &stats.identity
+        && !fields.iter().any(|(name, _)| *name == "identity")
+    {
+        fields.push(("identity", quote(identity)));
+    }
+    if let Some(tenant) = &stats.tenant
+        && !fields.iter().any(|(name, _)| *name == "tenant")
+    {
+        fields.push(("tenant", quote(tenant)));
+    }
+    if let Some(target) = stats.target {
+        fields.push(("target", quote(&target.to_string())));
+    }
+    fields.push(("bytes_sent", stats.bytes_sent.to_string()));
+    fields.push(("bytes_received", stats.bytes_received.to_string()));
+    fields.push(("messages_sent", stats.messages_sent.to_string()));
+    fields.push(("messages_received", stats.messages_received.to_string()));
+    fields.push(("duration_ms", stats.age.as_millis().to_string()));
+}
+
+struct Writer {
+    file: File,
+    size: u64,
+}
+
+// 追加写入的JSON lines审计日志
+//
+// 每条记录以一次write写入并立即落盘；打开文件时若末尾是崩溃留下的半行，
+// 先补一个换行，保证之后的记录从新行开始。
+#[derive(Clone)]
+pub struct AuditLog {
+    options: AuditOptions,
+    path: PathBuf,
+    writer: Arc<Mutex<Writer>>,
+}
+
+impl AuditLog {
+    /// 按配置打开审计日志，未配置路径时返回None
+    pub fn open(options: &AuditOptions) -> Result<Option<Self>, NeoError> {
+        let Some(path) = options.path.clone() else {
+            return Ok(None);
+        };
+        let writer = open_file(&path).map_err(|e| {
+            NeoError::Other(format!(
+                "Failed to open audit log {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        Ok(Some(AuditLog {
+            options: options.clone(),
+            path,
+            writer: Arc::new(Mutex::new(writer)),
+        }))
+    }
+
+    /// 使用的配置
+    pub fn options(&self) -> &AuditOptions {
+        &self.options
+    }
+
+    /// 记录一个事件，写入失败只输出错误日志
+    pub fn record(&self, event: &Event<'_>) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut line = event.to_json(&timestamp(now));
+        line.push('\n');
+        if let Err(e) = self.append(line.as_bytes()) {
+            log::error!("Failed to write audit log: {}", e);
+        }
+    }
+
+    fn append(&self, line: &[u8]) -> io::Result<()> {
+        let mut writer = self.writer.lock().expect("audit log poisoned");
+        if self.options.max_size > 0
+            && writer.size > 0
+            && writer.size + line.len() as u64 > self.options.max_size
+        {
+            self.rotate()?;
+            *writer = open_file(&self.path)?;
+        }
+        writer.file.write_all(line)?;
+        writer.file.sync_data()?;
+        writer.size += line.len() as u64;
+        Ok(())
+    }
+
+    // path -> path.1 -> path.2 ...，超出保留个数的最旧文件被删除
+    fn rotate(&self) -> io::Result<()> {
+        if self.options.keep == 0 {
+            return fs::remove_file(&self.path);
+        }
+        let _ = fs::remove_file(rotated_path(&self.path, self.options.keep));
+        for i in (1..self.options.keep).rev() {
+            match fs::rename(rotated_path(&self.path, i), rotated_path(&self.path, i + 1)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        fs::rename(&self.path, rotated_path(&self.path, 1))
+    }
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+// 以追加方式打开，并修复崩溃时写了一半的最后一行
+fn open_file(path: &Path) -> io::Result<Writer> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)?;
+    let mut size = file.metadata()?.len();
+    if size > 0 {
+        let mut last = [0u8; 1];
+        file.seek(SeekFrom::Start(size - 1))?;
+        file.read_exact(&mut last)?;
+        if last[0] != b'\n' {
+            file.write_all(b"\n")?;
+            file.sync_data()?;
+            size += 1;
+        }
+    }
+    Ok(Writer { file, size })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("neorust-audit-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("Failed to create temp dir");
+        dir.join("audit.jsonl")
+    }
+
+    static PEER: std::sync::LazyLock<Peer> = std::sync::LazyLock::new(|| Peer {
+        addr: Some("192.0.2.1:4000".parse().unwrap()),
+        identity: Some("alice".to_string()),
+        tenant: Some("red".to_string()),
+    });
+
+    fn connect_event(mark: &str) -> Event<'_> {
+        Event::Connect {
+            peer: &PEER,
+            mark,
+            target: "10.0.0.1:22",
+            result: Err("Connection \"refused\"".to_string()),
+        }
+    }
+
+    // 测试事件的JSON格式
+    #[test]
+    fn test_event_json() {
+        assert_eq!(
+            connect_event("m1").to_json("1970-01-01T00:00:00.000Z"),
+            r#"{"ts":"1970-01-01T00:00:00.000Z","event":"connect","client":"192.0.2.1:4000","identity":"alice","tenant":"red","mark":"m1","target":"10.0.0.1:22","result":"fail","error":"Connection \"refused\""}"#
+        );
+        assert_eq!(
+            Event::Shutdown {
+                reason: "kill-date",
+                totals: &Totals {
+                    requests: 9,
+                    bytes_read: 12,
+                    ..Totals::default()
+                }
+            }
+            .to_json("1970-01-01T00:00:00.000Z"),
+            r#"{"ts":"1970-01-01T00:00:00.000Z","event":"shutdown","reason":"kill-date","requests":9,"connects_ok":0,"connects_failed":0,"bytes_forwarded":0,"bytes_read":12,"decode_failures":0}"#
+        );
+    }
+
+    // 测试按大小轮转，超出保留个数的文件被删除
+    #[test]
+    fn test_rotation() {
+        let path = temp_path("rotation");
+        let options = AuditOptions {
+            path: Some(path.clone()),
+            max_size: 400,
+            keep: 2,
+        };
+        let audit = AuditLog::open(&options).unwrap().expect("Audit disabled");
+        for i in 0..10 {
+            audit.record(&connect_event(&format!("m{}", i)));
+        }
+
+        assert!(fs::metadata(&path).unwrap().len() <= 400);
+        assert!(rotated_path(&path, 1).exists());
+        assert!(rotated_path(&path, 2).exists());
+        assert!(!rotated_path(&path, 3).exists());
+        let latest = fs::read_to_string(&path).unwrap();
+        assert!(latest.ends_with("\n") && latest.contains("\"mark\":\"m9\""));
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    // 测试崩溃留下的半行被隔开，不影响之后的记录
+    #[test]
+    fn test_torn_line_repair() {
+        let path = temp_path("torn");
+        fs::write(&path, "{\"event\":\"connect\"}\n{\"event\":\"con").unwrap();
+        let options = AuditOptions {
+            path: Some(path.clone()),
+            ..AuditOptions::default()
+        };
+        AuditLog::open(&options)
+            .unwrap()
+            .expect("Audit disabled")
+            .record(&connect_event("m1"));
+
+        let text = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[2].starts_with('{') && lines[2].ends_with('}'));
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+}
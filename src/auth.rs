@@ -0,0 +1,337 @@
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::codec::{BlvMap, MessageField};
+use crate::config::AuthOptions;
+use crate::rotation::Rotation;
+
+const AUTH_INFO: &[u8] = b"neorust-auth-v1";
+const ADMIN_INFO: &[u8] = b"neorust-admin-v1";
+const TIMESTAMP_LEN: usize = 8;
+pub const NONCE_LEN: usize = 16;
+const TAG_LEN: usize = 32;
+/// 认证字段长度：时间戳(8) || nonce(16) || HMAC-SHA256(32)
+pub const AUTH_LEN: usize = TIMESTAMP_LEN + NONCE_LEN + TAG_LEN;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// 认证失败的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    /// 缺少认证字段或长度不对
+    Missing,
+    /// HMAC校验失败
+    BadMac,
+    /// 时间戳超出允许的偏差
+    Stale,
+    /// nonce已使用过
+    Replay,
+}
+
+impl Rejection {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Rejection::Missing => "missing auth",
+            Rejection::BadMac => "bad mac",
+            Rejection::Stale => "stale timestamp",
+            Rejection::Replay => "replayed nonce",
+        }
+    }
+}
+
+// 有界的重放缓存
+//
+// 记录时间窗口内见过的nonce；容量用尽时淘汰最早的记录，并把时间戳下限提高到
+// 被淘汰记录的时间戳，不晚于该时间戳的请求一律拒绝，因此淘汰不会留下重放机会。
+struct ReplayCache {
+    capacity: usize,
+    seen: HashSet<[u8; NONCE_LEN]>,
+    order: VecDeque<(u64, [u8; NONCE_LEN])>,
+    floor: u64,
+}
+
+impl ReplayCache {
+    fn new(capacity: usize) -> Self {
+        ReplayCache {
+            capacity,
+            seen: HashSet::new(),
+            order: VecDeque::new(),
+            floor: 0,
+        }
+    }
+
+    /// 记录nonce，已见过或时间戳不晚于下限时返回false
+    fn insert(&mut self, timestamp: u64, nonce: [u8; NONCE_LEN], oldest: u64) -> bool {
+        // 已过期的记录不会再被接受，可以直接丢弃
+        while let Some(&(ts, expired)) = self.order.front() {
+            if ts >= oldest {
+                break;
+            }
+            self.order.pop_front();
+            self.seen.remove(&expired);
+        }
+        if timestamp <= self.floor || self.seen.contains(&nonce) {
+            return false;
+        }
+        if self.order.len() >= self.capacity
+            && let Some((ts, evicted)) = self.order.pop_front()
+        {
+            self.seen.remove(&evicted);
+            self.floor = self.floor.max(ts);
+            if timestamp <= self.floor {
+                return false;
+            }
+        }
+        self.seen.insert(nonce);
+        self.order.push_back((timestamp, nonce));
+        true
+    }
+}
+
+// 请求认证：校验认证字段中的HMAC、时间戳和nonce
+//
+// 管理命令使用同样的格式，但放在单独的字段中，HMAC使用不同的标签，
+// 两种签名不能互相替代。
+#[derive(Clone)]
+pub struct Authenticator {
+    options: AuthOptions,
+    field: MessageField,
+    label: &'static [u8],
+    /// 当前密钥和轮换前的旧密钥，共用同一个重放缓存
+    macs: Rotation<HmacSha256>,
+    cache: Arc<Mutex<ReplayCache>>,
+}
+
+impl Authenticator {
+    /// 按配置创建，未配置密钥时返回None
+    pub fn new(options: &AuthOptions) -> Option<Self> {
+        Self::build(options, MessageField::Auth, AUTH_INFO)
+    }
+
+    /// 管理命令认证，校验`Admin`字段，未配置密钥时返回None
+    pub fn admin(options: &AuthOptions) -> Option<Self> {
+        Self::build(options, MessageField::Admin, ADMIN_INFO)
+    }
+
+    fn build(options: &AuthOptions, field: MessageField, label: &'static [u8]) -> Option<Self> {
+        let mac = |key: &str| {
+            HmacSha256::new_from_slice(key.as_bytes()).expect("HMAC accepts any key length")
+        };
+        let macs = options.previous_keys.iter().fold(
+            Rotation::new("auth key", mac(options.key.as_ref()?)),
+            |macs, p| macs.with_previous(mac(&p.key), p.not_after),
+        );
+        Some(Authenticator {
+            options: options.clone(),
+            field,
+            label,
+            macs,
+            cache: Arc::new(Mutex::new(ReplayCache::new(options.replay_cache.max(1)))),
+        })
+    }
+
+    /// 使用的配置
+    pub fn options(&self) -> &AuthOptions {
+        &self.options
+    }
+
+    /// 校验请求，成功后移除认证字段
+    pub fn verify(&self, info: &mut BlvMap) -> Result<(), Rejection> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.verify_at(info, now)
+    }
+
+    fn verify_at(&self, info: &mut BlvMap, now: u64) -> Result<(), Rejection> {
+        let field = info
+            .remove(&self.field.into())
+            .filter(|f| f.len() == AUTH_LEN)
+            .ok_or(Rejection::Missing)?;
+        let (timestamp, rest) = field.split_at(TIMESTAMP_LEN);
+        let (nonce, tag) = rest.split_at(NONCE_LEN);
+
+        // 先校验HMAC，未通过认证的请求不会占用重放缓存
+        self.macs
+            .try_each(|mac| self.tag(mac, timestamp, nonce, info).verify_slice(tag))
+            .map_err(|_| Rejection::BadMac)?;
+
+        let timestamp = u64::from_be_bytes(timestamp.try_into().expect("timestamp length"));
+        let skew = self.options.max_skew_secs;
+        if timestamp.abs_diff(now) > skew {
+            return Err(Rejection::Stale);
+        }
+        let nonce: [u8; NONCE_LEN] = nonce.try_into().expect("nonce length");
+        let mut cache = self.cache.lock().expect("replay cache poisoned");
+        if !cache.insert(timestamp, nonce, now.saturating_sub(skew)) {
+            return Err(Rejection::Replay);
+        }
+        Ok(())
+    }
+
+    // HMAC覆盖时间戳、nonce以及除认证字段外的所有BLV字段（按字段id排序）
+    fn tag(&self, mac: &HmacSha256, timestamp: &[u8], nonce: &[u8], info: &BlvMap) -> HmacSha256 {
+        let mut mac = mac.clone();
+        mac.update(self.label);
+        mac.update(timestamp);
+        mac.update(nonce);
+        let mut ids: Vec<&i32> = info.keys().collect();
+        ids.sort();
+        for id in ids {
+            let value = &info[id];
+            mac.update(&id.to_be_bytes());
+            mac.update(&(value.len() as u32).to_be_bytes());
+            mac.update(value);
+        }
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_792_300_000;
+
+    fn authenticator(replay_cache: usize) -> Authenticator {
+        Authenticator::new(&AuthOptions {
+            key: Some("secret".to_string()),
+            replay_cache,
+            ..AuthOptions::default()
+        })
+        .expect("Auth disabled")
+    }
+
+    // 按客户端的方式生成认证字段
+    fn sign(auth: &Authenticator, info: &BlvMap, timestamp: u64, nonce: u8) -> BlvMap {
+        let timestamp = timestamp.to_be_bytes();
+        let nonce = [nonce; NONCE_LEN];
+        let tag = auth
+            .tag(auth.macs.current(), &timestamp, &nonce, info)
+            .finalize()
+            .into_bytes();
+        let mut signed = info.clone();
+        signed.insert(auth.field.into(), [&timestamp[..], &nonce, &tag].concat());
+        signed
+    }
+
+    fn request() -> BlvMap {
+        let mut info = BlvMap::new();
+        info.insert(MessageField::Cmd.into(), b"READ".to_vec());
+        info.insert(MessageField::Mark.into(), b"m1".to_vec());
+        info
+    }
+
+    // 测试合法请求通过，篡改、缺失、过期和重放被拒绝
+    #[test]
+    fn test_verify() {
+        let auth = authenticator(16);
+        let mut signed = sign(&auth, &request(), NOW, 1);
+        assert_eq!(auth.verify_at(&mut signed.clone(), NOW + 5), Ok(()));
+        assert_eq!(
+            auth.verify_at(&mut signed.clone(), NOW + 5),
+            Err(Rejection::Replay)
+        );
+
+        signed.insert(MessageField::Mark.into(), b"m2".to_vec());
+        assert_eq!(auth.verify_at(&mut signed, NOW), Err(Rejection::BadMac));
+        assert_eq!(auth.verify_at(&mut request(), NOW), Err(Rejection::Missing));
+
+        let mut stale = sign(&auth, &request(), NOW - 301, 2);
+        assert_eq!(auth.verify_at(&mut stale, NOW), Err(Rejection::Stale));
+
+        // 其他密钥签名的请求
+        let other = Authenticator::new(&AuthOptions {
+            key: Some("other".to_string()),
+            ..AuthOptions::default()
+        })
+        .unwrap();
+        let mut forged = sign(&other, &request(), NOW, 3);
+        assert_eq!(auth.verify_at(&mut forged, NOW), Err(Rejection::BadMac));
+    }
+
+    // 测试轮换后旧密钥在到期前仍可通过认证，且与当前密钥共用重放缓存
+    #[test]
+    fn test_previous_keys() {
+        use crate::config::PreviousKey;
+        use std::time::{Duration, SystemTime};
+
+        let previous = |key: &str, not_after| PreviousKey {
+            key: key.to_string(),
+            not_after,
+        };
+        let now = SystemTime::now();
+        let auth = Authenticator::new(&AuthOptions {
+            key: Some("new".to_string()),
+            previous_keys: vec![
+                previous("secret", now + Duration::from_secs(3600)),
+                previous("expired", now - Duration::from_secs(1)),
+            ],
+            ..AuthOptions::default()
+        })
+        .unwrap();
+        let old = authenticator(16);
+        let mut signed = sign(&old, &request(), NOW, 1);
+        assert_eq!(auth.verify_at(&mut signed.clone(), NOW), Ok(()));
+        assert_eq!(auth.verify_at(&mut signed, NOW), Err(Rejection::Replay));
+
+        let expired = Authenticator::new(&AuthOptions {
+            key: Some("expired".to_string()),
+            ..AuthOptions::default()
+        })
+        .unwrap();
+        let mut signed = sign(&expired, &request(), NOW, 2);
+        assert_eq!(auth.verify_at(&mut signed, NOW), Err(Rejection::BadMac));
+    }
+
+    // 测试缓存容量用尽后，被淘汰nonce对应时间戳的请求不能重放
+    #[test]
+    fn test_bounded_cache() {
+        let auth = authenticator(2);
+        let first = sign(&auth, &request(), NOW, 1);
+        for (ts, nonce) in [(NOW, 1), (NOW + 1, 2), (NOW + 2, 3)] {
+            let mut signed = sign(&auth, &request(), ts, nonce);
+            assert_eq!(auth.verify_at(&mut signed, NOW + 2), Ok(()));
+        }
+        assert_eq!(auth.cache.lock().unwrap().order.len(), 2);
+        assert_eq!(
+            auth.verify_at(&mut first.clone(), NOW + 2),
+            Err(Rejection::Replay)
+        );
+
+        // 超出时间窗口的记录被清理
+        let mut later = sign(&auth, &request(), NOW + 1000, 4);
+        assert_eq!(auth.verify_at(&mut later, NOW + 1000), Ok(()));
+        assert_eq!(auth.cache.lock().unwrap().order.len(), 1);
+    }
+
+    // 测试管理命令签名与请求认证签名不能互相替代
+    #[test]
+    fn test_admin() {
+        let options = AuthOptions {
+            key: Some("secret".to_string()),
+            ..AuthOptions::default()
+        };
+        let auth = authenticator(16);
+        let admin = Authenticator::admin(&options).expect("Admin disabled");
+
+        let mut signed = sign(&admin, &request(), NOW, 1);
+        assert_eq!(
+            auth.verify_at(&mut signed.clone(), NOW),
+            Err(Rejection::Missing)
+        );
+        assert_eq!(admin.verify_at(&mut signed, NOW), Ok(()));
+        assert!(!signed.contains_key(&MessageField::Admin.into()));
+
+        // 同一密钥的请求认证字段放入Admin字段也无效
+        let mut forged = sign(&auth, &request(), NOW, 2);
+        let tag = forged.remove(&MessageField::Auth.into()).unwrap();
+        forged.insert(MessageField::Admin.into(), tag);
+        assert_eq!(admin.verify_at(&mut forged, NOW), Err(Rejection::BadMac));
+    }
+}
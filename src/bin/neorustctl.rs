@@ -0,0 +1,79 @@
+// neorust服务端的本地管理工具，通过控制套接字发送命令
+
+const USAGE: &str = "\
+Usage: neorustctl [-s <PATH>] <COMMAND> [ARGS]...
+
+Options:
+  -s, --socket <PATH>   Control socket [env: NEORUST_CONTROL_SOCKET]
+  -h, --help            Print help
+
+Commands:
+  sessions              List sessions
+  kill <MARK>           Close one session
+  kill-all              Close all sessions
+  limits                Show limits and session usage
+  config                Show the effective configuration
+  log-level [LEVEL]     Show or set the log level
+  reload                Reload the configuration
+  upgrade [PATH]        Start a new binary and hand over listeners and sessions";
+
+#[cfg(unix)]
+fn main() {
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+
+    let mut socket = std::env::var("NEORUST_CONTROL_SOCKET").ok();
+    let mut command = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            "-s" | "--socket" => socket = args.next(),
+            _ if command.is_empty() && arg.starts_with("--socket=") => {
+                socket = Some(arg["--socket=".len()..].to_string());
+            }
+            _ => command.push(arg),
+        }
+    }
+
+    let (Some(socket), false) = (socket, command.is_empty()) else {
+        eprintln!("{}", USAGE);
+        std::process::exit(2);
+    };
+
+    let response = UnixStream::connect(&socket).and_then(|mut stream| {
+        writeln!(stream, "{}", command.join(" "))?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        Ok(response)
+    });
+    let response = match response {
+        Ok(response) => response,
+        Err(e) => {
+            eprintln!("Failed to talk to {}: {}", socket, e);
+            std::process::exit(1);
+        }
+    };
+
+    let (status, body) = response.split_once('\n').unwrap_or((&response, ""));
+    match status.strip_prefix("ERR ") {
+        None if status == "OK" => print!("{}", body),
+        Some(reason) => {
+            eprintln!("{}", reason);
+            std::process::exit(1);
+        }
+        None => {
+            eprintln!("Unexpected response: {}", status);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn main() {
+    eprintln!("neorustctl is only supported on Unix\n\n{}", USAGE);
+    std::process::exit(1);
+}
@@ -0,0 +1,221 @@
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+
+use crate::inetd::{self, CLIENT_HEADER, Error, HOP_BY_HOP, MAX_BODY};
+
+/// 不返回给Web服务器的响应头，由Web服务器自行设置
+const SKIP_RESPONSE: &[&str] = &["Connection", "Keep-Alive", "Transfer-Encoding"];
+
+/// 处理一次CGI调用：按环境变量和标准输入构造请求转交给伴随进程，把响应写到标准输出
+pub fn run(companion: &Path) -> io::Result<()> {
+    let env: Vec<(String, String)> = std::env::vars_os()
+        .filter_map(|(k, v)| Some((k.into_string().ok()?, v.into_string().ok()?)))
+        .collect();
+    relay(
+        &env,
+        &mut io::stdin().lock(),
+        &mut io::stdout().lock(),
+        companion,
+    )
+}
+
+fn relay(
+    env: &[(String, String)],
+    input: &mut impl Read,
+    output: &mut impl Write,
+    companion: &Path,
+) -> io::Result<()> {
+    let request = match request(env, input) {
+        Ok(request) => request,
+        Err(Error::Status(status)) => return respond(output, status),
+        Err(Error::Io(e)) => {
+            respond(output, "400 Bad Request")?;
+            return Err(e);
+        }
+    };
+    let response = inetd::forward(companion, &request).and_then(|r| {
+        response(&r).ok_or_else(|| io::Error::other("malformed response from companion"))
+    });
+    match response {
+        Ok(response) => {
+            output.write_all(&response)?;
+            output.flush()
+        }
+        Err(e) => {
+            respond(output, "502 Bad Gateway")?;
+            Err(e)
+        }
+    }
+}
+
+// 按CGI环境变量还原HTTP请求：HTTP_*变量还原为请求头，REMOTE_ADDR、REMOTE_PORT作为客户端地址
+//
+// 使用HTTP/1.0转交，伴随进程的响应不会使用分块编码。
+fn request(env: &[(String, String)], input: &mut impl Read) -> Result<Vec<u8>, Error> {
+    let var = |name: &str| env.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str());
+    let method = var("REQUEST_METHOD").ok_or(Error::Status("400 Bad Request"))?;
+    let mut uri = var("SCRIPT_NAME").unwrap_or_default().replace(' ', "%20");
+    if !uri.starts_with('/') {
+        uri.insert(0, '/');
+    }
+    if let Some(query) = var("QUERY_STRING").filter(|q| !q.is_empty()) {
+        uri.push('?');
+        uri.push_str(query);
+    }
+    let length = match var("CONTENT_LENGTH").map(str::trim) {
+        None | Some("") => 0,
+        Some(value) => value
+            .parse()
+            .map_err(|_| Error::Status("400 Bad Request"))?,
+    };
+    if length > MAX_BODY {
+        return Err(Error::Status("413 Content Too Large"));
+    }
+
+    let mut request = format!("{} {} HTTP/1.0\r\n", method, uri);
+    for (key, value) in env {
+        let Some(name) = key.strip_prefix("HTTP_") else {
+            continue;
+        };
+        let name = header_name(name);
+        if name.eq_ignore_ascii_case("Content-Length")
+            || HOP_BY_HOP.iter().any(|h| name.eq_ignore_ascii_case(h))
+        {
+            continue;
+        }
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if let Some(content_type) = var("CONTENT_TYPE") {
+        request.push_str(&format!("Content-Type: {}\r\n", content_type));
+    }
+    request.push_str(&format!("Content-Length: {}\r\n", length));
+    if let Some(ip) = var("REMOTE_ADDR").and_then(|a| a.parse::<IpAddr>().ok()) {
+        let port = var("REMOTE_PORT")
+            .and_then(|p| p.parse().ok())
+            .unwrap_or_default();
+        request.push_str(&format!(
+            "{}: {}\r\n",
+            CLIENT_HEADER,
+            SocketAddr::new(ip, port)
+        ));
+    }
+    request.push_str("Connection: close\r\n\r\n");
+
+    let mut request = request.into_bytes();
+    let start = request.len();
+    request.resize(start + length, 0);
+    input.read_exact(&mut request[start..])?;
+    Ok(request)
+}
+
+// HTTP_X_FORWARDED_FOR -> X-Forwarded-For
+fn header_name(name: &str) -> String {
+    name.split('_')
+        .map(|word| {
+            let word = word.to_ascii_lowercase();
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(c) => c.to_ascii_uppercase().to_string() + chars.as_str(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+// 把伴随进程的HTTP响应改写为CGI响应：状态行改为Status头
+fn response(raw: &[u8]) -> Option<Vec<u8>> {
+    let end = raw.windows(4).position(|w| w == b"\r\n\r\n")?;
+    let head = std::str::from_utf8(&raw[..end]).ok()?;
+    let mut lines = head.split("\r\n");
+    let (_, status) = lines.next()?.split_once(' ')?;
+    let mut response = format!("Status: {}\r\n", status);
+    for line in lines {
+        let name = line.split_once(':')?.0.trim();
+        if SKIP_RESPONSE.iter().any(|h| name.eq_ignore_ascii_case(h)) {
+            continue;
+        }
+        response.push_str(line);
+        response.push_str("\r\n");
+    }
+    response.push_str("\r\n");
+    let mut response = response.into_bytes();
+    response.extend(&raw[end + 4..]);
+    Some(response)
+}
+
+/// 向Web服务器返回只有状态的响应
+pub fn respond(output: &mut impl Write, status: &str) -> io::Result<()> {
+    write!(output, "Status: {}\r\nContent-Length: 0\r\n\r\n", status)?;
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    // 测试按CGI环境变量还原请求
+    #[test]
+    fn test_request() {
+        let vars = env(&[
+            ("GATEWAY_INTERFACE", "CGI/1.1"),
+            ("REQUEST_METHOD", "POST"),
+            ("SCRIPT_NAME", "/cgi-bin/tunnel.cgi"),
+            ("QUERY_STRING", "a=1"),
+            ("CONTENT_TYPE", "application/octet-stream"),
+            ("CONTENT_LENGTH", "5"),
+            ("REMOTE_ADDR", "203.0.113.9"),
+            ("REMOTE_PORT", "5000"),
+            ("HTTP_HOST", "router"),
+            ("HTTP_X_FORWARDED_FOR", "10.0.0.1"),
+            ("HTTP_X_NEORUST_CLIENT", "10.0.0.2:1"),
+            ("HTTP_CONNECTION", "keep-alive"),
+        ]);
+        let rewritten = request(&vars, &mut "hello, extra".as_bytes())
+            .ok()
+            .expect("Request expected");
+        assert_eq!(
+            String::from_utf8(rewritten).unwrap(),
+            "POST /cgi-bin/tunnel.cgi?a=1 HTTP/1.0\r\nHost: router\r\nX-Forwarded-For: 10.0.0.1\r\nContent-Type: application/octet-stream\r\nContent-Length: 5\r\nX-Neorust-Client: 203.0.113.9:5000\r\nConnection: close\r\n\r\nhello"
+        );
+
+        let get = env(&[("REQUEST_METHOD", "GET"), ("CONTENT_LENGTH", "")]);
+        let rewritten = request(&get, &mut "".as_bytes()).ok().unwrap();
+        assert!(rewritten.starts_with(b"GET / HTTP/1.0\r\nContent-Length: 0\r\n"));
+
+        for (length, status) in [
+            ("x", "400 Bad Request"),
+            ("16777217", "413 Content Too Large"),
+        ] {
+            let vars = env(&[("REQUEST_METHOD", "POST"), ("CONTENT_LENGTH", length)]);
+            assert!(matches!(
+                request(&vars, &mut "".as_bytes()),
+                Err(Error::Status(s)) if s == status
+            ));
+        }
+        let vars = env(&[("REQUEST_METHOD", "POST"), ("CONTENT_LENGTH", "10")]);
+        assert!(matches!(
+            request(&vars, &mut "short".as_bytes()),
+            Err(Error::Io(_))
+        ));
+    }
+
+    // 测试响应改写为CGI格式
+    #[test]
+    fn test_response() {
+        let raw = b"HTTP/1.0 200 OK\r\nServer: tiny-http\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok";
+        assert_eq!(
+            response(raw).unwrap(),
+            b"Status: 200 OK\r\nServer: tiny-http\r\nContent-Length: 2\r\n\r\nok"
+        );
+        assert!(response(b"HTTP/1.0 200 OK\r\nContent-Length: 2").is_none());
+        assert_eq!(header_name("X_FORWARDED_FOR"), "X-Forwarded-For");
+    }
+}
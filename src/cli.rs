@@ -0,0 +1,340 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::errors::NeoError;
+use crate::log::{Format, Level, parse_timestamp};
+use crate::window::DailyHours;
+
+pub const USAGE: &str = "\
+Usage: neorust [OPTIONS] [LISTEN]...
+
+Arguments:
+  [LISTEN]...                    Listen address: PORT, HOST:PORT or [IPv6]:PORT
+
+Options:
+  -c, --config <PATH>            TOML configuration file
+      --check-config             Validate the configuration, print the effective settings and exit
+  -l, --listen <ADDR>            Listen address, may be repeated
+      --metrics-listen <ADDR>    Serve Prometheus metrics at /metrics on this address
+      --control-socket <PATH>    Unix socket for neorustctl
+      --companion-socket <PATH>  Unix socket accepting requests relayed by --inetd and --cgi processes
+      --companion-socket-mode <MODE>
+                                 Octal permissions of the companion socket [default: 0600]
+      --inetd                    Serve one HTTP connection on stdin/stdout, relaying to the companion socket
+      --cgi                      Handle one CGI request, relaying to the companion socket
+      --tls-listen <ADDR>        HTTPS listen address, may be repeated
+      --tls-cert <PATH>          PEM certificate chain for HTTPS
+      --tls-key <PATH>           PEM private key for HTTPS
+      --tls-self-signed          Generate a self-signed certificate if none is configured
+      --tls-client-ca <PATH>     Require client certificates issued by this CA
+  -k, --key <KEY>                Password for the AEAD layer
+      --auth-key <KEY>           Require HMAC-authenticated requests signed with this key
+      --admin-key <KEY>          Accept the SHUTDOWN command signed with this key
+      --not-before <TIME>        Refuse requests before this UTC time, e.g. 2026-11-01T09:00:00Z
+      --not-after <TIME>         Refuse requests and exit after this UTC time
+      --daily-hours <HH:MM-HH:MM>
+                                 Only accept requests during these UTC hours each day
+      --connect-timeout <MS>     Timeout for connecting to targets [default: 3000]
+      --max-sessions <N>         Maximum concurrent sessions, 0 for unlimited [default: 0]
+      --log-level <LEVEL>        off, error, warn, info or debug [default: warn]
+      --log-format <FORMAT>      text or json [default: text]
+      --audit-log <PATH>         Append tunnel activity to a JSON-lines audit log
+      --blv-offset <N>           BLV length offset used by the client
+      --field-ids <SPEC>         BLV field ids, e.g. data=1,cmd=2
+  -h, --help                     Print help
+  -V, --version                  Print version";
+
+// 命令行参数，未指定的选项为None，不覆盖其他来源的配置
+#[derive(Debug, Default, PartialEq)]
+pub struct Cli {
+    pub config: Option<PathBuf>,
+    pub check_config: bool,
+    pub listen: Vec<SocketAddr>,
+    pub metrics_listen: Option<SocketAddr>,
+    pub control_socket: Option<PathBuf>,
+    pub companion_socket: Option<PathBuf>,
+    pub companion_socket_mode: Option<u32>,
+    /// 以inetd模式运行
+    pub inetd: bool,
+    /// 以CGI模式运行
+    pub cgi: bool,
+    pub key: Option<String>,
+    pub auth_key: Option<String>,
+    pub admin_key: Option<String>,
+    pub not_before: Option<SystemTime>,
+    pub not_after: Option<SystemTime>,
+    pub daily_hours: Option<DailyHours>,
+    pub connect_timeout_ms: Option<u64>,
+    pub max_sessions: Option<usize>,
+    pub log_level: Option<Level>,
+    pub log_format: Option<Format>,
+    pub audit_log: Option<PathBuf>,
+    pub tls_listen: Vec<SocketAddr>,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub tls_self_signed: bool,
+    pub tls_client_ca: Option<PathBuf>,
+    pub blv_offset: Option<i32>,
+    pub field_ids: Option<String>,
+}
+
+// 解析结果
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    Run(Box<Cli>),
+    Help,
+    Version,
+}
+
+/// 解析命令行参数（不含程序名）
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Action, NeoError> {
+    let mut cli = Cli::default();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        // 支持 --name=value 形式
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, value)) if arg.starts_with("--") => (name.to_string(), Some(value)),
+            _ => (arg.clone(), None),
+        };
+        let mut value = || -> Result<String, NeoError> {
+            match inline {
+                Some(v) => Ok(v.to_string()),
+                None => args
+                    .next()
+                    .ok_or_else(|| NeoError::Other(format!("Missing value for {}", name))),
+            }
+        };
+
+        match name.as_str() {
+            "-h" | "--help" => return Ok(Action::Help),
+            "-V" | "--version" => return Ok(Action::Version),
+            "-c" | "--config" => cli.config = Some(PathBuf::from(value()?)),
+            "--check-config" => cli.check_config = true,
+            "-l" | "--listen" => cli.listen.push(parse_listen(&value()?)?),
+            "--metrics-listen" => cli.metrics_listen = Some(parse_listen(&value()?)?),
+            "--control-socket" => cli.control_socket = Some(PathBuf::from(value()?)),
+            "--companion-socket" => cli.companion_socket = Some(PathBuf::from(value()?)),
+            "--companion-socket-mode" => {
+                cli.companion_socket_mode = Some(parse_mode(&name, &value()?)?)
+            }
+            "--inetd" => cli.inetd = true,
+            "--cgi" => cli.cgi = true,
+            "-k" | "--key" => cli.key = Some(value()?),
+            "--auth-key" => cli.auth_key = Some(value()?),
+            "--admin-key" => cli.admin_key = Some(value()?),
+            "--not-before" => cli.not_before = Some(UNIX_EPOCH + parse_timestamp(&value()?)?),
+            "--not-after" => cli.not_after = Some(UNIX_EPOCH + parse_timestamp(&value()?)?),
+            "--daily-hours" => cli.daily_hours = Some(value()?.parse()?),
+            "--connect-timeout" => cli.connect_timeout_ms = Some(parse_number(&name, &value()?)?),
+            "--max-sessions" => cli.max_sessions = Some(parse_number(&name, &value()?)?),
+            "--log-level" => cli.log_level = Some(value()?.parse()?),
+            "--log-format" => cli.log_format = Some(value()?.parse()?),
+            "--audit-log" => cli.audit_log = Some(PathBuf::from(value()?)),
+            "--tls-listen" => cli.tls_listen.push(parse_listen(&value()?)?),
+            "--tls-cert" => cli.tls_cert = Some(PathBuf::from(value()?)),
+            "--tls-key" => cli.tls_key = Some(PathBuf::from(value()?)),
+            "--tls-self-signed" => cli.tls_self_signed = true,
+            "--tls-client-ca" => cli.tls_client_ca = Some(PathBuf::from(value()?)),
+            "--blv-offset" => cli.blv_offset = Some(parse_number(&name, &value()?)?),
+            "--field-ids" => cli.field_ids = Some(value()?),
+            _ if arg.starts_with('-') && arg.len() > 1 => {
+                return Err(NeoError::Other(format!("Unknown option: {}", arg)));
+            }
+            _ => cli.listen.push(parse_listen(&arg)?),
+        }
+    }
+
+    Ok(Action::Run(Box::new(cli)))
+}
+
+/// 设置了CGI环境变量时，是否由Web服务器直接执行
+///
+/// 直接执行时的参数是查询字符串中的检索词（RFC 3875 4.4），不是选项。没有参数，或以其他名称
+/// （如`tunnel.cgi`）执行且参数中没有选项时视为CGI调用；以neorust为名执行并带有参数时需要`--cgi`。
+pub fn cgi_invocation(program: Option<&str>, args: &[String]) -> bool {
+    let renamed = program
+        .and_then(|program| Path::new(program).file_name())
+        .is_some_and(|name| name != env!("CARGO_BIN_NAME"));
+    args.is_empty() || renamed && !args.iter().any(|a| a.starts_with('-'))
+}
+
+/// 解析监听地址
+///
+/// 纯端口号监听所有IPv4地址；IPv6地址需用方括号包裹并带端口，如`[::1]:8080`。
+pub fn parse_listen(s: &str) -> Result<SocketAddr, NeoError> {
+    if let Ok(port) = s.parse::<u16>() {
+        return Ok(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port));
+    }
+    if let Ok(addr) = s.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    if s.parse::<IpAddr>().is_ok() {
+        return Err(NeoError::Other(format!(
+            "Missing port in listen address {}, use e.g. [::1]:8080 or 127.0.0.1:8080",
+            s
+        )));
+    }
+    // 主机名:端口
+    s.to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .ok_or_else(|| NeoError::Other(format!("Invalid listen address: {}", s)))
+}
+
+/// 解析八进制的文件权限，如`0660`
+pub fn parse_mode(name: &str, value: &str) -> Result<u32, NeoError> {
+    u32::from_str_radix(value.trim(), 8)
+        .ok()
+        .filter(|m| *m <= 0o777)
+        .ok_or_else(|| NeoError::Other(format!("Invalid value for {}: {}", name, value)))
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, NeoError> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| NeoError::Other(format!("Invalid value for {}: {}", name, value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn run(args: &[&str]) -> Result<Cli, NeoError> {
+        match parse(args.iter().map(|s| s.to_string()))? {
+            Action::Run(cli) => Ok(*cli),
+            other => panic!("Unexpected action: {:?}", other),
+        }
+    }
+
+    // 测试兼容原有的单个位置参数
+    #[test]
+    fn test_positional_port() {
+        let cli = run(&["8080"]).expect("Parse failed");
+        assert_eq!(cli.listen, vec!["0.0.0.0:8080".parse().unwrap()]);
+        assert_eq!(
+            cli,
+            Cli {
+                listen: cli.listen.clone(),
+                ..Cli::default()
+            }
+        );
+    }
+
+    // 测试IPv4/IPv6监听地址
+    #[test]
+    fn test_listen_addresses() {
+        let cli =
+            run(&["-l", "[::1]:8080", "--listen=127.0.0.1:9090", "[::]:80"]).expect("Parse failed");
+        assert_eq!(
+            cli.listen,
+            vec![
+                "[::1]:8080".parse().unwrap(),
+                "127.0.0.1:9090".parse().unwrap(),
+                "[::]:80".parse().unwrap(),
+            ]
+        );
+
+        assert!(parse_listen("::1").is_err());
+        assert!(parse_listen("[::1]").is_err());
+        assert!(parse_listen("99999").is_err());
+        assert_eq!(
+            parse_listen("192.0.2.1:8080").expect("Parse failed"),
+            "192.0.2.1:8080".parse().unwrap()
+        );
+    }
+
+    // 测试各选项的解析
+    #[test]
+    fn test_options() {
+        let cli = run(&[
+            "--config=/etc/neorust.toml",
+            "--check-config",
+            "--inetd",
+            "--cgi",
+            "--companion-socket=/run/neorust-companion.sock",
+            "--companion-socket-mode",
+            "0660",
+            "-k",
+            "password",
+            "--connect-timeout",
+            "500",
+            "--max-sessions=64",
+            "--log-level",
+            "DEBUG",
+            "--log-format=json",
+            "--blv-offset=-5",
+            "--field-ids",
+            "data=1,cmd=2",
+            "--tls-listen=[::]:8443",
+            "--tls-self-signed",
+            "--not-after=2026-12-01T18:00:00Z",
+            "--daily-hours",
+            "22:00-06:00",
+            "8080",
+        ])
+        .expect("Parse failed");
+
+        assert_eq!(cli.config, Some(PathBuf::from("/etc/neorust.toml")));
+        assert!(cli.check_config);
+        assert!(cli.inetd);
+        assert!(cli.cgi);
+        assert_eq!(
+            cli.companion_socket,
+            Some(PathBuf::from("/run/neorust-companion.sock"))
+        );
+        assert_eq!(cli.companion_socket_mode, Some(0o660));
+        assert_eq!(cli.key.as_deref(), Some("password"));
+        assert_eq!(cli.connect_timeout_ms, Some(500));
+        assert_eq!(cli.max_sessions, Some(64));
+        assert_eq!(cli.log_level, Some(Level::Debug));
+        assert_eq!(cli.log_format, Some(Format::Json));
+        assert_eq!(cli.blv_offset, Some(-5));
+        assert_eq!(cli.field_ids.as_deref(), Some("data=1,cmd=2"));
+        assert_eq!(cli.tls_listen, vec!["[::]:8443".parse().unwrap()]);
+        assert!(cli.tls_self_signed);
+        assert_eq!(
+            cli.not_after,
+            Some(UNIX_EPOCH + Duration::from_secs(1_796_148_000))
+        );
+        assert_eq!(
+            cli.daily_hours.map(|h| h.to_string()).as_deref(),
+            Some("22:00-06:00")
+        );
+    }
+
+    // 测试只在没有参数或以其他名称执行时识别为CGI调用
+    #[test]
+    fn test_cgi_invocation() {
+        let args = |args: &[&str]| args.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert!(cgi_invocation(Some("/usr/bin/neorust"), &[]));
+        assert!(!cgi_invocation(Some("/usr/bin/neorust"), &args(&["8080"])));
+        assert!(cgi_invocation(
+            Some("/www/cgi-bin/tunnel.cgi"),
+            &args(&["search", "words"])
+        ));
+        assert!(!cgi_invocation(
+            Some("/www/cgi-bin/tunnel.cgi"),
+            &args(&["-c", "/etc/neorust.toml"])
+        ));
+    }
+
+    // 测试帮助、版本及错误输入
+    #[test]
+    fn test_help_version_errors() {
+        let parse = |args: &[&str]| parse(args.iter().map(|s| s.to_string()));
+        assert_eq!(parse(&["8080", "--help"]).unwrap(), Action::Help);
+        assert_eq!(parse(&["-V"]).unwrap(), Action::Version);
+
+        assert!(parse(&["--bogus"]).is_err());
+        assert!(parse(&["--key"]).is_err());
+        assert!(parse(&["--connect-timeout", "soon"]).is_err());
+        assert!(parse(&["--log-level", "loud"]).is_err());
+        assert!(parse(&["--companion-socket-mode", "0888"]).is_err());
+        assert!(parse(&["--companion-socket-mode", "01777"]).is_err());
+        assert!(parse(&["--not-before", "tomorrow"]).is_err());
+    }
+}
@@ -3,6 +3,8 @@ use std::collections::HashMap;
 
 use base64::engine::Engine as _;
 
+use crate::config::PreviousAlphabet;
+use crate::rotation::Rotation;
 use crate::{BLV_OFFSET, DE, EN, errors::NeoError};
 
 // 枚举定义
@@ -17,6 +19,55 @@ pub enum MessageField {
     Port = 7,
     Random1 = 0,  // 用于blv_encode中的额外字段
     Random2 = 39, // 用于blv_encode中的额外字段
+    // 以下为扩展字段，见 protocol::EXTENSIONS，官方neoreg客户端不会发送
+    Flags = 64,   // 消息标志位
+    Version = 65, // 协议版本
+    Caps = 66,    // 能力位
+    Stats = 67,   // 会话统计
+    Auth = 68,    // 请求认证
+    Admin = 69,   // 管理命令认证
+}
+
+impl MessageField {
+    /// 所有字段，用于按名称解析配置
+    pub const ALL: [MessageField; 15] = [
+        MessageField::Data,
+        MessageField::Cmd,
+        MessageField::Mark,
+        MessageField::Status,
+        MessageField::Error,
+        MessageField::Ip,
+        MessageField::Port,
+        MessageField::Random1,
+        MessageField::Random2,
+        MessageField::Flags,
+        MessageField::Version,
+        MessageField::Caps,
+        MessageField::Stats,
+        MessageField::Auth,
+        MessageField::Admin,
+    ];
+
+    /// 配置中使用的字段名
+    pub fn name(self) -> &'static str {
+        match self {
+            MessageField::Data => "data",
+            MessageField::Cmd => "cmd",
+            MessageField::Mark => "mark",
+            MessageField::Status => "status",
+            MessageField::Error => "error",
+            MessageField::Ip => "ip",
+            MessageField::Port => "port",
+            MessageField::Random1 => "random1",
+            MessageField::Random2 => "random2",
+            MessageField::Flags => "flags",
+            MessageField::Version => "version",
+            MessageField::Caps => "caps",
+            MessageField::Stats => "stats",
+            MessageField::Auth => "auth",
+            MessageField::Admin => "admin",
+        }
+    }
 }
 
 impl From<MessageField> for i32 {
@@ -39,6 +90,12 @@ impl TryFrom<i32> for MessageField {
             7 => Ok(MessageField::Port),
             0 => Ok(MessageField::Random1),
             39 => Ok(MessageField::Random2),
+            64 => Ok(MessageField::Flags),
+            65 => Ok(MessageField::Version),
+            66 => Ok(MessageField::Caps),
+            67 => Ok(MessageField::Stats),
+            68 => Ok(MessageField::Auth),
+            69 => Ok(MessageField::Admin),
             _ => Err(NeoError::Other(format!(
                 "Invalid message field value: {}",
                 value
@@ -48,8 +105,12 @@ impl TryFrom<i32> for MessageField {
 }
 
 /// 从数据中读取并解码长度字段
-/// 长度字段为4字节大端序整数，需要减去BLV_OFFSET
-pub fn read_and_decode_length(data: &[u8], cursor: &mut usize) -> Result<usize, NeoError> {
+/// 长度字段为4字节大端序整数，需要减去长度偏移量
+pub fn read_and_decode_length(
+    data: &[u8],
+    cursor: &mut usize,
+    offset: i32,
+) -> Result<usize, NeoError> {
     if *cursor + 4 > data.len() {
         return Err(NeoError::Other(
             "Insufficient data for length decoding".to_string(),
@@ -62,7 +123,7 @@ pub fn read_and_decode_length(data: &[u8], cursor: &mut usize) -> Result<usize,
         data[*cursor + 2],
         data[*cursor + 3],
     ];
-    let l = i32::from_be_bytes(l_bytes) - BLV_OFFSET;
+    let l = i32::from_be_bytes(l_bytes).wrapping_sub(offset);
     *cursor += 4;
 
     if l < 0 {
@@ -72,33 +133,163 @@ pub fn read_and_decode_length(data: &[u8], cursor: &mut usize) -> Result<usize,
     Ok(l as usize)
 }
 
+/// 读取大端序整数字段（最多4字节），缺失时为0
+pub fn read_be_u32(info: &BlvMap, field: MessageField) -> u32 {
+    info.get(&field.into())
+        .map(|v| v.iter().take(4).fold(0, |acc, &b| (acc << 8) | b as u32))
+        .unwrap_or(0)
+}
+
 // 类型别名
 pub type BlvMap = HashMap<i32, Vec<u8>>; // 保持i32类型以便与现有代码兼容
 
+/// BLV线上格式：长度偏移量和字段id映射
+///
+/// 不同版本、不同密钥生成的neoreg客户端取值不同，取值不一致时请求会被当作
+/// 无效数据。BlvMap内部始终使用MessageField的规范id，只在编解码时转换。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlvProfile {
+    pub offset: i32,
+    // 规范id -> 线上id，未列出的字段线上id与规范id相同
+    wire_ids: HashMap<i32, i32>,
+}
+
+impl Default for BlvProfile {
+    fn default() -> Self {
+        BlvProfile {
+            offset: BLV_OFFSET,
+            wire_ids: HashMap::new(),
+        }
+    }
+}
+
+impl BlvProfile {
+    /// 使用指定的长度偏移量
+    pub fn with_offset(mut self, offset: i32) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// 解析字段id映射，格式为`data=1,cmd=2,...`，未列出的字段保持默认
+    pub fn with_field_ids(mut self, spec: &str) -> Result<Self, NeoError> {
+        for item in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (name, id) = item
+                .split_once('=')
+                .ok_or_else(|| NeoError::Other(format!("Invalid field mapping: {}", item)))?;
+            let field = MessageField::ALL
+                .into_iter()
+                .find(|f| f.name() == name.trim())
+                .ok_or_else(|| NeoError::Other(format!("Unknown field name: {}", name)))?;
+            let id = id
+                .trim()
+                .parse::<u8>()
+                .map_err(|_| NeoError::Other(format!("Invalid field id: {}", item)))?;
+            self.wire_ids.insert(field.into(), id as i32);
+        }
+
+        // 线上id必须互不相同
+        let mut seen = HashMap::new();
+        for field in MessageField::ALL {
+            let id = self.wire_id(field.into());
+            if let Some(other) = seen.insert(id, field) {
+                return Err(NeoError::Other(format!(
+                    "Field id {} used by both {} and {}",
+                    id,
+                    other.name(),
+                    field.name()
+                )));
+            }
+        }
+        Ok(self)
+    }
+
+    /// 规范id -> 线上id
+    pub fn wire_id(&self, id: i32) -> i32 {
+        self.wire_ids.get(&id).copied().unwrap_or(id)
+    }
+
+    /// 线上id -> 规范id；被其他字段让出的规范id返回None
+    pub fn canonical_id(&self, id: i32) -> Option<i32> {
+        if let Some((&canonical, _)) = self.wire_ids.iter().find(|&(_, &w)| w == id) {
+            return Some(canonical);
+        }
+        if self.wire_ids.contains_key(&id) {
+            return None;
+        }
+        Some(id)
+    }
+}
+
 // 编解码模块
 #[derive(Clone)]
 pub struct Codec {
     en_map: HashMap<u8, u8>,
     de_map: HashMap<u8, u8>,
+    profile: BlvProfile,
 }
 
 impl Codec {
     /// 创建新的编解码器实例
     pub fn new() -> Self {
-        let (en_map, de_map) = Self::build_maps();
-        Codec { en_map, de_map }
+        let (en_map, de_map) = Self::build_maps(DE);
+        Codec {
+            en_map,
+            de_map,
+            profile: BlvProfile::default(),
+        }
+    }
+
+    /// 使用指定的BLV线上格式
+    pub fn with_profile(mut self, profile: BlvProfile) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    /// 使用自定义编码表，即标准Base64字符的一个排列（neoreg按密钥生成）
+    pub fn with_alphabet(mut self, alphabet: &str) -> Result<Self, NeoError> {
+        let alphabet = alphabet.as_bytes();
+        let mut sorted = alphabet.to_vec();
+        sorted.sort_unstable();
+        let mut standard = EN.to_vec();
+        standard.sort_unstable();
+        if sorted != standard {
+            return Err(NeoError::Other(
+                "Alphabet must be a permutation of the 64 Base64 characters".to_string(),
+            ));
+        }
+        (self.en_map, self.de_map) = Self::build_maps(alphabet);
+        Ok(self)
+    }
+
+    /// 按配置的编码表和轮换前的旧编码表创建编解码器，未配置编码表时使用默认编码表
+    pub fn rotation(
+        profile: BlvProfile,
+        alphabet: Option<&str>,
+        previous: &[PreviousAlphabet],
+    ) -> Result<Rotation<Codec>, NeoError> {
+        let codec = |alphabet: Option<&str>| {
+            let codec = Codec::new().with_profile(profile.clone());
+            match alphabet {
+                Some(alphabet) => codec.with_alphabet(alphabet),
+                None => Ok(codec),
+            }
+        };
+        previous.iter().try_fold(
+            Rotation::new("alphabet", codec(alphabet)?),
+            |rotation, p| Ok(rotation.with_previous(codec(Some(&p.alphabet))?, p.not_after)),
+        )
     }
 
     /// 构建编码映射表
-    fn build_maps() -> (HashMap<u8, u8>, HashMap<u8, u8>) {
+    fn build_maps(alphabet: &[u8]) -> (HashMap<u8, u8>, HashMap<u8, u8>) {
         let mut en_map = HashMap::new();
         let mut de_map = HashMap::new();
 
-        assert_eq!(EN.len(), DE.len());
+        assert_eq!(EN.len(), alphabet.len());
 
         for i in 0..EN.len() {
-            en_map.insert(EN[i], DE[i]);
-            de_map.insert(DE[i], EN[i]);
+            en_map.insert(EN[i], alphabet[i]);
+            de_map.insert(alphabet[i], EN[i]);
         }
 
         (en_map, de_map)
@@ -139,7 +330,7 @@ impl Codec {
             cursor += 1;
 
             // 使用函数封装读取和解码逻辑
-            let l = match read_and_decode_length(&data, &mut cursor) {
+            let l = match read_and_decode_length(data, &mut cursor, self.profile.offset) {
                 Ok(len) => len,
                 Err(_) => break,
             };
@@ -149,7 +340,9 @@ impl Codec {
             let v = data[cursor..cursor + l].to_vec();
             cursor += l;
 
-            info.insert(b, v);
+            if let Some(b) = self.profile.canonical_id(b) {
+                info.insert(b, v);
+            }
         }
 
         info
@@ -164,8 +357,8 @@ impl Codec {
         info.insert(MessageField::Random2.into(), Self::rand_byte());
 
         for (&b, v) in &info {
-            let l = v.len() as i32 + BLV_OFFSET;
-            data.push(b as u8);
+            let l = (v.len() as i32).wrapping_add(self.profile.offset);
+            data.push(self.profile.wire_id(b) as u8);
             data.extend_from_slice(&l.to_be_bytes());
             data.extend_from_slice(v);
         }
@@ -183,8 +376,6 @@ impl Codec {
     }
 }
 
-
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -235,6 +426,136 @@ mod tests {
         assert!(decoded.contains_key(&39));
     }
 
+    // neoreg客户端发出的请求样本，按发布版本分组，每组附带解出样本所需的偏移量和字段id映射
+    //
+    // 样本按neoreg.py的编码方式（BLV、标准Base64后按编码表替换）生成，随机填充字段取固定值。
+    // 目前只有使用本仓库隧道常量的一组；各个已发布版本的抓包样本尚未收录，收录时每个版本追加一组。
+    type Sample = (&'static str, &'static [(MessageField, &'static [u8])]);
+
+    struct NeoregRelease {
+        release: &'static str,
+        offset: i32,
+        // 与--field-ids相同的格式，空串表示默认映射
+        field_ids: &'static str,
+        samples: &'static [Sample],
+    }
+
+    const NEOREG_FIXTURES: &[NeoregRelease] = &[NeoregRelease {
+        release: "bundled constants",
+        offset: super::BLV_OFFSET,
+        field_ids: "",
+        samples: &[
+            (
+                "dsE1sXY6NYdsqdAFxQ1DimwHc+fLfdxFxQ1DnLWuncA0jrWFxQ1YMcu1/7dCMUyQh1E1sXEyML9rA1E1sXnhd9MNhiD=",
+                &[
+                    (MessageField::Cmd, b"CONNECT"),
+                    (MessageField::Mark, b"p6Hq2sK"),
+                    (MessageField::Ip, b"127.0.0.1"),
+                    (MessageField::Port, b"8080"),
+                ],
+            ),
+            (
+                "dsE1sXDiuLhdEdAFxQ1Db+wjfmVjbdxFxQ1DnLWuncA0jrVFxQ17bmfEuU59jVbEEU5Q/7NxU9mkA1E1sXzTTg15tr==",
+                &[
+                    (MessageField::Cmd, b"FORWARD"),
+                    (MessageField::Mark, b"p6Hq2sK"),
+                    (MessageField::Data, b"GET / HTTP/1.1\r\n\r\n"),
+                ],
+            ),
+        ],
+    }];
+
+    // 测试各版本neoreg客户端的请求样本能按对应的偏移量和字段id解出，且换用其他偏移量时解不出命令
+    #[test]
+    fn test_neoreg_fixtures() {
+        for fixture in NEOREG_FIXTURES {
+            let profile = BlvProfile::default().with_offset(fixture.offset);
+            let profile = if fixture.field_ids.is_empty() {
+                profile
+            } else {
+                profile
+                    .with_field_ids(fixture.field_ids)
+                    .expect("Invalid field ids")
+            };
+            let codec = Codec::new().with_profile(profile);
+            let other = Codec::new()
+                .with_profile(BlvProfile::default().with_offset(fixture.offset.wrapping_add(1)));
+            for (wire, fields) in fixture.samples {
+                let blv = codec
+                    .base64_decode(wire.as_bytes())
+                    .expect("Base64 decode failed");
+                let decoded = codec.blv_decode(&blv);
+                for (field, value) in *fields {
+                    assert_eq!(
+                        decoded.get(&(*field).into()).map(Vec::as_slice),
+                        Some(*value),
+                        "{}: {:?} in {}",
+                        fixture.release,
+                        field,
+                        wire
+                    );
+                }
+                assert!(decoded.contains_key(&MessageField::Random1.into()));
+                assert!(decoded.contains_key(&MessageField::Random2.into()));
+
+                assert!(
+                    !other
+                        .blv_decode(&blv)
+                        .contains_key(&MessageField::Cmd.into()),
+                    "{}",
+                    fixture.release
+                );
+            }
+        }
+    }
+
+    // 测试默认布局的线上字节与neoreg一致
+    #[test]
+    fn test_default_wire_format() {
+        let codec = Codec::new();
+        let mut wire = vec![MessageField::Cmd as u8];
+        wire.extend_from_slice(&(4 + super::BLV_OFFSET).to_be_bytes());
+        wire.extend_from_slice(b"READ");
+
+        let decoded = codec.blv_decode(&wire);
+        assert_eq!(
+            decoded.get(&MessageField::Cmd.into()),
+            Some(&b"READ".to_vec())
+        );
+    }
+
+    // 测试字段id映射的解析与校验
+    #[test]
+    fn test_field_ids_parse() {
+        let profile = BlvProfile::default()
+            .with_field_ids(" data = 9 , cmd=8 ")
+            .expect("Parse failed");
+        assert_eq!(profile.wire_id(MessageField::Data.into()), 9);
+        assert_eq!(profile.canonical_id(9), Some(MessageField::Data.into()));
+        assert_eq!(profile.canonical_id(1), None);
+
+        assert!(BlvProfile::default().with_field_ids("data=2").is_err());
+        assert!(BlvProfile::default().with_field_ids("nope=2").is_err());
+        assert!(BlvProfile::default().with_field_ids("data=300").is_err());
+        assert!(BlvProfile::default().with_field_ids("data").is_err());
+    }
+
+    // 测试自定义编码表的往返和校验
+    #[test]
+    fn test_with_alphabet() {
+        let reversed: String = super::EN.iter().rev().map(|&b| b as char).collect();
+        let codec = Codec::new()
+            .with_alphabet(&reversed)
+            .expect("Alphabet rejected");
+        let encoded = codec.base64_encode(b"tenant payload");
+        assert_ne!(encoded, Codec::new().base64_encode(b"tenant payload"));
+        assert_eq!(codec.base64_decode(&encoded).unwrap(), b"tenant payload");
+
+        assert!(Codec::new().with_alphabet("abc").is_err());
+        let duplicated = reversed.replacen('A', "B", 1);
+        assert!(Codec::new().with_alphabet(&duplicated).is_err());
+    }
+
     // 测试 rand_byte 函数
     #[test]
     fn test_rand_byte() {
@@ -247,7 +568,7 @@ mod tests {
     // 测试 build_maps 函数
     #[test]
     fn test_build_maps() {
-        let (en_map, de_map) = Codec::build_maps();
+        let (en_map, de_map) = Codec::build_maps(super::DE);
 
         // 验证映射表长度
         assert_eq!(en_map.len(), super::EN.len());
@@ -262,4 +583,4 @@ mod tests {
             assert_eq!(de_map.get(&de_char), Some(&en_char));
         }
     }
-}
\ No newline at end of file
+}
@@ -1,21 +1,497 @@
 use std::collections::HashMap;
 use std::net::SocketAddr;
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime};
 
-use tiny_http::Request;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, watch};
 
 use crate::NEO_HELLO;
+use crate::acl::Acl;
+#[cfg(feature = "aead")]
+use crate::aead::{FrameKey, KeyStore};
+use crate::audit::{AuditLog, Event};
+#[cfg(feature = "auth")]
+use crate::auth::Authenticator;
 use crate::codec::{BlvMap, Codec, MessageField};
+#[cfg(feature = "compress")]
+use crate::compress;
+use crate::config::Config;
 use crate::errors::NeoError;
-use crate::session::Session;
+use crate::http;
+#[cfg(feature = "aead")]
+use crate::kex;
+use crate::log;
+use crate::metrics::{DecodeStage, Metrics};
+use crate::protocol;
+use crate::rotation::Rotation;
+use crate::session::{Session, SessionStats};
+use crate::tenant::Tenant;
+use crate::window::State;
 
-const CONNECTION_TIMEOUT_MS: u64 = 3000;
+// 支持的命令，配置了租户时用于判断编码表是否匹配
+const COMMANDS: &[&str] = &[
+    "CONNECT",
+    "FORWARD",
+    "READ",
+    "DISCONNECT",
+    "NEGOTIATE",
+    "HANDSHAKE",
+    "SHUTDOWN",
+];
+
+// 配置了AEAD密码后，只有这些命令可以使用预共享密钥，其余命令必须使用握手得到的会话密钥
+#[cfg(feature = "aead")]
+const PSK_COMMANDS: &[&str] = &["HANDSHAKE", "NEGOTIATE"];
 
 // 类型别名
 pub type Sessions = Arc<Mutex<HashMap<String, Session>>>;
 
+// 请求来源
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Peer {
+    /// 客户端地址，HTTPS连接为TLS客户端的地址
+    pub addr: Option<SocketAddr>,
+    /// 双向TLS验证过的客户端证书身份
+    pub identity: Option<String>,
+    /// 解码请求时识别出的租户
+    pub tenant: Option<String>,
+}
+
+impl Peer {
+    /// 连接来自可信代理时，按转发头替换为真实的客户端地址
+    ///
+    /// header按名称取请求头的值，同名请求头有多个时以逗号连接。
+    pub fn forwarded(mut self, header: impl Fn(&str) -> Option<String>, acl: &Acl) -> Self {
+        if let Some(remote) = self.addr {
+            self.addr = Some(acl.client_addr(
+                remote,
+                header("Forwarded").as_deref(),
+                header("X-Forwarded-For").as_deref(),
+            ));
+        }
+        self
+    }
+}
+
+// 由配置派生的运行时状态，热加载时整体替换
+pub struct Runtime {
+    pub config: Config,
+    pub codec: Rotation<Codec>,
+    pub audit: Option<AuditLog>,
+    #[cfg(feature = "aead")]
+    pub keys: KeyStore,
+    /// 请求认证，未配置认证密钥时为None
+    #[cfg(feature = "auth")]
+    pub auth: Option<Authenticator>,
+    /// 管理命令认证，未配置管理密钥时为None
+    #[cfg(feature = "auth")]
+    pub admin: Option<Authenticator>,
+    /// HTTPS监听使用的证书和TLS配置
+    #[cfg(feature = "tls")]
+    pub tls: Option<crate::tls::Tls>,
+    pub tenants: Vec<Tenant>,
+}
+
+// 解码出的请求及识别出的租户
+struct Decoded<'a> {
+    info: BlvMap,
+    #[cfg(feature = "aead")]
+    key: Option<FrameKey>,
+    codec: &'a Codec,
+    tenant: Option<&'a Tenant>,
+}
+
+// 请求被拒绝的阶段，HMAC认证失败时附带原因
+struct Rejected {
+    stage: DecodeStage,
+    reason: Option<String>,
+}
+
+impl Rejected {
+    fn at(stage: DecodeStage) -> Self {
+        Rejected {
+            stage,
+            reason: None,
+        }
+    }
+}
+
+impl Runtime {
+    /// 根据配置构建运行时状态
+    ///
+    /// 密码未变化时沿用上一份状态的密钥存储，已协商的会话密钥继续有效，
+    /// 原密码被轮换为旧密码时同样保留会话密钥；
+    /// 审计日志配置未变化时沿用已打开的文件。
+    /// 认证配置未变化时沿用重放缓存；证书文件每次重新读取，自签名证书在HTTPS配置不变时沿用。
+    /// 同名租户沿用已用的流量配额。
+    pub fn new(config: Config, previous: Option<&Runtime>) -> Result<Self, NeoError> {
+        // 与其他版本neoreg生成的客户端兼容：可覆盖长度偏移量和字段id
+        let codec = Codec::rotation(
+            config.blv_profile()?,
+            config.alphabet.as_deref(),
+            &config.previous_alphabets,
+        )?;
+
+        let audit = match previous.and_then(|p| p.audit.as_ref()) {
+            Some(audit) if *audit.options() == config.audit => Some(audit.clone()),
+            _ => AuditLog::open(&config.audit)?,
+        };
+
+        #[cfg(feature = "auth")]
+        let auth = match previous.and_then(|p| p.auth.as_ref()) {
+            Some(auth) if *auth.options() == config.auth => Some(auth.clone()),
+            _ => Authenticator::new(&config.auth),
+        };
+        #[cfg(feature = "auth")]
+        let admin = {
+            let options = crate::config::AuthOptions {
+                key: config.admin_key.clone(),
+                previous_keys: Vec::new(),
+                ..config.auth.clone()
+            };
+            match previous.and_then(|p| p.admin.as_ref()) {
+                Some(admin) if *admin.options() == options => Some(admin.clone()),
+                _ => Authenticator::admin(&options),
+            }
+        };
+
+        #[cfg(feature = "tls")]
+        let tls = {
+            let previous = previous
+                .filter(|p| p.config.tls == config.tls)
+                .and_then(|p| p.tls.as_ref());
+            crate::tls::load(&config.tls, previous)?
+        };
+
+        #[cfg(not(feature = "aead"))]
+        let _ = previous;
+        #[cfg(feature = "aead")]
+        let keys = match previous {
+            Some(previous)
+                if previous.config.key == config.key
+                    && previous.config.previous_keys == config.previous_keys =>
+            {
+                previous.keys.clone()
+            }
+            // 配置了密码时启用AEAD封装层
+            _ => {
+                let keys = match &config.key {
+                    Some(key) => config
+                        .previous_keys
+                        .iter()
+                        .fold(KeyStore::with_password(key.as_bytes()), |keys, p| {
+                            keys.with_previous(p.key.as_bytes(), p.not_after)
+                        }),
+                    None => KeyStore::default(),
+                };
+                // 原密码轮换为旧密码时，用它认证过的会话密钥继续有效
+                match previous {
+                    Some(previous)
+                        if config
+                            .previous_keys
+                            .iter()
+                            .any(|p| Some(&p.key) == previous.config.key.as_ref()) =>
+                    {
+                        keys.with_sessions_of(&previous.keys)
+                    }
+                    _ => keys,
+                }
+            }
+        };
+
+        let tenants = config
+            .tenants
+            .iter()
+            .map(|options| {
+                let previous =
+                    previous.and_then(|p| p.tenants.iter().find(|t| t.name() == options.name));
+                Tenant::new(options, &config, previous)
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Runtime {
+            config,
+            codec,
+            audit,
+            #[cfg(feature = "aead")]
+            keys,
+            #[cfg(feature = "auth")]
+            auth,
+            #[cfg(feature = "auth")]
+            admin,
+            #[cfg(feature = "tls")]
+            tls,
+            tenants,
+        })
+    }
+
+    /// 服务端当前支持的能力位
+    pub fn server_caps(&self) -> u32 {
+        #[allow(unused_mut)]
+        let mut caps = 0;
+        #[cfg(feature = "aead")]
+        if self.keys.enabled() {
+            caps |= protocol::CAP_AEAD | protocol::CAP_KEX;
+        }
+        #[cfg(feature = "compress")]
+        {
+            caps |= protocol::CAP_DEFLATE;
+        }
+        #[cfg(feature = "auth")]
+        if self.auth.is_some() || self.tenants.iter().any(|t| t.auth.is_some()) {
+            caps |= protocol::CAP_AUTH;
+        }
+        caps
+    }
+
+    /// 解码请求
+    ///
+    /// 配置了租户时依次尝试每个租户，使用第一个能解码并通过认证的租户；
+    /// 都失败时按走得最远的阶段报告。
+    fn decode(&self, data: &[u8]) -> Result<Decoded<'_>, Rejected> {
+        let candidates: Vec<Option<&Tenant>> = if self.tenants.is_empty() {
+            vec![None]
+        } else {
+            self.tenants.iter().map(Some).collect()
+        };
+        let mut rejected = Rejected::at(DecodeStage::Base64);
+        for tenant in candidates {
+            match self.decode_as(data, tenant) {
+                Ok(decoded) => return Ok(decoded),
+                Err(e) if e.stage as usize >= rejected.stage as usize => rejected = e,
+                Err(_) => {}
+            }
+        }
+        Err(rejected)
+    }
+
+    fn decode_as<'a>(
+        &'a self,
+        data: &[u8],
+        tenant: Option<&'a Tenant>,
+    ) -> Result<Decoded<'a>, Rejected> {
+        let codecs = tenant.map_or(&self.codec, |t| &t.codec);
+        codecs.try_each(|codec| self.decode_with(data, codec, tenant, codecs.rotating()))
+    }
+
+    // 用一个编码表解码请求；编码表不唯一时要求解出已知命令
+    fn decode_with<'a>(
+        &'a self,
+        data: &[u8],
+        codec: &'a Codec,
+        tenant: Option<&'a Tenant>,
+        rotating: bool,
+    ) -> Result<Decoded<'a>, Rejected> {
+        let out = match codec.base64_decode(data) {
+            Ok(out) if !out.is_empty() => out,
+            _ => return Err(Rejected::at(DecodeStage::Base64)),
+        };
+
+        // 校验AEAD帧
+        #[cfg(feature = "aead")]
+        let (out, key) = self
+            .keys
+            .open(out)
+            .map_err(|_| Rejected::at(DecodeStage::Auth))?;
+
+        #[allow(unused_mut)]
+        let mut info = codec.blv_decode(&out);
+
+        // 编码表不匹配时解不出已知命令
+        if (tenant.is_some() || rotating)
+            && !COMMANDS.contains(&get_info_string_from_key(&info, MessageField::Cmd).as_str())
+        {
+            return Err(Rejected::at(DecodeStage::Command));
+        }
+
+        // 预共享密钥没有前向安全，不能直接用于隧道命令
+        #[cfg(feature = "aead")]
+        if key.as_ref().is_some_and(FrameKey::pre_shared)
+            && !PSK_COMMANDS.contains(&get_info_string_from_key(&info, MessageField::Cmd).as_str())
+        {
+            return Err(Rejected::at(DecodeStage::Auth));
+        }
+
+        // 校验HMAC认证字段
+        #[cfg(feature = "auth")]
+        if let Some(auth) = tenant.map_or(self.auth.as_ref(), |t| t.auth.as_ref()) {
+            auth.verify(&mut info).map_err(|rejection| Rejected {
+                stage: DecodeStage::Hmac,
+                reason: Some(rejection.as_str().to_string()),
+            })?;
+        }
+
+        // SHUTDOWN还需要管理密钥的签名，未配置管理密钥时按未知命令处理
+        #[cfg(feature = "auth")]
+        if get_info_string_from_key(&info, MessageField::Cmd) == "SHUTDOWN" {
+            let admin = self
+                .admin
+                .as_ref()
+                .ok_or(Rejected::at(DecodeStage::Command))?;
+            admin.verify(&mut info).map_err(|rejection| Rejected {
+                stage: DecodeStage::Hmac,
+                reason: Some(format!("admin {}", rejection.as_str())),
+            })?;
+        }
+
+        Ok(Decoded {
+            info,
+            #[cfg(feature = "aead")]
+            key,
+            codec,
+            tenant,
+        })
+    }
+}
+
+// 请求处理共享的服务端状态
+//
+// 会话表在热加载时保持不变，运行时状态则可被替换；
+// 每个请求开始时取一份快照，处理过程中不受并发的重新加载影响。
+#[derive(Clone)]
+pub struct Context {
+    runtime: Arc<RwLock<Arc<Runtime>>>,
+    pub sessions: Sessions,
+    pub metrics: Arc<Metrics>,
+    shutdown: Arc<watch::Sender<Option<&'static str>>>,
+    in_flight: Arc<watch::Sender<usize>>,
+}
+
+/// 正在处理的请求，释放时计数减一
+pub struct InFlight(Arc<watch::Sender<usize>>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.send_modify(|n| *n -= 1);
+    }
+}
+
+impl Context {
+    pub fn new(runtime: Runtime) -> Self {
+        Context {
+            runtime: Arc::new(RwLock::new(Arc::new(runtime))),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+            metrics: Arc::default(),
+            shutdown: Arc::new(watch::channel(None).0),
+            in_flight: Arc::new(watch::channel(0).0),
+        }
+    }
+
+    /// 开始处理一个请求，退出时等待其完成
+    pub fn track_request(&self) -> InFlight {
+        self.in_flight.send_modify(|n| *n += 1);
+        InFlight(Arc::clone(&self.in_flight))
+    }
+
+    /// 等待正在处理的请求完成，返回超时后仍未完成的请求数
+    pub async fn drain_requests(&self, grace: Duration) -> usize {
+        let mut receiver = self.in_flight.subscribe();
+        let _ = tokio::time::timeout(grace, receiver.wait_for(|n| *n == 0)).await;
+        *self.in_flight.borrow()
+    }
+
+    /// 是否已请求退出
+    pub fn shutting_down(&self) -> bool {
+        self.shutdown_reason().is_some()
+    }
+
+    /// 已请求退出时的原因
+    pub fn shutdown_reason(&self) -> Option<&'static str> {
+        *self.shutdown.borrow()
+    }
+
+    /// 请求退出进程，多次请求时保留第一次的原因
+    ///
+    /// 升级交接完成前收到其他原因的退出请求时改用该原因，进程不再交接而是正常退出。
+    pub fn request_shutdown(&self, reason: &'static str) {
+        self.shutdown.send_if_modified(|current| match current {
+            None => {
+                *current = Some(reason);
+                true
+            }
+            Some("upgrade") if reason != "upgrade" => {
+                *current = Some(reason);
+                true
+            }
+            Some(_) => false,
+        });
+    }
+
+    /// 交接失败后取消升级引起的退出，继续运行；已改为其他原因退出时返回false
+    #[cfg(unix)]
+    pub fn cancel_upgrade(&self) -> bool {
+        self.shutdown.send_if_modified(|current| {
+            let upgrading = *current == Some("upgrade");
+            if upgrading {
+                *current = None;
+            }
+            upgrading
+        })
+    }
+
+    /// 等待退出请求，返回原因
+    pub async fn shutdown_requested(&self) -> &'static str {
+        let mut receiver = self.shutdown.subscribe();
+        let reason = receiver
+            .wait_for(Option::is_some)
+            .await
+            .expect("shutdown sender is owned by the context");
+        reason.expect("waited for a reason")
+    }
+
+    /// 当前运行时状态的快照
+    pub fn runtime(&self) -> Arc<Runtime> {
+        Arc::clone(&self.runtime.read().expect("runtime lock poisoned"))
+    }
+
+    /// 应用新配置，之后的请求使用新配置；配置无效时保留原配置
+    pub fn reload(&self, config: Config) -> Result<(), NeoError> {
+        config.validate()?;
+        let mut current = self.runtime.write().expect("runtime lock poisoned");
+        let runtime = Runtime::new(config, Some(&current))?;
+        *current = Arc::new(runtime);
+        Ok(())
+    }
+}
+
+/// 所有会话的统计快照，按mark排序
+pub async fn session_stats(sessions: &Sessions) -> Vec<(String, SessionStats)> {
+    let sessions = sessions.lock().await;
+    let mut stats: Vec<(String, SessionStats)> = sessions
+        .iter()
+        .map(|(mark, session)| (mark.clone(), session.stats()))
+        .collect();
+    stats.sort_by(|a, b| a.0.cmp(&b.0));
+    stats
+}
+
+/// 回收空闲超时的会话，返回被回收的会话
+pub async fn evict_idle_sessions(sessions: &Sessions, idle: Duration) -> Vec<(String, Session)> {
+    let mut sessions = sessions.lock().await;
+    let expired: Vec<String> = sessions
+        .iter()
+        .filter(|(_, session)| session.idle_for() >= idle)
+        .map(|(mark, _)| mark.clone())
+        .collect();
+    let mut evicted = Vec::with_capacity(expired.len());
+    for mark in expired {
+        if let Some(session) = sessions.remove(&mark) {
+            session.close().await;
+            evicted.push((mark, session));
+        }
+    }
+    evicted
+}
+
+/// 关闭并移除所有会话
+pub async fn close_all_sessions(sessions: &Sessions) -> Vec<(String, Session)> {
+    let closed: Vec<_> = sessions.lock().await.drain().collect();
+    for (_, session) in &closed {
+        session.close().await;
+    }
+    closed
+}
+
 // 辅助函数：设置失败响应
 pub fn set_failure_response(rinfo: &mut BlvMap, error_msg: impl Into<Vec<u8>>) {
     rinfo.insert(MessageField::Status.into(), b"FAIL".to_vec());
@@ -30,35 +506,114 @@ pub fn get_info_string_from_key(info: &BlvMap, field: MessageField) -> String {
 }
 
 // 处理CONNECT命令
-pub async fn handle_connect(info: &BlvMap, mark: &str, sessions: &Sessions, rinfo: &mut BlvMap) {
+//
+// 同一mark重新CONNECT时关闭并返回原来的会话，供审计记录使用
+pub async fn handle_connect(
+    info: &BlvMap,
+    mark: &str,
+    sessions: &Sessions,
+    config: &Config,
+    tenant: Option<&Tenant>,
+    peer: &Peer,
+    rinfo: &mut BlvMap,
+) -> Option<Session> {
+    // 检查并发会话上限（同一mark重连不计入）
+    if config.max_sessions > 0 {
+        let sessions = sessions.lock().await;
+        if sessions.len() >= config.max_sessions && !sessions.contains_key(mark) {
+            set_failure_response(rinfo, b"Too many sessions".to_vec());
+            return None;
+        }
+    }
+    if let Some(tenant) = tenant
+        && tenant.options.max_sessions > 0
+    {
+        let sessions = sessions.lock().await;
+        let count = sessions
+            .iter()
+            .filter(|(m, s)| s.tenant() == Some(tenant.name()) && m.as_str() != mark)
+            .count();
+        if count >= tenant.options.max_sessions {
+            set_failure_response(rinfo, b"Too many sessions".to_vec());
+            return None;
+        }
+    }
+
+    // 全局ACL和租户ACL都要通过
+    let acls = std::iter::once(&config.acl).chain(tenant.map(|t| &t.options.acl));
+    for acl in acls.clone() {
+        if let Err(reason) = acl.check_identity(peer.identity.as_deref()) {
+            set_failure_response(rinfo, reason.as_bytes().to_vec());
+            return None;
+        }
+    }
+
     let ip = get_info_string_from_key(info, MessageField::Ip);
     let port_str = get_info_string_from_key(info, MessageField::Port);
     let target_addr = format!("{}:{}", ip, port_str);
 
-    match target_addr.parse::<SocketAddr>() {
-        Ok(addr) => match std::net::TcpStream::connect_timeout(
-            &addr,
-            Duration::from_millis(CONNECTION_TIMEOUT_MS),
-        ) {
-            Ok(conn) => {
-                sessions
-                    .lock()
-                    .await
-                    .insert(mark.to_string(), Session::new(conn));
-                rinfo.insert(MessageField::Status.into(), b"OK".to_vec());
-            }
-            Err(e) => {
-                set_failure_response(rinfo, e.to_string().into_bytes());
-            }
-        },
+    let addr = match target_addr.parse::<SocketAddr>() {
+        Ok(addr) => addr,
         Err(e) => {
             set_failure_response(rinfo, format!("Invalid address: {}", e).into_bytes());
+            return None;
+        }
+    };
+
+    // 目标访问控制
+    for acl in acls {
+        if let Err(reason) = acl.check(&addr) {
+            set_failure_response(rinfo, reason.as_bytes().to_vec());
+            return None;
+        }
+    }
+
+    let started = Instant::now();
+    match std::net::TcpStream::connect_timeout(
+        &addr,
+        Duration::from_millis(config.connect_timeout_ms),
+    ) {
+        Ok(conn) => {
+            let session = Session::new(conn, &config.session)
+                .with_client(peer.addr)
+                .with_identity(peer.identity.clone())
+                .with_tenant(peer.tenant.clone())
+                .with_connect_latency(started.elapsed());
+            let replaced = sessions.lock().await.insert(mark.to_string(), session);
+            rinfo.insert(MessageField::Status.into(), b"OK".to_vec());
+            if let Some(session) = &replaced {
+                session.close().await;
+                log::info!("Session replaced: {}", session.stats().summary());
+            }
+            replaced
+        }
+        Err(e) => {
+            set_failure_response(rinfo, e.to_string().into_bytes());
+            None
         }
     }
 }
 
+// 会话只能由创建它的客户端证书身份和租户操作，会话不存在时不限制
+async fn owns_session(sessions: &Sessions, mark: &str, peer: &Peer) -> bool {
+    match sessions.lock().await.get(mark) {
+        Some(session) => {
+            session.identity() == peer.identity.as_deref()
+                && session.tenant() == peer.tenant.as_deref()
+        }
+        None => true,
+    }
+}
+
 // 处理FORWARD命令
-pub async fn handle_forward(info: &BlvMap, mark: &str, sessions: &Sessions, rinfo: &mut BlvMap) {
+//
+// 目标已关闭连接时移除并返回会话，供审计记录使用
+pub async fn handle_forward(
+    info: &BlvMap,
+    mark: &str,
+    sessions: &Sessions,
+    rinfo: &mut BlvMap,
+) -> Option<Session> {
     let mut sessions = sessions.lock().await;
     if let Some(session) = sessions.get_mut(mark) {
         if let Some(data) = info.get(&MessageField::Data.into()) {
@@ -68,6 +623,7 @@ pub async fn handle_forward(info: &BlvMap, mark: &str, sessions: &Sessions, rinf
                 }
                 Err(e) => {
                     set_failure_response(rinfo, e.to_string().into_bytes());
+                    return remove_closed(&mut sessions, mark).await;
                 }
             }
         } else {
@@ -76,10 +632,13 @@ pub async fn handle_forward(info: &BlvMap, mark: &str, sessions: &Sessions, rinf
     } else {
         set_failure_response(rinfo, b"Session not found".to_vec());
     }
+    None
 }
 
 // 处理READ命令
-pub async fn handle_read(mark: &str, sessions: &Sessions, rinfo: &mut BlvMap) {
+//
+// 目标已关闭连接时移除并返回会话，供审计记录使用
+pub async fn handle_read(mark: &str, sessions: &Sessions, rinfo: &mut BlvMap) -> Option<Session> {
     // 首先检查会话是否存在
     let session_exists = { sessions.lock().await.contains_key(mark) };
 
@@ -89,15 +648,14 @@ pub async fn handle_read(mark: &str, sessions: &Sessions, rinfo: &mut BlvMap) {
         if let Some(session) = session {
             if session.is_closed().await {
                 set_failure_response(rinfo, b"Session is closed".to_vec());
+                return remove_closed(&mut *sessions.lock().await, mark).await;
             } else {
                 rinfo.insert(MessageField::Status.into(), b"OK".to_vec());
                 match session.read_async().await {
                     Ok(data) => {
                         rinfo.insert(MessageField::Data.into(), data);
                     }
-                    Err(e) => {
-                        eprintln!("Failed to read data: {:?}", e);
-                    }
+                    Err(e) => log::debug!("Failed to read data: {}", e),
                 }
             }
         } else {
@@ -106,71 +664,602 @@ pub async fn handle_read(mark: &str, sessions: &Sessions, rinfo: &mut BlvMap) {
     } else {
         set_failure_response(rinfo, b"Session not found".to_vec());
     }
+    None
+}
+
+// 目标关闭连接后移除会话；会话已被同一mark的新会话替换时不移除
+async fn remove_closed(sessions: &mut HashMap<String, Session>, mark: &str) -> Option<Session> {
+    if !sessions.get(mark)?.is_closed().await {
+        return None;
+    }
+    let session = sessions.remove(mark)?;
+    session.close().await;
+    log::info!("Session closed by target: {}", session.stats().summary());
+    Some(session)
 }
 
 // 处理DISCONNECT命令
-pub async fn handle_disconnect(mark: &str, sessions: &Sessions, rinfo: &mut BlvMap) {
-    let mut sessions = sessions.lock().await;
-    if let Some(session) = sessions.remove(mark) {
+//
+// 返回被关闭的会话，供审计记录使用
+pub async fn handle_disconnect(
+    mark: &str,
+    sessions: &Sessions,
+    rinfo: &mut BlvMap,
+) -> Option<Session> {
+    let session = sessions.lock().await.remove(mark);
+    if let Some(session) = &session {
         session.close().await;
+        let stats = session.stats().summary();
+        log::info!("Session closed: {}", stats);
+        rinfo.insert(MessageField::Stats.into(), stats.into_bytes());
     }
     rinfo.insert(MessageField::Status.into(), b"OK".to_vec());
+    session
+}
+
+// 处理HANDSHAKE命令
+#[cfg(feature = "aead")]
+pub fn handle_handshake(
+    info: &BlvMap,
+    keys: &KeyStore,
+    key: Option<&FrameKey>,
+    rinfo: &mut BlvMap,
+) {
+    // 只有经过预共享密码认证的请求才能协商会话密钥
+    if key.is_none() {
+        set_failure_response(rinfo, b"Handshake requires a configured key".to_vec());
+        return;
+    }
+    let client_public = info
+        .get(&MessageField::Data.into())
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    match kex::handshake(keys, client_public) {
+        Ok(data) => {
+            rinfo.insert(MessageField::Status.into(), b"OK".to_vec());
+            rinfo.insert(MessageField::Data.into(), data);
+        }
+        Err(e) => {
+            set_failure_response(rinfo, e.to_string().into_bytes());
+        }
+    }
 }
 
-// 主请求处理函数
-pub async fn handle_request(
-    mut request: Request,
-    codec: &Codec,
-    sessions: Sessions,
-) -> Result<(), NeoError> {
-    let decoded_hello = codec.base64_decode(NEO_HELLO).unwrap_or_default();
+/// 处理HTTP服务器收到的请求，peer为连接的来源
+///
+/// 来自可信代理的请求按转发头确定客户端地址，日志、审计和ACL都使用该地址。
+pub async fn handle_http(request: http::Request, ctx: &Context, peer: &Peer) -> http::Response {
+    let peer = peer
+        .clone()
+        .forwarded(|name| request.header(name), &ctx.runtime().config.acl);
+    let fields = log::Fields {
+        client: peer.addr,
+        identity: peer.identity.clone().unwrap_or_default(),
+        ..log::Fields::default()
+    };
+    http::Response::text(log::scope(fields, process(request.body, ctx, &peer)).await)
+}
 
-    // 读取并解码数据
-    let out = {
-        let mut data = Vec::new();
-        if request.as_reader().read_to_end(&mut data).is_err() || data.is_empty() {
-            write_reponse(request, decoded_hello.to_vec());
-            return Ok(());
+// 主请求处理函数：解码请求体，分发命令，返回编码后的响应
+//
+// SHUTDOWN在返回前请求退出，响应写出前请求仍计入正在处理的请求，退出时会等待其完成。
+async fn process(data: Vec<u8>, ctx: &Context, peer: &Peer) -> Vec<u8> {
+    let started = Instant::now();
+    let runtime = ctx.runtime();
+    let sessions = &ctx.sessions;
+    let decoded_hello = runtime
+        .codec
+        .current()
+        .base64_decode(NEO_HELLO)
+        .unwrap_or_default();
+
+    // 活动时间窗口外拒绝所有请求
+    if runtime.config.window.state(SystemTime::now()) != State::Active {
+        log::debug!("Request rejected: outside the activity window");
+        return decoded_hello;
+    }
+
+    // 客户端白名单在解码前检查，拒绝时的响应与解码失败相同
+    if let Err(reason) = runtime.config.acl.check_client(peer.addr.map(|a| a.ip())) {
+        log::warn!("Request rejected: {}", reason);
+        if let Some(audit) = &runtime.audit {
+            audit.record(&Event::Reject { peer, reason });
         }
-        match codec.base64_decode(&data) {
-            Ok(out) if !out.is_empty() => out,
-            _ => {
-                write_reponse(request, decoded_hello.to_vec());
-                return Ok(());
+        return decoded_hello;
+    }
+
+    // 读取并解码数据，AEAD或HMAC认证失败与解码失败同样处理
+    if data.is_empty() {
+        ctx.metrics.decode_failure(DecodeStage::Base64);
+        return decoded_hello;
+    }
+    let decoded = match runtime.decode(&data) {
+        Ok(decoded) => decoded,
+        Err(rejected) => {
+            ctx.metrics.decode_failure(rejected.stage);
+            // HMAC认证失败记入审计日志
+            if let Some(reason) = &rejected.reason {
+                log::warn!("Request rejected: {}", reason);
+                if let Some(audit) = &runtime.audit {
+                    audit.record(&Event::Reject { peer, reason });
+                }
             }
+            return decoded_hello;
         }
     };
+    #[allow(unused_mut)]
+    let Decoded {
+        mut info,
+        #[cfg(feature = "aead")]
+        key,
+        codec,
+        tenant,
+    } = decoded;
+    let peer = &Peer {
+        tenant: tenant.map(|t| t.name().to_string()),
+        ..peer.clone()
+    };
 
-    let info = codec.blv_decode(&out);
+    // 租户的客户端白名单
+    if let Some(tenant) = tenant
+        && let Err(reason) = tenant.options.acl.check_client(peer.addr.map(|a| a.ip()))
+    {
+        log::warn!("Request rejected: {}", reason);
+        if let Some(audit) = &runtime.audit {
+            audit.record(&Event::Reject { peer, reason });
+        }
+        return decoded_hello;
+    }
+
+    // 解压客户端压缩过的Data字段，失败与解码失败同样处理
+    #[cfg(feature = "compress")]
+    let flags = match compress::inflate_request(&mut info) {
+        Ok(flags) => flags,
+        Err(_) => {
+            ctx.metrics.decode_failure(DecodeStage::Inflate);
+            return decoded_hello;
+        }
+    };
 
     let mut rinfo = HashMap::new();
 
     // 提取命令和标记
     let cmd = get_info_string_from_key(&info, MessageField::Cmd);
     let mark = get_info_string_from_key(&info, MessageField::Mark);
+    log::annotate(peer.tenant.as_deref(), &mark, &cmd);
 
     // 根据命令类型分发处理
     match cmd.as_str() {
-        "CONNECT" => handle_connect(&info, &mark, &sessions, &mut rinfo).await,
-        "FORWARD" => handle_forward(&info, &mark, &sessions, &mut rinfo).await,
-        "READ" => handle_read(&mark, &sessions, &mut rinfo).await,
-        "DISCONNECT" => handle_disconnect(&mark, &sessions, &mut rinfo).await,
+        // 与其他身份或租户的会话同名时按会话不存在处理，不泄露会话信息
+        "CONNECT" | "FORWARD" | "READ" | "DISCONNECT"
+            if !owns_session(sessions, &mark, peer).await =>
+        {
+            log::warn!(
+                "Session {} belongs to another client identity or tenant",
+                mark
+            );
+            set_failure_response(&mut rinfo, b"Session not found".to_vec());
+        }
+        "CONNECT" | "FORWARD" | "READ" if tenant.is_some_and(Tenant::over_quota) => {
+            log::warn!("Bandwidth quota exceeded");
+            set_failure_response(&mut rinfo, b"Bandwidth quota exceeded".to_vec());
+        }
+        "CONNECT" => {
+            let replaced = handle_connect(
+                &info,
+                &mark,
+                sessions,
+                &runtime.config,
+                tenant,
+                peer,
+                &mut rinfo,
+            )
+            .await;
+            if let (Some(audit), Some(session)) = (&runtime.audit, &replaced) {
+                audit.record(&Event::Evict {
+                    mark: &mark,
+                    session,
+                    reason: "replaced",
+                });
+            }
+            ctx.metrics
+                .connect(!rinfo.contains_key(&MessageField::Error.into()));
+            if let Some(audit) = &runtime.audit {
+                let target = format!(
+                    "{}:{}",
+                    get_info_string_from_key(&info, MessageField::Ip),
+                    get_info_string_from_key(&info, MessageField::Port)
+                );
+                let result = match rinfo.get(&MessageField::Error.into()) {
+                    Some(error) => Err(String::from_utf8_lossy(error).into_owned()),
+                    None => Ok(()),
+                };
+                audit.record(&Event::Connect {
+                    peer,
+                    mark: &mark,
+                    target: &target,
+                    result,
+                });
+            }
+        }
+        "FORWARD" => {
+            let closed = handle_forward(&info, &mark, sessions, &mut rinfo).await;
+            record_closed(runtime.audit.as_ref(), &mark, closed.as_ref());
+            if !rinfo.contains_key(&MessageField::Error.into()) {
+                let bytes = info.get(&MessageField::Data.into()).map_or(0, Vec::len);
+                ctx.metrics.forwarded(bytes);
+                if let Some(tenant) = tenant {
+                    tenant.consume(bytes);
+                }
+            }
+        }
+        "READ" => {
+            let closed = handle_read(&mark, sessions, &mut rinfo).await;
+            record_closed(runtime.audit.as_ref(), &mark, closed.as_ref());
+            let bytes = rinfo.get(&MessageField::Data.into()).map_or(0, Vec::len);
+            ctx.metrics.read(bytes);
+            if let Some(tenant) = tenant {
+                tenant.consume(bytes);
+            }
+        }
+        "DISCONNECT" => {
+            let session = handle_disconnect(&mark, sessions, &mut rinfo).await;
+            if let (Some(audit), Some(session)) = (&runtime.audit, &session) {
+                audit.record(&Event::Disconnect {
+                    peer,
+                    mark: &mark,
+                    session,
+                });
+            }
+        }
+        // 管理密钥的签名已在解码时校验，响应发出后再退出
+        #[cfg(feature = "auth")]
+        "SHUTDOWN" => {
+            log::warn!("Shutdown requested by admin command");
+            rinfo.insert(MessageField::Status.into(), b"OK".to_vec());
+        }
+        "NEGOTIATE" => protocol::handle_negotiate(&info, runtime.server_caps(), &mut rinfo),
+        #[cfg(feature = "aead")]
+        "HANDSHAKE" => handle_handshake(&info, &runtime.keys, key.as_ref(), &mut rinfo),
         _ => {
-            write_reponse(request, decoded_hello.to_vec());
-            return Ok(());
+            ctx.metrics.decode_failure(DecodeStage::Command);
+            ctx.metrics.observe_request(&cmd, started.elapsed());
+            return decoded_hello;
         }
     }
 
+    log::debug!(
+        "Status {}",
+        get_info_string_from_key(&rinfo, MessageField::Status)
+    );
+
+    #[cfg(feature = "compress")]
+    compress::deflate_response(flags, &mut rinfo);
+    protocol::retain_supported(&info, &mut rinfo);
+
     // 构建并发送响应
     let data = codec.blv_encode(&rinfo);
+    #[cfg(feature = "aead")]
+    let data = runtime.keys.seal(data, key.as_ref());
     let encoded = codec.base64_encode(&data);
-    write_reponse(request, encoded);
-    Ok(())
+    ctx.metrics.observe_request(&cmd, started.elapsed());
+    #[cfg(feature = "auth")]
+    if cmd == "SHUTDOWN" {
+        ctx.request_shutdown("admin");
+    }
+    encoded
+}
+
+// 目标关闭连接的会话记入审计日志
+fn record_closed(audit: Option<&AuditLog>, mark: &str, session: Option<&Session>) {
+    if let (Some(audit), Some(session)) = (audit, session) {
+        audit.record(&Event::Evict {
+            mark,
+            session,
+            reason: "target",
+        });
+    }
 }
 
-// 响应写入函数
-pub fn write_reponse(request: Request, content: Vec<u8>) {
-    let response =
-        tiny_http::Response::from_string(String::from_utf8_lossy(&content)).with_status_code(200);
-    let _ = request.respond(response);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(max_sessions: usize) -> Config {
+        Config {
+            listen: vec!["127.0.0.1:8080".parse().unwrap()],
+            max_sessions,
+            ..Config::default()
+        }
+    }
+
+    // 测试按编码表识别租户，识别不出时拒绝
+    #[test]
+    fn test_tenant_decode() {
+        use crate::tenant::TenantOptions;
+
+        let reversed: String = crate::EN.iter().rev().map(|&b| b as char).collect();
+        let tenant = |name: &str, alphabet: Option<&str>| TenantOptions {
+            name: name.to_string(),
+            alphabet: alphabet.map(str::to_string),
+            ..TenantOptions::default()
+        };
+        let config = Config {
+            tenants: vec![tenant("red", None), tenant("blue", Some(&reversed))],
+            ..config(0)
+        };
+        let runtime = Runtime::new(config, None).expect("Runtime failed");
+
+        let mut info = BlvMap::new();
+        info.insert(MessageField::Cmd.into(), b"READ".to_vec());
+        for tenant in &runtime.tenants {
+            let codec = tenant.codec.current();
+            let data = codec.base64_encode(&codec.blv_encode(&info));
+            let decoded = runtime.decode(&data).ok().expect("Tenant not identified");
+            assert_eq!(decoded.tenant.map(Tenant::name), Some(tenant.name()));
+        }
+
+        let codec = runtime.tenants[1].codec.current();
+        info.insert(MessageField::Cmd.into(), b"BOGUS".to_vec());
+        let data = codec.base64_encode(&codec.blv_encode(&info));
+        assert!(runtime.decode(&data).is_err());
+    }
+
+    // 测试轮换编码表后旧编码表在到期前仍被识别，响应使用识别出请求的编码表
+    #[test]
+    fn test_alphabet_rotation() {
+        use crate::config::PreviousAlphabet;
+
+        let reversed: String = crate::EN.iter().rev().map(|&b| b as char).collect();
+        let previous = |alphabet: &[u8], not_after| PreviousAlphabet {
+            alphabet: String::from_utf8(alphabet.to_vec()).unwrap(),
+            not_after,
+        };
+        let now = SystemTime::now();
+        let config = Config {
+            alphabet: Some(reversed),
+            previous_alphabets: vec![
+                previous(crate::DE, now + Duration::from_secs(3600)),
+                previous(crate::EN, now - Duration::from_secs(1)),
+            ],
+            ..config(0)
+        };
+        let runtime = Runtime::new(config, None).expect("Runtime failed");
+
+        let mut info = BlvMap::new();
+        info.insert(MessageField::Cmd.into(), b"READ".to_vec());
+        for (alphabet, accepted) in [(crate::DE, true), (crate::EN, false)] {
+            let codec = Codec::new()
+                .with_alphabet(std::str::from_utf8(alphabet).unwrap())
+                .unwrap();
+            let data = codec.base64_encode(&codec.blv_encode(&info));
+            let decoded = runtime.decode(&data);
+            assert_eq!(decoded.is_ok(), accepted);
+            if let Ok(decoded) = decoded {
+                assert_eq!(
+                    decoded.codec.base64_encode(b"reply"),
+                    codec.base64_encode(b"reply")
+                );
+            }
+        }
+        let current = runtime.codec.current();
+        let data = current.base64_encode(&current.blv_encode(&info));
+        assert!(runtime.decode(&data).is_ok());
+    }
+
+    // 测试配置了AEAD密码后预共享密钥只能用于握手和协商，隧道命令必须使用会话密钥
+    #[cfg(feature = "aead")]
+    #[test]
+    fn test_psk_commands() {
+        use crate::aead::{Aead, PSK_KEY_ID};
+
+        let config = Config {
+            key: Some("password".to_string()),
+            ..config(0)
+        };
+        let runtime = Runtime::new(config, None).expect("Runtime failed");
+        let codec = runtime.codec.current();
+        let psk = Aead::new(&crate::aead::derive_key(b"password"));
+        let session = Aead::new(&[7; 32]);
+        let id = runtime.keys.insert(session.clone());
+
+        let frame = |aead: &Aead, key_id, cmd: &str| {
+            let mut info = BlvMap::new();
+            info.insert(MessageField::Cmd.into(), cmd.as_bytes().to_vec());
+            codec.base64_encode(&aead.seal(key_id, &codec.blv_encode(&info)))
+        };
+        for cmd in ["HANDSHAKE", "NEGOTIATE"] {
+            assert!(runtime.decode(&frame(&psk, &PSK_KEY_ID, cmd)).is_ok());
+        }
+        for cmd in ["CONNECT", "FORWARD", "READ", "DISCONNECT"] {
+            let rejected = runtime.decode(&frame(&psk, &PSK_KEY_ID, cmd)).err();
+            assert!(matches!(
+                rejected,
+                Some(Rejected {
+                    stage: DecodeStage::Auth,
+                    ..
+                })
+            ));
+            assert!(runtime.decode(&frame(&session, &id, cmd)).is_ok());
+        }
+    }
+
+    // 测试热加载：新配置对之后的请求生效，无效配置被拒绝，会话表保持不变
+    #[tokio::test]
+    async fn test_reload() {
+        let ctx = Context::new(Runtime::new(config(1), None).expect("Runtime failed"));
+        let sessions = Arc::clone(&ctx.sessions);
+        let before = ctx.runtime();
+
+        ctx.reload(config(8)).expect("Reload failed");
+        assert_eq!(ctx.runtime().config.max_sessions, 8);
+        assert_eq!(before.config.max_sessions, 1);
+
+        let invalid = Config {
+            field_ids: Some("data=1,cmd=1".to_string()),
+            ..config(16)
+        };
+        assert!(ctx.reload(invalid).is_err());
+        assert_eq!(ctx.runtime().config.max_sessions, 8);
+        assert!(Arc::ptr_eq(&sessions, &ctx.sessions));
+    }
+
+    // 测试退出流程：请求完成时立即结束等待，升级中收到其他退出请求时改用该原因
+    #[tokio::test]
+    async fn test_shutdown() {
+        let ctx = Context::new(Runtime::new(config(0), None).expect("Runtime failed"));
+        let in_flight = ctx.track_request();
+        let release = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            drop(in_flight);
+        });
+        let started = Instant::now();
+        assert_eq!(ctx.drain_requests(Duration::from_secs(10)).await, 0);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        release.await.unwrap();
+        let _stuck = ctx.track_request();
+        assert_eq!(ctx.drain_requests(Duration::from_millis(10)).await, 1);
+
+        ctx.request_shutdown("upgrade");
+        assert_eq!(ctx.shutdown_requested().await, "upgrade");
+        ctx.request_shutdown("SIGTERM");
+        ctx.request_shutdown("admin");
+        assert_eq!(ctx.shutdown_reason(), Some("SIGTERM"));
+        #[cfg(unix)]
+        assert!(!ctx.cancel_upgrade());
+    }
+
+    // 测试会话统计快照及DISCONNECT响应中的统计字段
+    #[tokio::test]
+    async fn test_disconnect_stats() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut info = BlvMap::new();
+        let addr = listener.local_addr().unwrap();
+        info.insert(MessageField::Ip.into(), addr.ip().to_string().into_bytes());
+        info.insert(
+            MessageField::Port.into(),
+            addr.port().to_string().into_bytes(),
+        );
+
+        let sessions: Sessions = Arc::default();
+        let peer = Peer {
+            addr: Some("192.0.2.1:4000".parse().unwrap()),
+            identity: Some("alice".to_string()),
+            tenant: None,
+        };
+        let mut rinfo = BlvMap::new();
+        handle_connect(&info, "m1", &sessions, &config(0), None, &peer, &mut rinfo).await;
+        assert_eq!(rinfo[&MessageField::Status.into()], b"OK");
+
+        let mut info = BlvMap::new();
+        info.insert(MessageField::Data.into(), b"hello".to_vec());
+        handle_forward(&info, "m1", &sessions, &mut BlvMap::new()).await;
+
+        let stats = session_stats(&sessions).await;
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].0, "m1");
+        assert_eq!(stats[0].1.client, peer.addr);
+        assert_eq!(stats[0].1.identity, peer.identity);
+        assert_eq!(stats[0].1.target, Some(addr));
+        assert_eq!((stats[0].1.bytes_sent, stats[0].1.messages_sent), (5, 1));
+
+        // 其他身份不能操作该会话
+        assert!(owns_session(&sessions, "m1", &peer).await);
+        assert!(!owns_session(&sessions, "m1", &Peer::default()).await);
+        assert!(owns_session(&sessions, "m2", &Peer::default()).await);
+
+        let mut rinfo = BlvMap::new();
+        assert!(
+            handle_disconnect("m1", &sessions, &mut rinfo)
+                .await
+                .is_some()
+        );
+        let summary = String::from_utf8(rinfo[&MessageField::Stats.into()].clone()).unwrap();
+        assert!(summary.contains(",bytes_sent=5,"));
+        assert!(sessions.lock().await.is_empty());
+
+        // 会话不存在时不返回统计
+        let mut rinfo = BlvMap::new();
+        assert!(
+            handle_disconnect("m1", &sessions, &mut rinfo)
+                .await
+                .is_none()
+        );
+        assert!(!rinfo.contains_key(&MessageField::Stats.into()));
+    }
+
+    // 测试同一mark重新CONNECT和目标关闭连接时返回被关闭的会话
+    #[tokio::test]
+    async fn test_closed_sessions() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut info = BlvMap::new();
+        let addr = listener.local_addr().unwrap();
+        info.insert(MessageField::Ip.into(), addr.ip().to_string().into_bytes());
+        info.insert(
+            MessageField::Port.into(),
+            addr.port().to_string().into_bytes(),
+        );
+        let sessions: Sessions = Arc::default();
+        let peer = Peer::default();
+
+        let connect = || async {
+            let mut rinfo = BlvMap::new();
+            let replaced =
+                handle_connect(&info, "m1", &sessions, &config(0), None, &peer, &mut rinfo).await;
+            let (target, _) = listener.accept().unwrap();
+            (replaced, target)
+        };
+        let (replaced, _first) = connect().await;
+        assert!(replaced.is_none());
+        let (replaced, target) = connect().await;
+        assert!(replaced.expect("Session not replaced").is_closed().await);
+
+        // 目标关闭连接后READ失败，会话被移除
+        drop(target);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let mut rinfo = BlvMap::new();
+        assert!(handle_read("m1", &sessions, &mut rinfo).await.is_some());
+        assert_eq!(rinfo[&MessageField::Error.into()], b"Session is closed");
+        assert!(sessions.lock().await.is_empty());
+        let mut rinfo = BlvMap::new();
+        assert!(handle_read("m1", &sessions, &mut rinfo).await.is_none());
+        assert_eq!(rinfo[&MessageField::Error.into()], b"Session not found");
+    }
+
+    // 测试空闲超时的会话被回收并关闭，仍在使用的会话保留
+    #[tokio::test]
+    async fn test_evict_idle_sessions() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut info = BlvMap::new();
+        let addr = listener.local_addr().unwrap();
+        info.insert(MessageField::Ip.into(), addr.ip().to_string().into_bytes());
+        info.insert(
+            MessageField::Port.into(),
+            addr.port().to_string().into_bytes(),
+        );
+        let sessions: Sessions = Arc::default();
+        let peer = Peer::default();
+        let mut targets = Vec::new();
+        for mark in ["m1", "m2"] {
+            let mut rinfo = BlvMap::new();
+            handle_connect(&info, mark, &sessions, &config(0), None, &peer, &mut rinfo).await;
+            targets.push(listener.accept().unwrap());
+        }
+
+        assert!(
+            evict_idle_sessions(&sessions, Duration::from_secs(60))
+                .await
+                .is_empty()
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let mut rinfo = BlvMap::new();
+        info.insert(MessageField::Data.into(), b"ping".to_vec());
+        handle_forward(&info, "m2", &sessions, &mut rinfo).await;
+
+        let evicted = evict_idle_sessions(&sessions, Duration::from_millis(50)).await;
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].0, "m1");
+        assert!(evicted[0].1.is_closed().await);
+        assert!(sessions.lock().await.contains_key("m2"));
+    }
 }
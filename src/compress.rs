@@ -0,0 +1,121 @@
+use std::io::{Read, Write};
+
+use flate2::Compression;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+
+use crate::codec::{BlvMap, MessageField, read_be_u32};
+use crate::errors::NeoError;
+
+/// 发送方可以解压deflate压缩的Data字段
+pub const FLAG_DEFLATE_ACCEPT: u32 = 0x01;
+/// 本消息的Data字段经过deflate压缩
+pub const FLAG_DEFLATE: u32 = 0x02;
+
+/// 小于该长度的数据不压缩
+const MIN_COMPRESS_LEN: usize = 128;
+/// 解压后允许的最大长度，防止压缩炸弹
+const MAX_INFLATE_LEN: usize = 16 * 1024 * 1024;
+
+/// deflate压缩，压缩收益不足（节省不到1/8）时返回None
+pub fn deflate(data: &[u8]) -> Option<Vec<u8>> {
+    if data.len() < MIN_COMPRESS_LEN {
+        return None;
+    }
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+    encoder.write_all(data).ok()?;
+    let out = encoder.finish().ok()?;
+    if out.len() + data.len() / 8 > data.len() {
+        return None;
+    }
+    Some(out)
+}
+
+/// deflate解压
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>, NeoError> {
+    let mut out = Vec::new();
+    DeflateDecoder::new(data)
+        .take(MAX_INFLATE_LEN as u64 + 1)
+        .read_to_end(&mut out)?;
+    if out.len() > MAX_INFLATE_LEN {
+        return Err(NeoError::Other("Inflated data too large".to_string()));
+    }
+    Ok(out)
+}
+
+/// 读取标志位字段，缺失时为0
+pub fn read_flags(info: &BlvMap) -> u32 {
+    read_be_u32(info, MessageField::Flags)
+}
+
+/// 解压请求中带FLAG_DEFLATE标记的Data字段，返回请求的标志位
+pub fn inflate_request(info: &mut BlvMap) -> Result<u32, NeoError> {
+    let flags = read_flags(info);
+    if flags & FLAG_DEFLATE != 0
+        && let Some(data) = info.get_mut(&MessageField::Data.into())
+    {
+        *data = inflate(data)?;
+    }
+    Ok(flags)
+}
+
+/// 对声明支持压缩的客户端压缩响应的Data字段
+///
+/// 只有请求携带了标志字段的客户端才会在响应中收到该字段，旧客户端不受影响。
+pub fn deflate_response(request_flags: u32, rinfo: &mut BlvMap) {
+    if request_flags == 0 {
+        return;
+    }
+    let mut flags = FLAG_DEFLATE_ACCEPT;
+    if request_flags & FLAG_DEFLATE_ACCEPT != 0
+        && let Some(data) = rinfo.get_mut(&MessageField::Data.into())
+        && let Some(compressed) = deflate(data)
+    {
+        *data = compressed;
+        flags |= FLAG_DEFLATE;
+    }
+    rinfo.insert(MessageField::Flags.into(), flags.to_be_bytes().to_vec());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 测试压缩往返
+    #[test]
+    fn test_deflate_roundtrip() {
+        let data = b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n".repeat(20);
+        let compressed = deflate(&data).expect("Should compress");
+        assert!(compressed.len() < data.len());
+        assert_eq!(inflate(&compressed).expect("Inflate failed"), data);
+    }
+
+    // 测试过小或不可压缩的数据跳过压缩
+    #[test]
+    fn test_skip_tiny_and_incompressible() {
+        assert!(deflate(b"tiny").is_none());
+
+        let mut random = vec![0u8; 4096];
+        rand::RngCore::fill_bytes(&mut rand::rng(), &mut random);
+        assert!(deflate(&random).is_none());
+    }
+
+    // 测试只有声明支持的客户端才收到压缩数据
+    #[test]
+    fn test_negotiated_response() {
+        let data = b"a".repeat(1024);
+
+        let mut rinfo = BlvMap::new();
+        rinfo.insert(MessageField::Data.into(), data.clone());
+        deflate_response(0, &mut rinfo);
+        assert_eq!(rinfo.get(&MessageField::Data.into()), Some(&data));
+        assert!(!rinfo.contains_key(&MessageField::Flags.into()));
+
+        deflate_response(FLAG_DEFLATE_ACCEPT, &mut rinfo);
+        assert_eq!(read_flags(&rinfo), FLAG_DEFLATE_ACCEPT | FLAG_DEFLATE);
+
+        let mut info = rinfo.clone();
+        inflate_request(&mut info).expect("Inflate failed");
+        assert_eq!(info.get(&MessageField::Data.into()), Some(&data));
+    }
+}
@@ -0,0 +1,1330 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::acl::Acl;
+use crate::audit::AuditOptions;
+use crate::cli::Cli;
+use crate::codec::{BlvProfile, Codec};
+use crate::errors::NeoError;
+use crate::log::{Format, Level};
+use crate::session::SessionOptions;
+use crate::tenant::TenantOptions;
+use crate::window::Window;
+
+const DEFAULT_CONNECT_TIMEOUT_MS: u64 = 3000;
+const DEFAULT_SHUTDOWN_GRACE_MS: u64 = 10000;
+const DEFAULT_AUTH_MAX_SKEW_SECS: u64 = 300;
+const DEFAULT_AUTH_REPLAY_CACHE: usize = 65536;
+const DEFAULT_COMPANION_SOCKET_MODE: u32 = 0o600;
+
+// 服务端配置
+//
+// 优先级：默认值 < 配置文件 < 环境变量 < 命令行参数
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub listen: Vec<SocketAddr>,
+    /// 指标监听地址，未配置时不启用
+    pub metrics_listen: Option<SocketAddr>,
+    /// 本地控制套接字路径，未配置时不启用
+    pub control_socket: Option<PathBuf>,
+    /// 接收inetd和CGI模式进程转交请求的Unix套接字路径，未配置时不启用
+    pub companion_socket: Option<PathBuf>,
+    /// 伴随套接字的权限，CGI程序以Web服务器的用户运行时可与其共用组并设为0660
+    pub companion_socket_mode: u32,
+    /// 除本进程的用户和root外，允许通过伴随套接字转交请求的用户（按连接的对端凭据）
+    pub companion_uids: Vec<u32>,
+    /// 允许通过伴随套接字转交请求的组
+    pub companion_gids: Vec<u32>,
+    /// 由systemd按套接字激活启动（设置了`LISTEN_FDS`），传入的监听套接字都用于接收请求
+    pub socket_activated: bool,
+    pub key: Option<String>,
+    /// 轮换前的旧密码，到期前仍被接受
+    pub previous_keys: Vec<PreviousKey>,
+    /// 自定义Base64编码表，未配置时使用默认编码表；配置了租户时按租户配置
+    pub alphabet: Option<String>,
+    /// 轮换前的旧编码表，到期前仍被接受
+    pub previous_alphabets: Vec<PreviousAlphabet>,
+    /// 管理命令（SHUTDOWN）的认证密钥，未配置时不接受管理命令
+    pub admin_key: Option<String>,
+    pub connect_timeout_ms: u64,
+    /// 退出时等待正在处理的请求完成的最长时间
+    pub shutdown_grace_ms: u64,
+    /// 最大并发会话数，0表示不限制
+    pub max_sessions: usize,
+    /// 会话空闲超过该时间后被回收，0表示不回收
+    pub session_idle_secs: u64,
+    pub session: SessionOptions,
+    pub acl: Acl,
+    pub audit: AuditOptions,
+    pub tls: TlsOptions,
+    pub auth: AuthOptions,
+    /// 租户，配置后请求必须能被其中一个租户识别
+    pub tenants: Vec<TenantOptions>,
+    /// 活动时间窗口，窗口外拒绝所有请求，过了结束时间后进程退出
+    pub window: Window,
+    pub log_level: Level,
+    pub log_format: Format,
+    pub blv_offset: Option<i32>,
+    pub field_ids: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen: Vec::new(),
+            metrics_listen: None,
+            control_socket: None,
+            companion_socket: None,
+            companion_socket_mode: DEFAULT_COMPANION_SOCKET_MODE,
+            companion_uids: Vec::new(),
+            companion_gids: Vec::new(),
+            socket_activated: false,
+            key: None,
+            previous_keys: Vec::new(),
+            alphabet: None,
+            previous_alphabets: Vec::new(),
+            admin_key: None,
+            connect_timeout_ms: DEFAULT_CONNECT_TIMEOUT_MS,
+            shutdown_grace_ms: DEFAULT_SHUTDOWN_GRACE_MS,
+            max_sessions: 0,
+            session_idle_secs: 0,
+            session: SessionOptions::default(),
+            acl: Acl::default(),
+            audit: AuditOptions::default(),
+            tls: TlsOptions::default(),
+            auth: AuthOptions::default(),
+            tenants: Vec::new(),
+            window: Window::default(),
+            log_level: Level::Warn,
+            log_format: Format::Text,
+            blv_offset: None,
+            field_ids: None,
+        }
+    }
+}
+
+// HTTPS监听参数
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TlsOptions {
+    /// HTTPS监听地址，为空时不启用
+    pub listen: Vec<SocketAddr>,
+    /// PEM格式的证书链
+    pub cert: Option<PathBuf>,
+    /// PEM格式的私钥
+    pub key: Option<PathBuf>,
+    /// 未配置证书时启动时生成自签名证书
+    pub self_signed: bool,
+    /// 签发客户端证书的CA，配置后只接受出示有效客户端证书的连接
+    pub client_ca: Option<PathBuf>,
+}
+
+// 轮换前的密码（AEAD密码或认证密钥）
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreviousKey {
+    pub key: String,
+    /// 到期时间，之后使用该密码的请求被拒绝
+    pub not_after: SystemTime,
+}
+
+// 轮换前的Base64编码表
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreviousAlphabet {
+    pub alphabet: String,
+    /// 到期时间，之后使用该编码表的请求被拒绝
+    pub not_after: SystemTime,
+}
+
+// 请求认证参数
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthOptions {
+    /// HMAC密钥，配置后所有请求都必须携带有效的认证字段
+    pub key: Option<String>,
+    /// 轮换前的旧密钥，到期前仍被接受
+    pub previous_keys: Vec<PreviousKey>,
+    /// 请求时间戳与服务端时间允许的最大偏差
+    pub max_skew_secs: u64,
+    /// 重放缓存最多记录的nonce个数
+    pub replay_cache: usize,
+}
+
+impl Default for AuthOptions {
+    fn default() -> Self {
+        AuthOptions {
+            key: None,
+            previous_keys: Vec::new(),
+            max_skew_secs: DEFAULT_AUTH_MAX_SKEW_SECS,
+            replay_cache: DEFAULT_AUTH_REPLAY_CACHE,
+        }
+    }
+}
+
+impl Config {
+    /// 按优先级合并配置文件、环境变量和命令行参数
+    pub fn load(cli: &Cli) -> Result<Self, NeoError> {
+        let mut config = Config::default();
+        if let Some(path) = &cli.config {
+            config.apply_file(path)?;
+        }
+        config.apply_env(|name| std::env::var(name).ok())?;
+        config.apply_cli(cli);
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// 应用配置文件
+    #[cfg(feature = "config-file")]
+    pub fn apply_file(&mut self, path: &Path) -> Result<(), NeoError> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| NeoError::Other(format!("Failed to read {}: {}", path.display(), e)))?;
+        file::apply(self, &text).map_err(|e| match e {
+            NeoError::Other(msg) => NeoError::Other(format!("{}: {}", path.display(), msg)),
+            e => e,
+        })
+    }
+
+    #[cfg(not(feature = "config-file"))]
+    pub fn apply_file(&mut self, _path: &Path) -> Result<(), NeoError> {
+        Err(NeoError::Other(
+            "Configuration files require the config-file feature".to_string(),
+        ))
+    }
+
+    /// 应用 NEORUST_* 环境变量
+    pub fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), NeoError> {
+        if let Some(key) = var("NEORUST_KEY") {
+            self.key = Some(key);
+        }
+        if let Some(offset) = var("NEORUST_BLV_OFFSET") {
+            self.blv_offset = Some(parse_env("NEORUST_BLV_OFFSET", &offset)?);
+        }
+        if let Some(ids) = var("NEORUST_FIELD_IDS") {
+            self.field_ids = Some(ids);
+        }
+        if let Some(ms) = var("NEORUST_CONNECT_TIMEOUT") {
+            self.connect_timeout_ms = parse_env("NEORUST_CONNECT_TIMEOUT", &ms)?;
+        }
+        if let Some(n) = var("NEORUST_MAX_SESSIONS") {
+            self.max_sessions = parse_env("NEORUST_MAX_SESSIONS", &n)?;
+        }
+        if let Some(level) = var("NEORUST_LOG_LEVEL") {
+            self.log_level = level.parse()?;
+        }
+        if let Some(addr) = var("NEORUST_METRICS_LISTEN") {
+            self.metrics_listen = Some(crate::cli::parse_listen(&addr)?);
+        }
+        if let Some(path) = var("NEORUST_CONTROL_SOCKET") {
+            self.control_socket = Some(path.into());
+        }
+        if let Some(path) = var("NEORUST_COMPANION_SOCKET") {
+            self.companion_socket = Some(path.into());
+        }
+        // 升级启动的新进程继承该变量，接管的监听套接字同样来自套接字激活
+        self.socket_activated = var("LISTEN_FDS").is_some();
+        if let Some(path) = var("NEORUST_AUDIT_LOG") {
+            self.audit.path = Some(path.into());
+        }
+        if let Some(format) = var("NEORUST_LOG_FORMAT") {
+            self.log_format = format.parse()?;
+        }
+        if let Some(path) = var("NEORUST_TLS_CERT") {
+            self.tls.cert = Some(path.into());
+        }
+        if let Some(path) = var("NEORUST_TLS_KEY") {
+            self.tls.key = Some(path.into());
+        }
+        if let Some(path) = var("NEORUST_TLS_CLIENT_CA") {
+            self.tls.client_ca = Some(path.into());
+        }
+        if let Some(key) = var("NEORUST_AUTH_KEY") {
+            self.auth.key = Some(key);
+        }
+        if let Some(key) = var("NEORUST_ADMIN_KEY") {
+            self.admin_key = Some(key);
+        }
+        Ok(())
+    }
+
+    /// 应用命令行参数
+    pub fn apply_cli(&mut self, cli: &Cli) {
+        if !cli.listen.is_empty() {
+            self.listen = cli.listen.clone();
+        }
+        if let Some(key) = &cli.key {
+            self.key = Some(key.clone());
+        }
+        if let Some(ms) = cli.connect_timeout_ms {
+            self.connect_timeout_ms = ms;
+        }
+        if let Some(n) = cli.max_sessions {
+            self.max_sessions = n;
+        }
+        if let Some(level) = cli.log_level {
+            self.log_level = level;
+        }
+        if let Some(addr) = cli.metrics_listen {
+            self.metrics_listen = Some(addr);
+        }
+        if let Some(path) = &cli.control_socket {
+            self.control_socket = Some(path.clone());
+        }
+        if let Some(path) = &cli.companion_socket {
+            self.companion_socket = Some(path.clone());
+        }
+        if let Some(mode) = cli.companion_socket_mode {
+            self.companion_socket_mode = mode;
+        }
+        if let Some(path) = &cli.audit_log {
+            self.audit.path = Some(path.clone());
+        }
+        if let Some(format) = cli.log_format {
+            self.log_format = format;
+        }
+        if !cli.tls_listen.is_empty() {
+            self.tls.listen = cli.tls_listen.clone();
+        }
+        if let Some(path) = &cli.tls_cert {
+            self.tls.cert = Some(path.clone());
+        }
+        if let Some(path) = &cli.tls_key {
+            self.tls.key = Some(path.clone());
+        }
+        if cli.tls_self_signed {
+            self.tls.self_signed = true;
+        }
+        if let Some(path) = &cli.tls_client_ca {
+            self.tls.client_ca = Some(path.clone());
+        }
+        if let Some(key) = &cli.auth_key {
+            self.auth.key = Some(key.clone());
+        }
+        if let Some(key) = &cli.admin_key {
+            self.admin_key = Some(key.clone());
+        }
+        if let Some(time) = cli.not_before {
+            self.window.not_before = Some(time);
+        }
+        if let Some(time) = cli.not_after {
+            self.window.not_after = Some(time);
+        }
+        if let Some(hours) = cli.daily_hours {
+            self.window.daily_hours = Some(hours);
+        }
+        if let Some(offset) = cli.blv_offset {
+            self.blv_offset = Some(offset);
+        }
+        if let Some(ids) = &cli.field_ids {
+            self.field_ids = Some(ids.clone());
+        }
+    }
+
+    /// 检查配置是否完整有效
+    pub fn validate(&self) -> Result<(), NeoError> {
+        if self.listen.is_empty()
+            && self.tls.listen.is_empty()
+            && self.companion_socket.is_none()
+            && !self.socket_activated
+        {
+            return Err(NeoError::Other("No listen address configured".to_string()));
+        }
+        self.validate_tls()?;
+        if !self.previous_keys.is_empty() && self.key.is_none() {
+            return Err(NeoError::Other(
+                "Previous keys require a current key".to_string(),
+            ));
+        }
+        if !self.auth.previous_keys.is_empty() && self.auth.key.is_none() {
+            return Err(NeoError::Other(
+                "Previous auth keys require a current auth key".to_string(),
+            ));
+        }
+        if self.auth.key.is_some() && !cfg!(feature = "auth") {
+            return Err(NeoError::Other(
+                "Request authentication requires the auth feature".to_string(),
+            ));
+        }
+        for alphabet in self
+            .alphabet
+            .iter()
+            .chain(self.previous_alphabets.iter().map(|p| &p.alphabet))
+        {
+            Codec::new().with_alphabet(alphabet)?;
+        }
+        self.validate_admin_key()?;
+        if self.auth.replay_cache == 0 {
+            return Err(NeoError::Other(
+                "Replay cache size must be greater than 0".to_string(),
+            ));
+        }
+        if self.connect_timeout_ms == 0 {
+            return Err(NeoError::Other(
+                "Connect timeout must be greater than 0".to_string(),
+            ));
+        }
+        if self.session.channel_capacity == 0 || self.session.buffer_size == 0 {
+            return Err(NeoError::Other(
+                "Buffer sizes must be greater than 0".to_string(),
+            ));
+        }
+        self.blv_profile()?;
+        if !self.tenants.is_empty() && self.auth.key.is_some() {
+            return Err(NeoError::Other(
+                "With tenants configured, set auth_key per tenant instead of [auth] key"
+                    .to_string(),
+            ));
+        }
+        if !self.tenants.is_empty()
+            && (self.alphabet.is_some() || !self.previous_alphabets.is_empty())
+        {
+            return Err(NeoError::Other(
+                "With tenants configured, set alphabet per tenant instead of the top-level alphabet"
+                    .to_string(),
+            ));
+        }
+        crate::tenant::validate(&self.tenants)?;
+        self.window.validate()?;
+        Ok(())
+    }
+
+    // 管理密钥必须独立于隧道使用的所有密钥
+    fn validate_admin_key(&self) -> Result<(), NeoError> {
+        let Some(admin_key) = &self.admin_key else {
+            return Ok(());
+        };
+        if !cfg!(feature = "auth") {
+            return Err(NeoError::Other(
+                "Admin commands require the auth feature".to_string(),
+            ));
+        }
+        let reused = [&self.key, &self.auth.key]
+            .into_iter()
+            .flatten()
+            .chain(self.previous_keys.iter().map(|p| &p.key))
+            .chain(self.auth.previous_keys.iter().map(|p| &p.key))
+            .chain(self.tenants.iter().flat_map(|t| t.auth_keys()))
+            .any(|key| key == admin_key);
+        if admin_key.is_empty() || reused {
+            return Err(NeoError::Other(
+                "Admin key must be non-empty and differ from the tunnel keys".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn validate_tls(&self) -> Result<(), NeoError> {
+        let tls = &self.tls;
+        if tls.cert.is_some() != tls.key.is_some() {
+            return Err(NeoError::Other(
+                "TLS certificate and key must be configured together".to_string(),
+            ));
+        }
+        // 要求客户端证书时，套接字激活传入的监听套接字也使用HTTPS
+        let activated_https = tls.client_ca.is_some() && self.socket_activated;
+        if tls.client_ca.is_some() {
+            // 明文监听和伴随套接字不经过客户端证书校验，会绕过双向TLS
+            if tls.listen.is_empty() && !activated_https {
+                return Err(NeoError::Other(
+                    "client_ca requires HTTPS listeners".to_string(),
+                ));
+            }
+            if !self.listen.is_empty() || self.companion_socket.is_some() {
+                return Err(NeoError::Other(
+                    "With client_ca configured, plain HTTP listeners and the companion socket are not allowed"
+                        .to_string(),
+                ));
+            }
+        }
+        if tls.listen.is_empty() && !activated_https {
+            return Ok(());
+        }
+        if !cfg!(feature = "tls") {
+            return Err(NeoError::Other(
+                "HTTPS listeners require the tls feature".to_string(),
+            ));
+        }
+        if tls.cert.is_none() && !tls.self_signed {
+            return Err(NeoError::Other(
+                "HTTPS listeners need a certificate and key, or self_signed = true".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// 根据配置构建BLV线上格式
+    pub fn blv_profile(&self) -> Result<BlvProfile, NeoError> {
+        let mut profile = BlvProfile::default();
+        if let Some(offset) = self.blv_offset {
+            profile = profile.with_offset(offset);
+        }
+        if let Some(ids) = &self.field_ids {
+            profile = profile.with_field_ids(ids)?;
+        }
+        Ok(profile)
+    }
+}
+
+// 以配置文件格式输出生效的配置，密钥不输出原文
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let listen = self.listen.iter().map(|a| a.to_string()).collect();
+        writeln!(f, "listen = [{}]", quoted(listen))?;
+        if let Some(addr) = self.metrics_listen {
+            writeln!(f, "metrics_listen = \"{}\"", addr)?;
+        }
+        if let Some(path) = &self.control_socket {
+            writeln!(f, "control_socket = \"{}\"", path.display())?;
+        }
+        if let Some(path) = &self.companion_socket {
+            writeln!(f, "companion_socket = \"{}\"", path.display())?;
+            writeln!(
+                f,
+                "companion_socket_mode = \"{:04o}\"",
+                self.companion_socket_mode
+            )?;
+            if !self.companion_uids.is_empty() {
+                writeln!(f, "companion_uids = {:?}", self.companion_uids)?;
+            }
+            if !self.companion_gids.is_empty() {
+                writeln!(f, "companion_gids = {:?}", self.companion_gids)?;
+            }
+        }
+        if self.key.is_some() {
+            writeln!(f, "key = \"<redacted>\"")?;
+        }
+        if self.admin_key.is_some() {
+            writeln!(f, "admin_key = \"<redacted>\"")?;
+        }
+        if let Some(alphabet) = &self.alphabet {
+            writeln!(f, "alphabet = \"{}\"", alphabet)?;
+        }
+        writeln!(f, "log_level = \"{}\"", self.log_level)?;
+        writeln!(f, "log_format = \"{}\"", self.log_format)?;
+        if let Some(offset) = self.blv_offset {
+            writeln!(f, "blv_offset = {}", offset)?;
+        }
+        if let Some(ids) = &self.field_ids {
+            writeln!(f, "field_ids = \"{}\"", ids)?;
+        }
+        for previous in &self.previous_keys {
+            writeln!(f, "\n[[previous_keys]]")?;
+            writeln!(f, "key = \"<redacted>\"")?;
+            writeln!(f, "not_after = \"{}\"", not_after(previous.not_after))?;
+        }
+        for previous in &self.previous_alphabets {
+            writeln!(f, "\n[[previous_alphabets]]")?;
+            writeln!(f, "alphabet = \"{}\"", previous.alphabet)?;
+            writeln!(f, "not_after = \"{}\"", not_after(previous.not_after))?;
+        }
+
+        writeln!(f, "\n[timeouts]")?;
+        writeln!(f, "connect_ms = {}", self.connect_timeout_ms)?;
+        writeln!(f, "read_ms = {}", self.session.read_timeout_ms)?;
+        writeln!(f, "shutdown_grace_ms = {}", self.shutdown_grace_ms)?;
+
+        writeln!(f, "\n[buffers]")?;
+        writeln!(f, "channel_capacity = {}", self.session.channel_capacity)?;
+        writeln!(f, "buffer_size = {}", self.session.buffer_size)?;
+
+        writeln!(f, "\n[limits]")?;
+        writeln!(f, "max_sessions = {}", self.max_sessions)?;
+        writeln!(f, "session_idle_secs = {}", self.session_idle_secs)?;
+
+        writeln!(f, "\n[acl]")?;
+        write_acl(f, &self.acl)?;
+        writeln!(
+            f,
+            "trusted_proxies = [{}]",
+            quoted(cidrs(&self.acl.trusted_proxies))
+        )?;
+
+        for tenant in &self.tenants {
+            writeln!(f, "\n[[tenants]]")?;
+            writeln!(f, "name = \"{}\"", tenant.name)?;
+            if let Some(alphabet) = &tenant.alphabet {
+                writeln!(f, "alphabet = \"{}\"", alphabet)?;
+            }
+            if tenant.auth_key.is_some() {
+                writeln!(f, "auth_key = \"<redacted>\"")?;
+            }
+            let alphabets: Vec<String> = tenant
+                .previous_alphabets
+                .iter()
+                .map(|p| {
+                    format!(
+                        "{{ alphabet = \"{}\", not_after = \"{}\" }}",
+                        p.alphabet,
+                        not_after(p.not_after)
+                    )
+                })
+                .collect();
+            writeln!(f, "previous_alphabets = [{}]", alphabets.join(", "))?;
+            let keys: Vec<String> = tenant
+                .previous_auth_keys
+                .iter()
+                .map(|p| {
+                    format!(
+                        "{{ key = \"<redacted>\", not_after = \"{}\" }}",
+                        not_after(p.not_after)
+                    )
+                })
+                .collect();
+            writeln!(f, "previous_auth_keys = [{}]", keys.join(", "))?;
+            writeln!(f, "max_sessions = {}", tenant.max_sessions)?;
+            writeln!(f, "quota_bytes = {}", tenant.quota_bytes)?;
+            writeln!(f, "[tenants.acl]")?;
+            write_acl(f, &tenant.acl)?;
+        }
+
+        writeln!(f, "\n[window]")?;
+        for (name, time) in [
+            ("not_before", self.window.not_before),
+            ("not_after", self.window.not_after),
+        ] {
+            if let Some(time) = time {
+                let time = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+                writeln!(f, "{} = \"{}\"", name, crate::log::timestamp(time))?;
+            }
+        }
+        if let Some(hours) = self.window.daily_hours {
+            writeln!(f, "daily_hours = \"{}\"", hours)?;
+        }
+
+        writeln!(f, "\n[audit]")?;
+        if let Some(path) = &self.audit.path {
+            writeln!(f, "path = \"{}\"", path.display())?;
+        }
+        writeln!(f, "max_size = {}", self.audit.max_size)?;
+        writeln!(f, "keep = {}", self.audit.keep)?;
+
+        let tls_listen = self.tls.listen.iter().map(|a| a.to_string()).collect();
+        writeln!(f, "\n[tls]")?;
+        writeln!(f, "listen = [{}]", quoted(tls_listen))?;
+        if let Some(path) = &self.tls.cert {
+            writeln!(f, "cert = \"{}\"", path.display())?;
+        }
+        if let Some(path) = &self.tls.key {
+            writeln!(f, "key = \"{}\"", path.display())?;
+        }
+        if let Some(path) = &self.tls.client_ca {
+            writeln!(f, "client_ca = \"{}\"", path.display())?;
+        }
+        writeln!(f, "self_signed = {}", self.tls.self_signed)?;
+
+        writeln!(f, "\n[auth]")?;
+        if self.auth.key.is_some() {
+            writeln!(f, "key = \"<redacted>\"")?;
+        }
+        writeln!(f, "max_skew_secs = {}", self.auth.max_skew_secs)?;
+        write!(f, "replay_cache = {}", self.auth.replay_cache)?;
+        for previous in &self.auth.previous_keys {
+            writeln!(f, "\n\n[[auth.previous_keys]]")?;
+            writeln!(f, "key = \"<redacted>\"")?;
+            write!(f, "not_after = \"{}\"", not_after(previous.not_after))?;
+        }
+        Ok(())
+    }
+}
+
+fn not_after(time: SystemTime) -> String {
+    crate::log::timestamp(time.duration_since(UNIX_EPOCH).unwrap_or_default())
+}
+
+fn quoted(items: Vec<String>) -> String {
+    items
+        .iter()
+        .map(|s| format!("\"{}\"", s))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn ports(ports: &[u16]) -> String {
+    ports
+        .iter()
+        .map(u16::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn cidrs(cidrs: &[crate::acl::Cidr]) -> Vec<String> {
+    cidrs.iter().map(|c| c.to_string()).collect()
+}
+
+// 输出全局ACL与租户ACL共有的设置
+fn write_acl(f: &mut fmt::Formatter<'_>, acl: &Acl) -> fmt::Result {
+    writeln!(f, "allow = [{}]", quoted(cidrs(&acl.allow)))?;
+    writeln!(f, "deny = [{}]", quoted(cidrs(&acl.deny)))?;
+    writeln!(f, "allow_ports = [{}]", ports(&acl.allow_ports))?;
+    writeln!(f, "deny_ports = [{}]", ports(&acl.deny_ports))?;
+    writeln!(
+        f,
+        "allow_identities = [{}]",
+        quoted(acl.allow_identities.clone())
+    )?;
+    writeln!(f, "allow_clients = [{}]", quoted(cidrs(&acl.allow_clients)))
+}
+
+fn parse_env<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, NeoError> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| NeoError::Other(format!("Invalid value for {}: {}", name, value)))
+}
+
+// TOML配置文件
+#[cfg(feature = "config-file")]
+mod file {
+    use serde::Deserialize;
+
+    use super::Config;
+    use crate::acl::parse_cidrs;
+    use crate::cli::{parse_listen, parse_mode};
+    use std::time::UNIX_EPOCH;
+
+    use crate::errors::NeoError;
+    use crate::log::parse_timestamp;
+    use crate::tenant::TenantOptions;
+
+    #[derive(Deserialize, Default)]
+    #[serde(default, deny_unknown_fields)]
+    struct File {
+        listen: Option<Vec<String>>,
+        metrics_listen: Option<String>,
+        control_socket: Option<String>,
+        companion_socket: Option<String>,
+        companion_socket_mode: Option<String>,
+        companion_uids: Option<Vec<u32>>,
+        companion_gids: Option<Vec<u32>>,
+        key: Option<String>,
+        admin_key: Option<String>,
+        log_level: Option<String>,
+        log_format: Option<String>,
+        blv_offset: Option<i32>,
+        field_ids: Option<String>,
+        timeouts: Timeouts,
+        buffers: Buffers,
+        limits: Limits,
+        acl: Acl,
+        audit: Audit,
+        tls: Tls,
+        auth: Auth,
+        tenants: Option<Vec<Tenant>>,
+        previous_keys: Option<Vec<PreviousKey>>,
+        alphabet: Option<String>,
+        previous_alphabets: Option<Vec<PreviousAlphabet>>,
+        window: Window,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct PreviousKey {
+        key: String,
+        not_after: String,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct PreviousAlphabet {
+        alphabet: String,
+        not_after: String,
+    }
+
+    #[derive(Deserialize, Default)]
+    #[serde(default, deny_unknown_fields)]
+    struct Timeouts {
+        connect_ms: Option<u64>,
+        read_ms: Option<u64>,
+        shutdown_grace_ms: Option<u64>,
+    }
+
+    #[derive(Deserialize, Default)]
+    #[serde(default, deny_unknown_fields)]
+    struct Buffers {
+        channel_capacity: Option<usize>,
+        buffer_size: Option<usize>,
+    }
+
+    #[derive(Deserialize, Default)]
+    #[serde(default, deny_unknown_fields)]
+    struct Limits {
+        max_sessions: Option<usize>,
+        session_idle_secs: Option<u64>,
+    }
+
+    #[derive(Deserialize, Default)]
+    #[serde(default, deny_unknown_fields)]
+    struct Acl {
+        allow: Option<Vec<String>>,
+        deny: Option<Vec<String>>,
+        allow_ports: Option<Vec<u16>>,
+        deny_ports: Option<Vec<u16>>,
+        allow_identities: Option<Vec<String>>,
+        allow_clients: Option<Vec<String>>,
+        trusted_proxies: Option<Vec<String>>,
+    }
+
+    #[derive(Deserialize, Default)]
+    #[serde(default, deny_unknown_fields)]
+    struct Tenant {
+        name: String,
+        alphabet: Option<String>,
+        previous_alphabets: Vec<PreviousAlphabet>,
+        auth_key: Option<String>,
+        previous_auth_keys: Vec<PreviousKey>,
+        max_sessions: usize,
+        quota_bytes: u64,
+        acl: Acl,
+    }
+
+    #[derive(Deserialize, Default)]
+    #[serde(default, deny_unknown_fields)]
+    struct Window {
+        not_before: Option<String>,
+        not_after: Option<String>,
+        daily_hours: Option<String>,
+    }
+
+    #[derive(Deserialize, Default)]
+    #[serde(default, deny_unknown_fields)]
+    struct Audit {
+        path: Option<String>,
+        max_size: Option<u64>,
+        keep: Option<usize>,
+    }
+
+    #[derive(Deserialize, Default)]
+    #[serde(default, deny_unknown_fields)]
+    struct Tls {
+        listen: Option<Vec<String>>,
+        cert: Option<String>,
+        key: Option<String>,
+        self_signed: Option<bool>,
+        client_ca: Option<String>,
+    }
+
+    #[derive(Deserialize, Default)]
+    #[serde(default, deny_unknown_fields)]
+    struct Auth {
+        key: Option<String>,
+        previous_keys: Option<Vec<PreviousKey>>,
+        max_skew_secs: Option<u64>,
+        replay_cache: Option<usize>,
+    }
+
+    /// 解析配置文件内容并覆盖到配置上
+    pub fn apply(config: &mut Config, text: &str) -> Result<(), NeoError> {
+        let file: File = basic_toml::from_str(text).map_err(|e| NeoError::Other(e.to_string()))?;
+
+        if let Some(listen) = file.listen {
+            config.listen = listen
+                .iter()
+                .map(|s| parse_listen(s))
+                .collect::<Result<_, _>>()?;
+        }
+        if let Some(addr) = file.metrics_listen {
+            config.metrics_listen = Some(parse_listen(&addr)?);
+        }
+        if let Some(path) = file.control_socket {
+            config.control_socket = Some(path.into());
+        }
+        if let Some(path) = file.companion_socket {
+            config.companion_socket = Some(path.into());
+        }
+        if let Some(mode) = file.companion_socket_mode {
+            config.companion_socket_mode = parse_mode("companion_socket_mode", &mode)?;
+        }
+        if let Some(uids) = file.companion_uids {
+            config.companion_uids = uids;
+        }
+        if let Some(gids) = file.companion_gids {
+            config.companion_gids = gids;
+        }
+        if let Some(key) = file.key {
+            config.key = Some(key);
+        }
+        if let Some(key) = file.admin_key {
+            config.admin_key = Some(key);
+        }
+        if let Some(keys) = file.previous_keys {
+            config.previous_keys = previous_keys(keys)?;
+        }
+        if let Some(alphabet) = file.alphabet {
+            config.alphabet = Some(alphabet);
+        }
+        if let Some(alphabets) = file.previous_alphabets {
+            config.previous_alphabets = previous_alphabets(alphabets)?;
+        }
+        if let Some(level) = file.log_level {
+            config.log_level = level.parse()?;
+        }
+        if let Some(format) = file.log_format {
+            config.log_format = format.parse()?;
+        }
+        if let Some(offset) = file.blv_offset {
+            config.blv_offset = Some(offset);
+        }
+        if let Some(ids) = file.field_ids {
+            config.field_ids = Some(ids);
+        }
+
+        if let Some(ms) = file.timeouts.connect_ms {
+            config.connect_timeout_ms = ms;
+        }
+        if let Some(ms) = file.timeouts.read_ms {
+            config.session.read_timeout_ms = ms;
+        }
+        if let Some(ms) = file.timeouts.shutdown_grace_ms {
+            config.shutdown_grace_ms = ms;
+        }
+        if let Some(n) = file.buffers.channel_capacity {
+            config.session.channel_capacity = n;
+        }
+        if let Some(n) = file.buffers.buffer_size {
+            config.session.buffer_size = n;
+        }
+        if let Some(n) = file.limits.max_sessions {
+            config.max_sessions = n;
+        }
+        if let Some(secs) = file.limits.session_idle_secs {
+            config.session_idle_secs = secs;
+        }
+
+        apply_acl(&mut config.acl, file.acl)?;
+
+        if let Some(time) = file.window.not_before {
+            config.window.not_before = Some(UNIX_EPOCH + parse_timestamp(&time)?);
+        }
+        if let Some(time) = file.window.not_after {
+            config.window.not_after = Some(UNIX_EPOCH + parse_timestamp(&time)?);
+        }
+        if let Some(hours) = file.window.daily_hours {
+            config.window.daily_hours = Some(hours.parse()?);
+        }
+
+        if let Some(path) = file.audit.path {
+            config.audit.path = Some(path.into());
+        }
+        if let Some(size) = file.audit.max_size {
+            config.audit.max_size = size;
+        }
+        if let Some(keep) = file.audit.keep {
+            config.audit.keep = keep;
+        }
+
+        if let Some(listen) = file.tls.listen {
+            config.tls.listen = listen
+                .iter()
+                .map(|s| parse_listen(s))
+                .collect::<Result<_, _>>()?;
+        }
+        if let Some(path) = file.tls.cert {
+            config.tls.cert = Some(path.into());
+        }
+        if let Some(path) = file.tls.key {
+            config.tls.key = Some(path.into());
+        }
+        if let Some(self_signed) = file.tls.self_signed {
+            config.tls.self_signed = self_signed;
+        }
+        if let Some(path) = file.tls.client_ca {
+            config.tls.client_ca = Some(path.into());
+        }
+
+        if let Some(key) = file.auth.key {
+            config.auth.key = Some(key);
+        }
+        if let Some(keys) = file.auth.previous_keys {
+            config.auth.previous_keys = previous_keys(keys)?;
+        }
+        if let Some(secs) = file.auth.max_skew_secs {
+            config.auth.max_skew_secs = secs;
+        }
+        if let Some(n) = file.auth.replay_cache {
+            config.auth.replay_cache = n;
+        }
+
+        if let Some(tenants) = file.tenants {
+            config.tenants = tenants
+                .into_iter()
+                .map(|tenant| {
+                    let mut options = TenantOptions {
+                        name: tenant.name,
+                        alphabet: tenant.alphabet,
+                        previous_alphabets: previous_alphabets(tenant.previous_alphabets)?,
+                        auth_key: tenant.auth_key,
+                        previous_auth_keys: previous_keys(tenant.previous_auth_keys)?,
+                        max_sessions: tenant.max_sessions,
+                        quota_bytes: tenant.quota_bytes,
+                        ..TenantOptions::default()
+                    };
+                    apply_acl(&mut options.acl, tenant.acl)?;
+                    Ok(options)
+                })
+                .collect::<Result<_, NeoError>>()?;
+        }
+        Ok(())
+    }
+
+    fn previous_keys(keys: Vec<PreviousKey>) -> Result<Vec<super::PreviousKey>, NeoError> {
+        keys.into_iter()
+            .map(|previous| {
+                Ok(super::PreviousKey {
+                    key: previous.key,
+                    not_after: UNIX_EPOCH + parse_timestamp(&previous.not_after)?,
+                })
+            })
+            .collect()
+    }
+
+    fn previous_alphabets(
+        alphabets: Vec<PreviousAlphabet>,
+    ) -> Result<Vec<super::PreviousAlphabet>, NeoError> {
+        alphabets
+            .into_iter()
+            .map(|previous| {
+                Ok(super::PreviousAlphabet {
+                    alphabet: previous.alphabet,
+                    not_after: UNIX_EPOCH + parse_timestamp(&previous.not_after)?,
+                })
+            })
+            .collect()
+    }
+
+    fn apply_acl(acl: &mut crate::acl::Acl, file: Acl) -> Result<(), NeoError> {
+        if let Some(allow) = file.allow {
+            acl.allow = parse_cidrs(&allow)?;
+        }
+        if let Some(deny) = file.deny {
+            acl.deny = parse_cidrs(&deny)?;
+        }
+        if let Some(ports) = file.allow_ports {
+            acl.allow_ports = ports;
+        }
+        if let Some(ports) = file.deny_ports {
+            acl.deny_ports = ports;
+        }
+        if let Some(identities) = file.allow_identities {
+            acl.allow_identities = identities;
+        }
+        if let Some(clients) = file.allow_clients {
+            acl.allow_clients = parse_cidrs(&clients)?;
+        }
+        if let Some(proxies) = file.trusted_proxies {
+            acl.trusted_proxies = parse_cidrs(&proxies)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 测试命令行参数优先于环境变量
+    #[test]
+    fn test_precedence() {
+        let mut config = Config::default();
+        config
+            .apply_env(|name| match name {
+                "NEORUST_KEY" => Some("env-key".to_string()),
+                "NEORUST_MAX_SESSIONS" => Some("8".to_string()),
+                "NEORUST_CONNECT_TIMEOUT" => Some("100".to_string()),
+                _ => None,
+            })
+            .expect("Env failed");
+        config.apply_cli(&Cli {
+            listen: vec!["[::1]:8080".parse().unwrap()],
+            key: Some("cli-key".to_string()),
+            ..Cli::default()
+        });
+
+        assert_eq!(config.key.as_deref(), Some("cli-key"));
+        assert_eq!(config.max_sessions, 8);
+        assert_eq!(config.connect_timeout_ms, 100);
+        assert!(config.validate().is_ok());
+    }
+
+    // 测试无效配置被拒绝
+    #[test]
+    fn test_invalid() {
+        let mut config = Config::default();
+        assert!(config.validate().is_err());
+        // 只接收inetd和CGI模式转交的请求时可以不监听端口
+        let companion = Config {
+            companion_socket: Some("/run/neorust-companion.sock".into()),
+            ..Config::default()
+        };
+        assert!(companion.validate().is_ok());
+        // systemd传入监听套接字时可以不配置监听地址；要求客户端证书时这些套接字使用HTTPS
+        let mut activated = Config::default();
+        activated
+            .apply_env(|name| (name == "LISTEN_FDS").then(|| "1".to_string()))
+            .expect("Env failed");
+        assert!(activated.socket_activated);
+        assert!(activated.validate().is_ok());
+        activated.tls.client_ca = Some("ca.pem".into());
+        activated.tls.self_signed = true;
+        assert_eq!(activated.validate().is_ok(), cfg!(feature = "tls"));
+
+        assert!(
+            config
+                .apply_env(|name| (name == "NEORUST_MAX_SESSIONS").then(|| "many".to_string()))
+                .is_err()
+        );
+
+        config.listen = vec!["127.0.0.1:8080".parse().unwrap()];
+        config.tls.cert = Some("cert.pem".into());
+        assert!(config.validate().is_err());
+        config.tls.cert = None;
+
+        // HTTPS监听需要证书或自签名
+        config.tls.listen = vec!["127.0.0.1:8443".parse().unwrap()];
+        assert!(config.validate().is_err());
+        config.tls.self_signed = true;
+        assert_eq!(config.validate().is_ok(), cfg!(feature = "tls"));
+
+        // 双向TLS不能与明文监听或伴随套接字同时配置
+        config.tls.client_ca = Some("ca.pem".into());
+        assert!(config.validate().is_err());
+        config.listen.clear();
+        assert_eq!(config.validate().is_ok(), cfg!(feature = "tls"));
+        config.companion_socket = Some("/run/neorust-companion.sock".into());
+        assert!(config.validate().is_err());
+        config.companion_socket = None;
+        config.tls.listen.clear();
+        assert!(config.validate().is_err());
+        config.listen = vec!["127.0.0.1:8080".parse().unwrap()];
+        config.tls = TlsOptions::default();
+
+        // 旧密码需要同时配置当前密码
+        config.previous_keys = vec![PreviousKey {
+            key: "old".to_string(),
+            not_after: SystemTime::now(),
+        }];
+        assert!(config.validate().is_err());
+        config.key = Some("new".to_string());
+        assert!(config.validate().is_ok());
+
+        // 管理密钥不能与隧道密钥相同
+        config.admin_key = Some("old".to_string());
+        assert!(config.validate().is_err());
+        config.admin_key = Some("admin".to_string());
+        assert_eq!(config.validate().is_ok(), cfg!(feature = "auth"));
+        config.admin_key = None;
+
+        // 旧认证密钥需要同时配置当前认证密钥，编码表必须有效
+        config.auth.previous_keys = config.previous_keys.clone();
+        assert!(config.validate().is_err());
+        config.auth.previous_keys.clear();
+        config.alphabet = Some("abc".to_string());
+        assert!(config.validate().is_err());
+        config.alphabet = None;
+
+        // 配置了租户时编码表按租户配置
+        config.tenants = vec![TenantOptions {
+            name: "red".to_string(),
+            ..TenantOptions::default()
+        }];
+        assert!(config.validate().is_ok());
+        config.alphabet = Some(String::from_utf8(crate::EN.to_vec()).unwrap());
+        assert!(config.validate().is_err());
+        config.alphabet = None;
+        config.tenants.clear();
+
+        config.field_ids = Some("data=2".to_string());
+        assert!(config.validate().is_err());
+    }
+
+    // 测试配置文件解析，以及环境变量覆盖配置文件
+    #[cfg(feature = "config-file")]
+    #[test]
+    fn test_file() {
+        let text = r#"
+            listen = ["8080", "[::1]:9090"]
+            metrics_listen = "127.0.0.1:9100"
+            control_socket = "/run/neorust.sock"
+            companion_socket = "/run/neorust-companion.sock"
+            companion_socket_mode = "0660"
+            companion_uids = [33]
+            companion_gids = [33, 1000]
+            key = "file-key"
+            log_level = "info"
+            log_format = "json"
+
+            [[previous_keys]]
+            key = "old-key"
+            not_after = "2026-11-01T00:00:00Z"
+
+            [timeouts]
+            connect_ms = 1500
+            read_ms = 20
+            shutdown_grace_ms = 2000
+
+            [buffers]
+            buffer_size = 4096
+
+            [limits]
+            max_sessions = 32
+            session_idle_secs = 600
+
+            [acl]
+            allow = ["10.0.0.0/8"]
+            deny_ports = [25]
+            allow_identities = ["alice"]
+            allow_clients = ["203.0.113.0/24"]
+            trusted_proxies = ["127.0.0.1"]
+
+            [window]
+            not_after = "2026-12-01"
+            daily_hours = "08:00-18:00"
+
+            [audit]
+            path = "/var/log/neorust/audit.jsonl"
+            keep = 10
+
+            [tls]
+            cert = "/etc/neorust/cert.pem"
+            key = "/etc/neorust/key.pem"
+            client_ca = "/etc/neorust/ca.pem"
+
+            [auth]
+            max_skew_secs = 60
+
+            [[tenants]]
+            name = "red"
+            alphabet = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/"
+            previous_alphabets = [{ alphabet = "dhULNVGsuAk/MxH6ibjcEfRqDWYznXBe9Pl7+SKoZ8pJaICgrQO0mF21yv345wtT", not_after = "2026-11-01" }]
+            auth_key = "red-key"
+            previous_auth_keys = [{ key = "red-old", not_after = "2026-11-01" }]
+            max_sessions = 4
+            quota_bytes = 1048576
+
+            [tenants.acl]
+            allow_ports = [443]
+        "#;
+        let mut config = Config::default();
+        file::apply(&mut config, text).expect("Parse failed");
+        config
+            .apply_env(|name| (name == "NEORUST_KEY").then(|| "env-key".to_string()))
+            .unwrap();
+
+        assert_eq!(config.listen.len(), 2);
+        assert_eq!(
+            config.metrics_listen,
+            Some("127.0.0.1:9100".parse().unwrap())
+        );
+        assert_eq!(config.key.as_deref(), Some("env-key"));
+        assert_eq!(config.previous_keys.len(), 1);
+        assert_eq!(
+            config.previous_keys[0].not_after,
+            UNIX_EPOCH + std::time::Duration::from_secs(1_793_491_200)
+        );
+        assert_eq!(config.log_level, Level::Info);
+        assert_eq!(config.log_format, Format::Json);
+        assert_eq!(config.window.not_before, None);
+        assert_eq!(
+            config.window.daily_hours.map(|h| h.to_string()).as_deref(),
+            Some("08:00-18:00")
+        );
+        assert_eq!(config.connect_timeout_ms, 1500);
+        assert_eq!(config.session.read_timeout_ms, 20);
+        assert_eq!(config.shutdown_grace_ms, 2000);
+        assert_eq!(config.session.buffer_size, 4096);
+        assert_eq!(config.session.channel_capacity, 1024);
+        assert_eq!(config.max_sessions, 32);
+        assert_eq!(config.session_idle_secs, 600);
+        assert!(config.acl.check(&"10.1.1.1:80".parse().unwrap()).is_ok());
+        assert!(config.acl.check(&"10.1.1.1:25".parse().unwrap()).is_err());
+        assert_eq!(
+            config.audit.path,
+            Some(PathBuf::from("/var/log/neorust/audit.jsonl"))
+        );
+        assert_eq!(config.audit.keep, 10);
+        assert_eq!(config.tls.key, Some(PathBuf::from("/etc/neorust/key.pem")));
+        assert_eq!(
+            config.tls.client_ca,
+            Some(PathBuf::from("/etc/neorust/ca.pem"))
+        );
+        assert_eq!(config.acl.allow_identities, vec!["alice".to_string()]);
+        assert!(
+            config
+                .acl
+                .check_client(Some("203.0.113.1".parse().unwrap()))
+                .is_ok()
+        );
+        assert_eq!(config.acl.trusted_proxies.len(), 1);
+        assert_eq!(config.auth.max_skew_secs, 60);
+        assert_eq!(config.auth.replay_cache, 65536);
+        assert_eq!(config.tenants.len(), 1);
+        assert_eq!(config.tenants[0].name, "red");
+        assert_eq!(config.tenants[0].quota_bytes, 1048576);
+        assert_eq!(config.tenants[0].acl.allow_ports, vec![443]);
+        assert_eq!(config.tenants[0].alphabets().count(), 2);
+        assert_eq!(
+            config.tenants[0].auth_keys().collect::<Vec<_>>(),
+            ["red-key", "red-old"]
+        );
+        assert_eq!(
+            config.control_socket,
+            Some(PathBuf::from("/run/neorust.sock"))
+        );
+        assert_eq!(
+            config.companion_socket,
+            Some(PathBuf::from("/run/neorust-companion.sock"))
+        );
+        assert_eq!(config.companion_socket_mode, 0o660);
+        assert_eq!(config.companion_uids, vec![33]);
+        assert_eq!(config.companion_gids, vec![33, 1000]);
+        // 双向TLS不能与明文监听和伴随套接字同时配置
+        assert!(config.validate().is_err());
+        let without_client_ca = Config {
+            tls: TlsOptions {
+                client_ca: None,
+                ..config.tls.clone()
+            },
+            ..config.clone()
+        };
+        assert_eq!(without_client_ca.validate().is_ok(), cfg!(feature = "auth"));
+
+        // 输出的生效配置可以再次解析
+        let mut reparsed = Config::default();
+        let printed = config.to_string().replace("<redacted>", "env-key");
+        file::apply(&mut reparsed, &printed).expect("Reparse failed");
+        reparsed.previous_keys[0].key = "old-key".to_string();
+        reparsed.tenants[0].auth_key = Some("red-key".to_string());
+        reparsed.tenants[0].previous_auth_keys[0].key = "red-old".to_string();
+        assert_eq!(reparsed, config);
+
+        // 编码表和认证密钥的轮换
+        let text = r#"
+            alphabet = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/"
+
+            [[previous_alphabets]]
+            alphabet = "dhULNVGsuAk/MxH6ibjcEfRqDWYznXBe9Pl7+SKoZ8pJaICgrQO0mF21yv345wtT"
+            not_after = "2026-11-01"
+
+            [auth]
+            key = "new-auth"
+
+            [[auth.previous_keys]]
+            key = "old-auth"
+            not_after = "2026-11-01"
+        "#;
+        let mut config = Config::default();
+        file::apply(&mut config, text).expect("Parse failed");
+        assert_eq!(
+            config.alphabet.as_deref(),
+            Some(std::str::from_utf8(crate::EN).unwrap())
+        );
+        assert_eq!(config.previous_alphabets.len(), 1);
+        assert_eq!(config.auth.previous_keys[0].key, "old-auth");
+        let mut reparsed = Config::default();
+        let printed = config.to_string().replace("<redacted>", "old-auth");
+        file::apply(&mut reparsed, &printed).expect("Reparse failed");
+        reparsed.auth.key = Some("new-auth".to_string());
+        assert_eq!(reparsed, config);
+
+        assert!(file::apply(&mut Config::default(), "bogus = 1").is_err());
+        assert!(file::apply(&mut Config::default(), "[acl]\nallow = [\"x\"]").is_err());
+    }
+}
@@ -0,0 +1,303 @@
+use std::fmt::Write;
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+
+use crate::audit::Event;
+use crate::cli::Cli;
+use crate::commands::{Context, close_all_sessions, session_stats};
+use crate::log::{self, Level};
+
+/// 单条命令的最大长度
+const MAX_LINE: usize = 1024;
+
+pub const HELP: &str = "\
+sessions              List sessions
+kill <MARK>           Close one session
+kill-all              Close all sessions
+limits                Show limits and session usage
+config                Show the effective configuration
+log-level [LEVEL]     Show or set the log level
+reload                Reload the configuration
+upgrade [PATH]        Start a new binary and hand over listeners and sessions";
+
+/// 创建控制套接字，仅允许当前用户访问
+pub fn bind(path: &Path) -> io::Result<UnixListener> {
+    let listener = socket(path, 0o600)?;
+    listener.set_nonblocking(true)?;
+    UnixListener::from_std(listener)
+}
+
+/// 创建指定权限的Unix套接字
+///
+/// 路径上已有的套接字文件视为上次运行残留并删除；其他类型的文件不会被覆盖。
+/// 套接字先在仅当前用户可访问的临时目录中创建并设置权限，再移动到目标路径，
+/// 设置权限前其他用户无法连接。
+pub fn socket(path: &Path, mode: u32) -> io::Result<std::os::unix::net::UnixListener> {
+    if let Ok(meta) = std::fs::symlink_metadata(path) {
+        if !meta.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "path exists and is not a socket",
+            ));
+        }
+        std::fs::remove_file(path)?;
+    }
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid socket path"))?;
+    let mut staging = name.to_os_string();
+    staging.push(format!(".{}.tmp", std::process::id()));
+    let staging = path.with_file_name(staging);
+    // 上次以相同pid运行时残留的临时目录
+    let _ = std::fs::remove_dir_all(&staging);
+    std::fs::DirBuilder::new().mode(0o700).create(&staging)?;
+    let temporary = staging.join(name);
+    let result = std::os::unix::net::UnixListener::bind(&temporary).and_then(|listener| {
+        std::fs::set_permissions(&temporary, std::fs::Permissions::from_mode(mode))?;
+        std::fs::rename(&temporary, path)?;
+        Ok(listener)
+    });
+    let _ = std::fs::remove_dir_all(&staging);
+    result
+}
+
+/// 接收控制连接：每个连接发送一行命令，收到响应后连接关闭
+///
+/// 响应第一行为`OK`或`ERR <原因>`，之后是命令输出。
+pub async fn serve(listener: UnixListener, ctx: Context, cli: Arc<Cli>) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                log::error!("Control socket accept error: {}", e);
+                continue;
+            }
+        };
+        let ctx = ctx.clone();
+        let cli = Arc::clone(&cli);
+        tokio::spawn(async move {
+            if let Err(e) = handle(stream, &ctx, &cli).await {
+                log::debug!("Control connection error: {}", e);
+            }
+        });
+    }
+}
+
+async fn handle(stream: UnixStream, ctx: &Context, cli: &Cli) -> io::Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut line = String::new();
+    BufReader::new(read.take(MAX_LINE as u64))
+        .read_line(&mut line)
+        .await?;
+
+    let response = match execute(line.trim(), ctx, cli).await {
+        Ok(body) => format!("OK\n{}", body),
+        Err(e) => format!("ERR {}\n", e),
+    };
+    write.write_all(response.as_bytes()).await?;
+    write.shutdown().await
+}
+
+/// 执行一条控制命令，返回输出内容
+pub async fn execute(line: &str, ctx: &Context, cli: &Cli) -> Result<String, String> {
+    let mut args = line.split_whitespace();
+    let command = args.next().unwrap_or_default();
+    log::info!("Control command: {}", line);
+
+    match (command, args.next()) {
+        ("sessions", None) => Ok(list_sessions(ctx).await),
+        ("kill", Some(mark)) => {
+            let session = ctx.sessions.lock().await.remove(mark);
+            match session {
+                Some(session) => {
+                    session.close().await;
+                    log::info!("Killed session {}: {}", mark, session.stats().summary());
+                    if let Some(audit) = &ctx.runtime().audit {
+                        audit.record(&Event::Evict {
+                            mark,
+                            session: &session,
+                            reason: "control",
+                        });
+                    }
+                    Ok(String::new())
+                }
+                None => Err(format!("Session not found: {}", mark)),
+            }
+        }
+        ("kill-all", None) => {
+            let sessions = close_all_sessions(&ctx.sessions).await;
+            let runtime = ctx.runtime();
+            for (mark, session) in &sessions {
+                if let Some(audit) = &runtime.audit {
+                    audit.record(&Event::Evict {
+                        mark,
+                        session,
+                        reason: "control",
+                    });
+                }
+            }
+            Ok(format!("Closed {} sessions\n", sessions.len()))
+        }
+        ("limits", None) => {
+            let config = &ctx.runtime().config;
+            let active = ctx.sessions.lock().await.len();
+            let limit = |n: usize| match n {
+                0 => "unlimited".to_string(),
+                n => n.to_string(),
+            };
+            Ok(format!(
+                "sessions: {} / {}\nsession_idle_secs: {}\nconnect_timeout_ms: {}\nread_timeout_ms: {}\nchannel_capacity: {}\nbuffer_size: {}\n",
+                active,
+                limit(config.max_sessions),
+                config.session_idle_secs,
+                config.connect_timeout_ms,
+                config.session.read_timeout_ms,
+                config.session.channel_capacity,
+                config.session.buffer_size,
+            ))
+        }
+        ("config", None) => Ok(format!("{}\n", ctx.runtime().config)),
+        ("log-level", None) => Ok(format!("{}\n", log::level())),
+        ("log-level", Some(level)) => {
+            let level: Level = level.parse().map_err(|e| format!("{}", e))?;
+            log::set_level(level);
+            Ok(String::new())
+        }
+        ("reload", None) => crate::reload(ctx, cli)
+            .map(|()| String::new())
+            .map_err(|e| e.to_string()),
+        ("upgrade", program) => crate::upgrade::start(ctx, program.map(PathBuf::from))
+            .await
+            .map(|pid| format!("New process {} is ready, handing over\n", pid))
+            .map_err(|e| e.to_string()),
+        ("help", None) => Ok(format!("{}\n", HELP)),
+        _ => Err(format!("Unknown command: {}", line)),
+    }
+}
+
+async fn list_sessions(ctx: &Context) -> String {
+    let addr = |a: Option<std::net::SocketAddr>| a.map_or("-".to_string(), |a| a.to_string());
+    let mut out =
+        String::from("MARK\tTARGET\tCLIENT\tIDENTITY\tTENANT\tAGE\tIDLE\tSENT\tRECEIVED\n");
+    for (mark, stats) in session_stats(&ctx.sessions).await {
+        let _ = writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}\t{}s\t{}s\t{}\t{}",
+            mark,
+            addr(stats.target),
+            addr(stats.client),
+            stats.identity.as_deref().unwrap_or("-"),
+            stats.tenant.as_deref().unwrap_or("-"),
+            stats.age.as_secs(),
+            stats.idle.as_secs(),
+            stats.bytes_sent,
+            stats.bytes_received
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::Runtime;
+    use crate::config::Config;
+    use crate::session::{Session, SessionOptions};
+
+    fn context() -> Context {
+        let config = Config {
+            listen: vec!["127.0.0.1:8080".parse().unwrap()],
+            ..Config::default()
+        };
+        Context::new(Runtime::new(config, None).expect("Runtime failed"))
+    }
+
+    async fn open_session(ctx: &Context, mark: &str) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let session = Session::new(stream, &SessionOptions::default())
+            .with_client(Some("192.0.2.1:4000".parse().unwrap()));
+        ctx.sessions.lock().await.insert(mark.to_string(), session);
+    }
+
+    // 测试列出和关闭会话
+    #[tokio::test]
+    async fn test_sessions_and_kill() {
+        let ctx = context();
+        let cli = Cli::default();
+        open_session(&ctx, "b").await;
+        open_session(&ctx, "a").await;
+
+        let list = execute("sessions", &ctx, &cli).await.unwrap();
+        let lines: Vec<&str> = list.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with("a\t127.0.0.1:"));
+        assert!(lines[1].contains("\t192.0.2.1:4000\t"));
+
+        assert!(execute("kill a", &ctx, &cli).await.is_ok());
+        assert!(execute("kill a", &ctx, &cli).await.is_err());
+        assert_eq!(
+            execute("kill-all", &ctx, &cli).await.unwrap(),
+            "Closed 1 sessions\n"
+        );
+        assert!(ctx.sessions.lock().await.is_empty());
+    }
+
+    // 测试日志级别切换和错误命令
+    #[tokio::test]
+    async fn test_log_level_and_errors() {
+        let ctx = context();
+        let cli = Cli::default();
+        // 断言失败时也恢复全局日志级别，避免影响其他测试
+        struct Restore(Level);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                log::set_level(self.0);
+            }
+        }
+        let restore = Restore(log::level());
+
+        assert!(execute("log-level debug", &ctx, &cli).await.is_ok());
+        assert_eq!(execute("log-level", &ctx, &cli).await.unwrap(), "debug\n");
+        assert!(execute("log-level loud", &ctx, &cli).await.is_err());
+        drop(restore);
+
+        assert!(execute("bogus", &ctx, &cli).await.is_err());
+        assert!(execute("sessions extra", &ctx, &cli).await.is_err());
+        assert!(
+            execute("limits", &ctx, &cli)
+                .await
+                .unwrap()
+                .starts_with("sessions: 0 / unlimited\n")
+        );
+    }
+
+    // 测试套接字以指定权限创建在目标路径，不留下临时目录，不覆盖其他文件
+    #[test]
+    fn test_socket_mode() {
+        let dir = std::env::temp_dir().join(format!("neorust-control-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        let path = dir.join("control.sock");
+
+        let listener = socket(&path, 0o660).expect("Bind failed");
+        let meta = std::fs::symlink_metadata(&path).unwrap();
+        assert!(meta.file_type().is_socket());
+        assert_eq!(meta.permissions().mode() & 0o777, 0o660);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::os::unix::net::UnixStream::connect(&path).expect("Connect failed");
+        drop(listener);
+
+        // 残留的套接字被替换
+        assert!(socket(&path, 0o600).is_ok());
+        let file = dir.join("file");
+        std::fs::write(&file, b"").unwrap();
+        assert!(socket(&file, 0o600).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -8,6 +8,7 @@ pub enum NeoError {
     Io(io::Error),
     SessionClosed,
     Base64Decode(base64::DecodeError),
+    AuthFailed,
     Other(String),
 }
 
@@ -17,6 +18,7 @@ impl fmt::Display for NeoError {
             NeoError::Io(e) => write!(f, "IO error: {}", e),
             NeoError::SessionClosed => write!(f, "Session is closed"),
             NeoError::Base64Decode(e) => write!(f, "Base64 decode error: {}", e),
+            NeoError::AuthFailed => write!(f, "Message authentication failed"),
             NeoError::Other(s) => write!(f, "Error: {}", s),
         }
     }
@@ -0,0 +1,488 @@
+use std::future::Future;
+use std::io;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+use crate::commands::Context;
+
+/// 请求行和请求头的最大长度
+const MAX_HEAD: usize = 64 * 1024;
+/// 请求体的最大长度
+const MAX_BODY: usize = 16 * 1024 * 1024;
+/// 连接上等待下一个请求的最长时间
+const IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+/// 读取一个完整请求的最长时间
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// 解析出的HTTP请求
+pub struct Request {
+    /// 请求目标，例如`/metrics`
+    pub url: String,
+    headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    // 请求结束后是否保持连接
+    keep_alive: bool,
+}
+
+impl Request {
+    /// 请求头的值，同名请求头有多个时以逗号连接
+    pub fn header(&self, name: &str) -> Option<String> {
+        let values: Vec<&str> = self
+            .headers
+            .iter()
+            .filter(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+            .collect();
+        (!values.is_empty()).then(|| values.join(","))
+    }
+}
+
+/// HTTP响应
+pub struct Response {
+    /// 状态码和原因短语，例如`200 OK`
+    pub status: &'static str,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// 200响应，内容为文本
+    pub fn text(body: Vec<u8>) -> Self {
+        Response {
+            status: "200 OK",
+            content_type: "text/plain; charset=UTF-8",
+            body,
+        }
+    }
+
+    /// 没有内容的响应
+    pub fn status(status: &'static str) -> Self {
+        Response {
+            status,
+            content_type: "text/plain; charset=UTF-8",
+            body: Vec::new(),
+        }
+    }
+}
+
+// 无法处理的请求
+enum Error {
+    Io(io::Error),
+    /// 直接返回给客户端的状态行，之后关闭连接
+    Status(&'static str),
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+/// 在一个连接上依次处理请求，直到客户端关闭连接或请求关闭连接
+///
+/// 每个请求从读完请求头到写出响应计入正在处理的请求。收到退出请求后，
+/// 空闲的连接立即关闭，正在处理的请求写出响应后关闭连接。
+pub async fn serve<S, H, F>(stream: S, ctx: &Context, handler: H) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    H: Fn(Request) -> F,
+    F: Future<Output = Response>,
+{
+    let mut stream = BufReader::new(stream);
+    loop {
+        let ready = tokio::select! {
+            ready = tokio::time::timeout(IDLE_TIMEOUT, stream.fill_buf()) => {
+                ready.map(|r| r.map(|buf| !buf.is_empty()))
+            }
+            _ = ctx.shutdown_requested() => return Ok(()),
+        };
+        match ready {
+            Ok(Ok(true)) => {}
+            Ok(Ok(false)) | Err(_) => return Ok(()),
+            Ok(Err(e)) => return Err(e),
+        }
+
+        let _in_flight = ctx.track_request();
+        let request = match tokio::time::timeout(READ_TIMEOUT, read_request(&mut stream)).await {
+            Ok(Ok(request)) => request,
+            Ok(Err(Error::Io(e))) => return Err(e),
+            Ok(Err(Error::Status(status))) => {
+                return respond(&mut stream, &Response::status(status), false).await;
+            }
+            Err(_) => {
+                return respond(&mut stream, &Response::status("408 Request Timeout"), false).await;
+            }
+        };
+        let keep_alive = request.keep_alive;
+        let response = handler(request).await;
+        let keep_alive = keep_alive && !ctx.shutting_down();
+        respond(&mut stream, &response, keep_alive).await?;
+        if !keep_alive {
+            return Ok(());
+        }
+    }
+}
+
+// 读取一个请求，请求体按Content-Length或分块编码读取；客户端等待100 Continue时先行回复
+async fn read_request<S>(stream: &mut BufReader<S>) -> Result<Request, Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut line = Vec::new();
+    let mut head = 0;
+    let mut request_line = None;
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        let n = (&mut *stream)
+            .take((MAX_HEAD - head) as u64)
+            .read_until(b'\n', &mut line)
+            .await?;
+        if n == 0 && head == 0 {
+            return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()));
+        }
+        head += n;
+        if !line.ends_with(b"\n") {
+            return Err(Error::Status(if head >= MAX_HEAD {
+                "431 Request Header Fields Too Large"
+            } else {
+                "400 Bad Request"
+            }));
+        }
+        let text = std::str::from_utf8(&line)
+            .map_err(|_| Error::Status("400 Bad Request"))?
+            .trim_end_matches(['\r', '\n']);
+        if request_line.is_none() {
+            request_line = Some(text.to_string());
+            continue;
+        }
+        if text.is_empty() {
+            break;
+        }
+        let (name, value) = text
+            .split_once(':')
+            .ok_or(Error::Status("400 Bad Request"))?;
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+
+    let request_line = request_line.unwrap_or_default();
+    let mut parts = request_line.split_whitespace();
+    let (Some(_method), Some(url), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(Error::Status("400 Bad Request"));
+    };
+    let http10 = match version {
+        "HTTP/1.1" => false,
+        "HTTP/1.0" => true,
+        _ => return Err(Error::Status("505 HTTP Version Not Supported")),
+    };
+    let mut request = Request {
+        url: url.to_string(),
+        headers,
+        body: Vec::new(),
+        keep_alive: false,
+    };
+
+    // 同时声明两种长度的请求可能被前置代理按另一种方式解析，直接拒绝
+    let chunked = match request.header("Transfer-Encoding") {
+        Some(_) if request.header("Content-Length").is_some() => {
+            return Err(Error::Status("400 Bad Request"));
+        }
+        Some(encoding) if encoding.eq_ignore_ascii_case("chunked") => true,
+        Some(_) => return Err(Error::Status("501 Not Implemented")),
+        None => false,
+    };
+    let length = match request.header("Content-Length") {
+        Some(value) => value
+            .parse::<usize>()
+            .map_err(|_| Error::Status("400 Bad Request"))?,
+        None => 0,
+    };
+    if length > MAX_BODY {
+        return Err(Error::Status("413 Content Too Large"));
+    }
+    let connection = request.header("Connection").unwrap_or_default();
+    let has = |option: &str| {
+        connection
+            .split(',')
+            .any(|o| o.trim().eq_ignore_ascii_case(option))
+    };
+    request.keep_alive = if http10 {
+        has("keep-alive")
+    } else {
+        !has("close")
+    };
+
+    let expect = request.header("Expect");
+    if (length > 0 || chunked) && expect.is_some_and(|e| e.eq_ignore_ascii_case("100-continue")) {
+        stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await?;
+        stream.flush().await?;
+    }
+    if chunked {
+        request.body = read_chunked(stream).await?;
+    } else {
+        request.body.resize(length, 0);
+        stream.read_exact(&mut request.body).await?;
+    }
+    Ok(request)
+}
+
+// 读取分块编码的请求体，忽略分块扩展和尾部字段
+async fn read_chunked<S>(stream: &mut BufReader<S>) -> Result<Vec<u8>, Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut body = Vec::new();
+    let mut line = Vec::new();
+    loop {
+        let size = read_line(stream, &mut line).await?;
+        let size = size.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16).map_err(|_| Error::Status("400 Bad Request"))?;
+        if size == 0 {
+            break;
+        }
+        if size > MAX_BODY - body.len() {
+            return Err(Error::Status("413 Content Too Large"));
+        }
+        let start = body.len();
+        body.resize(start + size, 0);
+        stream.read_exact(&mut body[start..]).await?;
+        if !read_line(stream, &mut line).await?.is_empty() {
+            return Err(Error::Status("400 Bad Request"));
+        }
+    }
+    while !read_line(stream, &mut line).await?.is_empty() {}
+    Ok(body)
+}
+
+// 读取一行，去掉行尾的CRLF
+async fn read_line<'a, S>(
+    stream: &mut BufReader<S>,
+    line: &'a mut Vec<u8>,
+) -> Result<&'a str, Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    line.clear();
+    (&mut *stream)
+        .take(MAX_HEAD as u64)
+        .read_until(b'\n', line)
+        .await?;
+    if !line.ends_with(b"\n") {
+        return Err(Error::Status("400 Bad Request"));
+    }
+    std::str::from_utf8(line)
+        .map(|line| line.trim_end_matches(['\r', '\n']))
+        .map_err(|_| Error::Status("400 Bad Request"))
+}
+
+async fn respond<S>(
+    stream: &mut BufReader<S>,
+    response: &Response,
+    keep_alive: bool,
+) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut out = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+    if !keep_alive {
+        out.push_str("Connection: close\r\n");
+    }
+    out.push_str("\r\n");
+    let mut out = out.into_bytes();
+    out.extend(&response.body);
+    stream.write_all(&out).await?;
+    stream.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::Runtime;
+    use crate::config::Config;
+
+    fn context() -> Context {
+        let config = Config {
+            listen: vec!["127.0.0.1:8080".parse().unwrap()],
+            ..Config::default()
+        };
+        Context::new(Runtime::new(config, None).expect("Runtime failed"))
+    }
+
+    async fn exchange(ctx: &Context, input: &[u8]) -> String {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (mut read, mut write) = tokio::io::split(client);
+        // 服务端拒绝请求后可能不再读取剩余的输入
+        let written = async {
+            if write.write_all(input).await.is_ok() {
+                let _ = write.shutdown().await;
+            }
+        };
+        let served = serve(server, ctx, |request| async move {
+            let mut body = request.header("X-Test").unwrap_or_default().into_bytes();
+            body.push(b' ');
+            body.extend(&request.body);
+            Response::text(body)
+        });
+        let mut output = Vec::new();
+        let (_, _, read) = tokio::join!(written, served, read.read_to_end(&mut output));
+        read.unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    // 测试同一连接上的多个请求、合并同名请求头，以及HTTP/1.0和Connection: close时关闭连接
+    #[tokio::test]
+    async fn test_keep_alive() {
+        let ctx = context();
+        let output = exchange(
+            &ctx,
+            b"POST /a HTTP/1.1\r\nX-Test: 1\r\nx-test: 2\r\nContent-Length: 4\r\n\r\nbodyGET /b HTTP/1.1\r\nConnection: close\r\n\r\nGET /c HTTP/1.1\r\n\r\n",
+        )
+        .await;
+        assert_eq!(
+            output,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=UTF-8\r\nContent-Length: 8\r\n\r\n1,2 body\
+             HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=UTF-8\r\nContent-Length: 1\r\nConnection: close\r\n\r\n "
+        );
+
+        let output = exchange(&ctx, b"GET / HTTP/1.0\r\n\r\nGET / HTTP/1.0\r\n\r\n").await;
+        assert_eq!(output.matches("HTTP/1.1 200 OK").count(), 1);
+
+        // 分块编码的请求体
+        let output = exchange(
+            &ctx,
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n3;x=1\r\nbod\r\n1\r\ny\r\n0\r\nTrailer: 1\r\n\r\n",
+        )
+        .await;
+        assert!(output.ends_with("\r\n\r\n body"), "{}", output);
+    }
+
+    // 测试无效请求的响应，以及收到退出请求后空闲连接被关闭
+    #[tokio::test]
+    async fn test_invalid_requests() {
+        let ctx = context();
+        for (input, status) in [
+            (&b"GARBAGE\r\n\r\n"[..], "400 Bad Request"),
+            (b"GET / HTTP/2\r\n\r\n", "505 HTTP Version Not Supported"),
+            (
+                b"POST / HTTP/1.1\r\nContent-Length: x\r\n\r\n",
+                "400 Bad Request",
+            ),
+            (
+                b"POST / HTTP/1.1\r\nContent-Length: 16777217\r\n\r\n",
+                "413 Content Too Large",
+            ),
+            (
+                b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n",
+                "501 Not Implemented",
+            ),
+            (
+                b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 3\r\n\r\n",
+                "400 Bad Request",
+            ),
+            (
+                b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n",
+                "400 Bad Request",
+            ),
+        ] {
+            let output = exchange(&ctx, input).await;
+            assert!(
+                output.starts_with(&format!("HTTP/1.1 {}\r\n", status)),
+                "{}",
+                output
+            );
+        }
+
+        let (_client, server) = tokio::io::duplex(1024);
+        ctx.request_shutdown("test");
+        let served = serve(server, &ctx, |_| async { Response::status("200 OK") });
+        tokio::time::timeout(Duration::from_secs(1), served)
+            .await
+            .expect("Idle connection was not closed")
+            .unwrap();
+    }
+
+    // 测试分块大小的解析和上限，超大的分块大小不能使累计长度溢出
+    #[tokio::test]
+    async fn test_chunk_sizes() {
+        let ctx = context();
+        for (input, status) in [
+            (
+                &b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n1\r\nx\r\nffffffffffffffff\r\n"[..],
+                "413 Content Too Large",
+            ),
+            (
+                b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n1\r\nx\r\n1000000\r\n",
+                "413 Content Too Large",
+            ),
+            (
+                b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n10000000000000000\r\n",
+                "400 Bad Request",
+            ),
+            (
+                b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n1\r\nxy\r\n0\r\n\r\n",
+                "400 Bad Request",
+            ),
+        ] {
+            let output = exchange(&ctx, input).await;
+            assert!(
+                output.starts_with(&format!("HTTP/1.1 {}\r\n", status)),
+                "{}",
+                output
+            );
+        }
+
+        // 分块总长度恰好达到上限时正常处理
+        let mut input = format!(
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n1\r\nx\r\n{:x}\r\n",
+            MAX_BODY - 1
+        )
+        .into_bytes();
+        input.resize(input.len() + MAX_BODY - 1, b'y');
+        input.extend(b"\r\n0\r\n\r\n");
+        let output = exchange(&ctx, &input).await;
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(output.contains(&format!("Content-Length: {}\r\n", MAX_BODY + 1)));
+        assert!(output.contains("\r\n\r\n xy") && output.ends_with("yy"));
+    }
+
+    // 测试请求头长度上限和请求体不完整时不调用处理函数
+    #[tokio::test]
+    async fn test_limits() {
+        let ctx = context();
+        let mut input = b"GET / HTTP/1.1\r\nX-Test: ".to_vec();
+        input.resize(MAX_HEAD + 1, b'a');
+        let output = exchange(&ctx, &input).await;
+        assert!(
+            output.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"),
+            "{}",
+            output
+        );
+
+        // 多个请求头合计超过上限
+        let mut input = b"GET / HTTP/1.1\r\n".to_vec();
+        while input.len() <= MAX_HEAD {
+            input.extend(b"X-Test: aaaaaaaaaaaaaaaa\r\n");
+        }
+        input.extend(b"\r\n");
+        let output = exchange(&ctx, &input).await;
+        assert!(output.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+
+        for input in [
+            &b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nshort"[..],
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nsh",
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n1\r\nx\r\n",
+            b"POST / HTTP/1.1\r\nX-Test: 1\r\n",
+        ] {
+            let output = exchange(&ctx, input).await;
+            assert!(!output.contains("200 OK"), "{}", output);
+        }
+    }
+}
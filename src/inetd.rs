@@ -0,0 +1,352 @@
+use std::ffi::CString;
+use std::io::{self, BufRead, Read, Write};
+use std::mem::ManuallyDrop;
+use std::net::{SocketAddr, TcpStream};
+use std::os::fd::FromRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::commands::Peer;
+use crate::config::Config;
+use crate::http::Request;
+
+/// 转交请求时携带客户端地址的请求头，只在伴随套接字上生效
+pub const CLIENT_HEADER: &str = "X-Neorust-Client";
+/// 请求行和请求头的最大长度
+const MAX_HEAD: usize = 64 * 1024;
+/// 请求体的最大长度
+pub const MAX_BODY: usize = 16 * 1024 * 1024;
+/// 伴随进程暂时不可用（例如正在升级）时重试连接的时长
+const CONNECT_RETRY: Duration = Duration::from_secs(5);
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+/// 不转交给伴随进程的逐跳请求头
+pub const HOP_BY_HOP: &[&str] = &[
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Connection",
+    "Expect",
+    CLIENT_HEADER,
+];
+
+/// 无法转交的请求
+pub enum Error {
+    Io(io::Error),
+    /// 直接返回给客户端的状态行
+    Status(&'static str),
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+/// 伴随套接字的连接是否来自允许转交请求的用户：与本进程相同的用户、root，或配置的用户和组
+///
+/// 只有这些连接的`X-Neorust-Client`请求头可信，其他连接被拒绝。
+pub fn authorized(uid: u32, gid: u32, config: &Config) -> bool {
+    uid == 0
+        || uid == unsafe { libc::geteuid() }
+        || config.companion_uids.contains(&uid)
+        || config.companion_gids.contains(&gid)
+}
+
+/// 伴随套接字上的请求来源：inetd和CGI模式的进程在请求头中带上客户端地址
+pub fn peer(request: &Request) -> Peer {
+    let addr = request
+        .header(CLIENT_HEADER)
+        .and_then(|value| value.parse().ok());
+    Peer {
+        addr,
+        identity: None,
+        tenant: None,
+    }
+}
+
+/// 处理标准输入输出上的一个HTTP连接：读取一个请求转交给伴随进程，返回其响应后结束
+///
+/// 响应要求客户端关闭连接，之后的请求由inetd启动的新进程处理。
+pub fn run(companion: &Path) -> io::Result<()> {
+    relay(
+        &mut io::stdin().lock(),
+        &mut io::stdout().lock(),
+        companion,
+        client_addr(),
+    )
+}
+
+/// 把inetd模式的错误写入系统日志：标准输出和标准错误都是客户端连接
+pub fn syslog(message: &str) {
+    let Ok(message) = CString::new(message) else {
+        return;
+    };
+    unsafe {
+        libc::openlog(c"neorust".as_ptr(), libc::LOG_PID, libc::LOG_DAEMON);
+        libc::syslog(libc::LOG_ERR, c"%s".as_ptr(), message.as_ptr());
+    }
+}
+
+// inetd把客户端连接作为标准输入；标准输入不是套接字时（例如手动测试）没有客户端地址
+fn client_addr() -> Option<SocketAddr> {
+    let stdin = ManuallyDrop::new(unsafe { TcpStream::from_raw_fd(0) });
+    stdin.peer_addr().ok()
+}
+
+fn relay(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    companion: &Path,
+    client: Option<SocketAddr>,
+) -> io::Result<()> {
+    let request = match read_request(input, output, client) {
+        Ok(Some(request)) => request,
+        Ok(None) => return Ok(()),
+        Err(Error::Status(status)) => return respond(output, status),
+        Err(Error::Io(e)) => return Err(e),
+    };
+    match forward(companion, &request) {
+        Ok(response) => {
+            output.write_all(&response)?;
+            output.flush()
+        }
+        Err(e) => {
+            respond(output, "502 Bad Gateway")?;
+            Err(e)
+        }
+    }
+}
+
+// 读取一个请求，改写为转交给伴随进程的形式：去掉逐跳请求头，带上客户端地址，处理后关闭连接
+//
+// 只支持Content-Length声明长度的请求体；客户端等待100 Continue时先行回复。
+fn read_request(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    client: Option<SocketAddr>,
+) -> Result<Option<Vec<u8>>, Error> {
+    let mut request = Vec::new();
+    let mut line = Vec::new();
+    let mut head = 0;
+    let mut length = 0;
+    let mut expect_continue = false;
+    loop {
+        line.clear();
+        let n = (&mut *input)
+            .take((MAX_HEAD - head) as u64)
+            .read_until(b'\n', &mut line)?;
+        if n == 0 && head == 0 {
+            return Ok(None);
+        }
+        head += n;
+        if !line.ends_with(b"\n") {
+            return Err(Error::Status(if head >= MAX_HEAD {
+                "431 Request Header Fields Too Large"
+            } else {
+                "400 Bad Request"
+            }));
+        }
+        let text = std::str::from_utf8(&line)
+            .map_err(|_| Error::Status("400 Bad Request"))?
+            .trim_end_matches(['\r', '\n']);
+        if head == n {
+            request.extend(text.as_bytes());
+            request.extend(b"\r\n");
+            continue;
+        }
+        if text.is_empty() {
+            break;
+        }
+        let (name, value) = text
+            .split_once(':')
+            .ok_or(Error::Status("400 Bad Request"))?;
+        let (name, value) = (name.trim(), value.trim());
+        if name.eq_ignore_ascii_case("Content-Length") {
+            length = value
+                .parse()
+                .map_err(|_| Error::Status("400 Bad Request"))?;
+            if length > MAX_BODY {
+                return Err(Error::Status("413 Content Too Large"));
+            }
+        } else if name.eq_ignore_ascii_case("Transfer-Encoding") {
+            return Err(Error::Status("411 Length Required"));
+        } else if name.eq_ignore_ascii_case("Expect") {
+            expect_continue = value.eq_ignore_ascii_case("100-continue");
+        }
+        if HOP_BY_HOP.iter().any(|h| name.eq_ignore_ascii_case(h)) {
+            continue;
+        }
+        request.extend(text.as_bytes());
+        request.extend(b"\r\n");
+    }
+    if let Some(client) = client {
+        request.extend(format!("{}: {}\r\n", CLIENT_HEADER, client).as_bytes());
+    }
+    request.extend(b"Connection: close\r\n\r\n");
+
+    if expect_continue && length > 0 {
+        output.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+        output.flush()?;
+    }
+    let start = request.len();
+    request.resize(start + length, 0);
+    input.read_exact(&mut request[start..])?;
+    Ok(Some(request))
+}
+
+/// 把请求交给伴随进程，读取响应直到其关闭连接
+pub fn forward(companion: &Path, request: &[u8]) -> io::Result<Vec<u8>> {
+    let mut stream = connect(companion)?;
+    stream.write_all(request)?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    if response.is_empty() {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(response)
+}
+
+// 伴随进程重启或升级时套接字短暂不可用，重试一段时间
+fn connect(path: &Path) -> io::Result<UnixStream> {
+    let deadline = Instant::now() + CONNECT_RETRY;
+    loop {
+        match UnixStream::connect(path) {
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::ConnectionRefused | io::ErrorKind::NotFound
+                ) && Instant::now() < deadline =>
+            {
+                std::thread::sleep(RETRY_INTERVAL);
+            }
+            result => return result,
+        }
+    }
+}
+
+fn respond(output: &mut impl Write, status: &str) -> io::Result<()> {
+    write!(
+        output,
+        "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        status
+    )?;
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufReader;
+    use std::os::unix::net::UnixListener;
+
+    fn rewrite(
+        input: &str,
+        client: Option<SocketAddr>,
+    ) -> (Result<Option<Vec<u8>>, Error>, Vec<u8>) {
+        let mut output = Vec::new();
+        let result = read_request(&mut input.as_bytes(), &mut output, client);
+        (result, output)
+    }
+
+    // 测试请求的改写和拒绝
+    #[test]
+    fn test_read_request() {
+        let client = Some("203.0.113.9:5000".parse().unwrap());
+        let (result, output) = rewrite(
+            "POST /tunnel HTTP/1.1\r\nHost: example\r\nConnection: keep-alive\r\nX-Neorust-Client: 10.0.0.1:1\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\nhello",
+            client,
+        );
+        let request = result.ok().flatten().expect("Request expected");
+        assert_eq!(
+            String::from_utf8(request).unwrap(),
+            "POST /tunnel HTTP/1.1\r\nHost: example\r\nContent-Length: 5\r\nX-Neorust-Client: 203.0.113.9:5000\r\nConnection: close\r\n\r\nhello"
+        );
+        assert_eq!(output, b"HTTP/1.1 100 Continue\r\n\r\n");
+
+        assert!(matches!(rewrite("", client).0, Ok(None)));
+        for (input, status) in [
+            (
+                "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n",
+                "411 Length Required",
+            ),
+            (
+                "POST / HTTP/1.1\r\nContent-Length: x\r\n\r\n",
+                "400 Bad Request",
+            ),
+            ("POST / HTTP/1.1\r\nHost", "400 Bad Request"),
+        ] {
+            assert!(
+                matches!(rewrite(input, client).0, Err(Error::Status(s)) if s == status),
+                "{}",
+                input
+            );
+        }
+        assert!(matches!(
+            rewrite("POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nshort", client).0,
+            Err(Error::Io(_))
+        ));
+    }
+
+    // 测试只信任本进程的用户、root和配置的用户和组
+    #[test]
+    fn test_authorized() {
+        let uid = unsafe { libc::geteuid() };
+        let other = uid.wrapping_add(1).max(1);
+        let mut config = Config::default();
+        assert!(authorized(uid, 12345, &config));
+        assert!(authorized(0, 12345, &config));
+        assert!(!authorized(other, 12345, &config));
+        config.companion_gids = vec![12345];
+        assert!(authorized(other, 12345, &config));
+        config.companion_gids.clear();
+        config.companion_uids = vec![other];
+        assert!(authorized(other, 12345, &config));
+    }
+
+    // 测试转交请求并返回伴随进程的响应，伴随进程没有响应时返回502
+    #[test]
+    fn test_relay() {
+        let path = std::env::temp_dir().join(format!("neorust-inetd-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let companion = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(&stream);
+            let mut head = String::new();
+            while !head.ends_with("\r\n\r\n") {
+                reader.read_line(&mut head).unwrap();
+            }
+            let mut body = [0; 4];
+            reader.read_exact(&mut body).unwrap();
+            (&stream)
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok")
+                .unwrap();
+            drop(stream);
+            drop(listener.accept().unwrap());
+            (head, body)
+        });
+
+        let mut output = Vec::new();
+        relay(
+            &mut "POST / HTTP/1.1\r\nContent-Length: 4\r\n\r\nping".as_bytes(),
+            &mut output,
+            &path,
+            None,
+        )
+        .expect("Relay failed");
+        assert!(output.ends_with(b"\r\n\r\nok"));
+
+        let mut output = Vec::new();
+        let request = "GET / HTTP/1.1\r\n\r\n";
+        assert!(relay(&mut request.as_bytes(), &mut output, &path, None).is_err());
+        assert!(output.starts_with(b"HTTP/1.1 502 Bad Gateway\r\n"));
+
+        let (head, body) = companion.join().unwrap();
+        assert_eq!(
+            head,
+            "POST / HTTP/1.1\r\nContent-Length: 4\r\nConnection: close\r\n\r\n"
+        );
+        assert_eq!(&body, b"ping");
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,117 @@
+use hkdf::Hkdf;
+use rand::RngCore;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::aead::{Aead, KEY_ID_LEN, KeyStore};
+use crate::errors::NeoError;
+
+const KEX_INFO: &[u8] = b"neorust-kex-v1";
+
+/// X25519 握手，协商出的会话密钥保存到密钥存储
+///
+/// 客户端发送临时公钥，服务端生成自己的临时密钥对，用共享秘密和双方公钥
+/// 派生会话密钥，返回 key_id(8) || server_public(32)。握手消息本身由预共享
+/// 密码的AEAD帧保护，从而完成认证；临时私钥用完即销毁，保证前向安全。
+pub fn handshake(keys: &KeyStore, client_public: &[u8]) -> Result<Vec<u8>, NeoError> {
+    let client_public: [u8; 32] = client_public
+        .try_into()
+        .map_err(|_| NeoError::Other("Invalid public key length".to_string()))?;
+    let client_public = PublicKey::from(client_public);
+
+    let mut secret = [0u8; 32];
+    rand::rng().fill_bytes(&mut secret);
+    let secret = StaticSecret::from(secret);
+    let server_public = PublicKey::from(&secret);
+
+    let shared = secret.diffie_hellman(&client_public);
+    if !shared.was_contributory() {
+        return Err(NeoError::Other("Invalid public key".to_string()));
+    }
+
+    let mut info = Vec::with_capacity(KEX_INFO.len() + 64);
+    info.extend_from_slice(KEX_INFO);
+    info.extend_from_slice(client_public.as_bytes());
+    info.extend_from_slice(server_public.as_bytes());
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(None, shared.as_bytes())
+        .expand(&info, &mut key)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+
+    let id = keys.insert(Aead::new(&key));
+
+    let mut out = Vec::with_capacity(KEY_ID_LEN + 32);
+    out.extend_from_slice(&id);
+    out.extend_from_slice(server_public.as_bytes());
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aead::{KeyId, PSK_KEY_ID};
+
+    // 模拟客户端完成握手，返回会话密钥
+    fn client_handshake(store: &KeyStore) -> (KeyId, Aead) {
+        let mut secret = [0u8; 32];
+        rand::rng().fill_bytes(&mut secret);
+        let secret = StaticSecret::from(secret);
+        let public = PublicKey::from(&secret);
+
+        let reply = handshake(store, public.as_bytes()).expect("Handshake failed");
+        let id: KeyId = reply[..KEY_ID_LEN].try_into().unwrap();
+        let server_public: [u8; 32] = reply[KEY_ID_LEN..].try_into().unwrap();
+        let server_public = PublicKey::from(server_public);
+
+        let shared = secret.diffie_hellman(&server_public);
+        let mut info = KEX_INFO.to_vec();
+        info.extend_from_slice(public.as_bytes());
+        info.extend_from_slice(server_public.as_bytes());
+        let mut key = [0u8; 32];
+        Hkdf::<Sha256>::new(None, shared.as_bytes())
+            .expand(&info, &mut key)
+            .unwrap();
+        (id, Aead::new(&key))
+    }
+
+    // 测试握手后双方得到相同的会话密钥，且响应使用同一密钥
+    #[test]
+    fn test_handshake_session_key() {
+        let store = KeyStore::with_password(b"password");
+        let (id, client) = client_handshake(&store);
+        assert_ne!(id, PSK_KEY_ID);
+
+        let frame = client.seal(&id, b"blv payload");
+        let (payload, key) = store.open(frame).expect("Open failed");
+        assert_eq!(payload, b"blv payload");
+
+        let response = store.seal(b"reply".to_vec(), key.as_ref());
+        assert_eq!(client.open(&response).expect("Open failed"), b"reply");
+    }
+
+    // 测试每个客户端的会话密钥互相独立，且不同于预共享密钥
+    #[test]
+    fn test_keys_per_client() {
+        let store = KeyStore::with_password(b"password");
+        let (id_a, client_a) = client_handshake(&store);
+        let (id_b, _) = client_handshake(&store);
+        assert_ne!(id_a, id_b);
+
+        // 用A的密钥加密却声明B的标识，应被拒绝
+        let forged = client_a.seal(&id_b, b"blv payload");
+        assert!(store.open(forged).is_err());
+
+        let psk = Aead::from_password(b"password");
+        let forged = client_a.seal(&PSK_KEY_ID, b"blv payload");
+        assert!(store.open(forged).is_err());
+        assert!(store.open(psk.seal(&PSK_KEY_ID, b"ok")).is_ok());
+    }
+
+    // 测试拒绝长度错误或低阶的公钥
+    #[test]
+    fn test_reject_bad_public_key() {
+        let store = KeyStore::with_password(b"password");
+        assert!(handshake(&store, &[1u8; 16]).is_err());
+        assert!(handshake(&store, &[0u8; 32]).is_err());
+    }
+}
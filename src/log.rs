@@ -0,0 +1,467 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::errors::NeoError;
+
+// 日志级别
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Off = 0,
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+}
+
+// 日志输出格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    #[default]
+    Text = 0,
+    /// 每行一个JSON对象
+    Json = 1,
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Warn as u8);
+#[cfg_attr(not(feature = "logging"), allow(dead_code))]
+static FORMAT: AtomicU8 = AtomicU8::new(Format::Text as u8);
+
+impl Level {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::Off => "off",
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Level {
+    type Err = NeoError;
+
+    fn from_str(s: &str) -> Result<Self, NeoError> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "off" => Ok(Level::Off),
+            "error" => Ok(Level::Error),
+            "warn" | "warning" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            _ => Err(NeoError::Other(format!("Invalid log level: {}", s))),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Format::Text => "text",
+            Format::Json => "json",
+        })
+    }
+}
+
+impl FromStr for Format {
+    type Err = NeoError;
+
+    fn from_str(s: &str) -> Result<Self, NeoError> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(NeoError::Other(format!("Invalid log format: {}", s))),
+        }
+    }
+}
+
+/// 设置全局日志级别
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// 当前的全局日志级别
+#[cfg_attr(not(unix), allow(dead_code))]
+pub fn level() -> Level {
+    match LEVEL.load(Ordering::Relaxed) {
+        0 => Level::Off,
+        1 => Level::Error,
+        2 => Level::Warn,
+        3 => Level::Info,
+        _ => Level::Debug,
+    }
+}
+
+/// 设置全局日志格式
+pub fn set_format(format: Format) {
+    FORMAT.store(format as u8, Ordering::Relaxed);
+}
+
+/// 指定级别的日志是否输出
+///
+/// 未启用logging feature时恒为false，日志宏及其参数在编译期被优化掉。
+#[inline(always)]
+pub fn enabled(level: Level) -> bool {
+    cfg!(feature = "logging") && level != Level::Off && level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+// 每条日志附带的上下文
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Fields {
+    pub client: Option<std::net::SocketAddr>,
+    /// 客户端证书身份
+    pub identity: String,
+    /// 识别出的租户
+    pub tenant: String,
+    pub mark: String,
+    pub cmd: String,
+}
+
+#[cfg(feature = "logging")]
+tokio::task_local! {
+    static FIELDS: std::cell::RefCell<Fields>;
+}
+
+/// 在指定上下文中运行异步任务
+pub async fn scope<F: Future>(fields: Fields, f: F) -> F::Output {
+    #[cfg(feature = "logging")]
+    {
+        FIELDS.scope(std::cell::RefCell::new(fields), f).await
+    }
+    #[cfg(not(feature = "logging"))]
+    {
+        let _ = fields;
+        f.await
+    }
+}
+
+/// 当前任务的日志上下文，用于传递给新启动的任务
+pub fn current() -> Fields {
+    #[cfg(feature = "logging")]
+    {
+        FIELDS
+            .try_with(|fields| fields.borrow().clone())
+            .unwrap_or_default()
+    }
+    #[cfg(not(feature = "logging"))]
+    {
+        Fields::default()
+    }
+}
+
+/// 解码出命令后补充租户、会话标记和命令
+pub fn annotate(tenant: Option<&str>, mark: &str, cmd: &str) {
+    #[cfg(feature = "logging")]
+    let _ = FIELDS.try_with(|fields| {
+        let mut fields = fields.borrow_mut();
+        fields.tenant = tenant.unwrap_or_default().to_string();
+        fields.mark = mark.to_string();
+        fields.cmd = cmd.to_string();
+    });
+    #[cfg(not(feature = "logging"))]
+    let _ = (tenant, mark, cmd);
+}
+
+/// 输出一条日志，由日志宏调用
+#[cfg_attr(not(feature = "logging"), allow(unused_variables))]
+pub fn write(level: Level, args: fmt::Arguments<'_>) {
+    #[cfg(feature = "logging")]
+    {
+        use std::io::Write;
+
+        let format = match FORMAT.load(Ordering::Relaxed) {
+            1 => Format::Json,
+            _ => Format::Text,
+        };
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        let line = format_line(format, now, level, &current(), &args.to_string());
+        let _ = writeln!(std::io::stderr().lock(), "{}", line);
+    }
+}
+
+// 按格式拼接一行日志
+#[cfg(feature = "logging")]
+fn format_line(
+    format: Format,
+    now: std::time::Duration,
+    level: Level,
+    fields: &Fields,
+    message: &str,
+) -> String {
+    let ts = timestamp(now);
+    let client = fields.client.map(|c| c.to_string()).unwrap_or_default();
+    match format {
+        Format::Text => {
+            let mut line = format!("{} {:<5}", ts, level.as_str().to_ascii_uppercase());
+            for (name, value) in [
+                ("client", &client),
+                ("identity", &fields.identity),
+                ("tenant", &fields.tenant),
+                ("mark", &fields.mark),
+                ("cmd", &fields.cmd),
+            ] {
+                if !value.is_empty() {
+                    line.push_str(&format!(" {}={}", name, value));
+                }
+            }
+            line.push(' ');
+            line.push_str(message);
+            line
+        }
+        Format::Json => {
+            let mut line = format!("{{\"ts\":\"{}\",\"level\":\"{}\"", ts, level);
+            for (name, value) in [
+                ("client", &client),
+                ("identity", &fields.identity),
+                ("tenant", &fields.tenant),
+                ("mark", &fields.mark),
+                ("cmd", &fields.cmd),
+            ] {
+                if !value.is_empty() {
+                    line.push_str(&format!(",\"{}\":\"{}\"", name, json_escape(value)));
+                }
+            }
+            line.push_str(&format!(",\"msg\":\"{}\"}}", json_escape(message)));
+            line
+        }
+    }
+}
+
+/// JSON字符串转义
+pub fn json_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// UTC时间戳，RFC 3339格式，精确到毫秒
+pub fn timestamp(since_epoch: std::time::Duration) -> String {
+    let secs = since_epoch.as_secs();
+    let (days, rem) = (secs / 86400, secs % 86400);
+
+    // 由天数推算公历日期
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// 解析UTC时间戳：`YYYY-MM-DD`或`YYYY-MM-DDTHH:MM:SS[.fff]Z`
+pub fn parse_timestamp(s: &str) -> Result<std::time::Duration, crate::errors::NeoError> {
+    let invalid = || crate::errors::NeoError::Other(format!("Invalid timestamp: {}", s));
+    let (date, time) = match s.trim().split_once('T') {
+        Some((date, time)) => (date, Some(time.strip_suffix('Z').ok_or_else(invalid)?)),
+        None => (s.trim(), None),
+    };
+    let number = |part: Option<&str>, range: std::ops::RangeInclusive<i64>| {
+        part.and_then(|p| p.parse::<i64>().ok())
+            .filter(|n| range.contains(n))
+            .ok_or_else(invalid)
+    };
+
+    let mut parts = date.split('-');
+    let year = number(parts.next(), 1970..=9999)?;
+    let month = number(parts.next(), 1..=12)?;
+    let day = number(parts.next(), 1..=31)?;
+    if parts.next().is_some() {
+        return Err(invalid());
+    }
+
+    let (mut secs, mut millis) = (0, 0);
+    if let Some(time) = time {
+        let (time, fraction) = match time.split_once('.') {
+            Some((time, fraction)) => (time, Some(fraction)),
+            None => (time, None),
+        };
+        let mut parts = time.split(':');
+        let hour = number(parts.next(), 0..=23)?;
+        let minute = number(parts.next(), 0..=59)?;
+        let second = number(parts.next(), 0..=59)?;
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+        secs = hour * 3600 + minute * 60 + second;
+        if let Some(fraction) = fraction {
+            if fraction.is_empty() || fraction.len() > 3 {
+                return Err(invalid());
+            }
+            millis = number(Some(&format!("{:0<3}", fraction)), 0..=999)?;
+        }
+    }
+
+    // 由公历日期推算天数，与timestamp互逆
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    let since_epoch = std::time::Duration::from_secs((days * 86400 + secs) as u64)
+        + std::time::Duration::from_millis(millis as u64);
+    // 拒绝2月30日之类不存在的日期
+    if !timestamp(since_epoch).starts_with(date) {
+        return Err(invalid());
+    }
+    Ok(since_epoch)
+}
+
+macro_rules! log_at {
+    ($level:expr, $($arg:tt)+) => {
+        if $crate::log::enabled($level) {
+            $crate::log::write($level, format_args!($($arg)+));
+        }
+    };
+}
+
+macro_rules! error {
+    ($($arg:tt)+) => { $crate::log::log_at!($crate::log::Level::Error, $($arg)+) };
+}
+
+macro_rules! log_warn {
+    ($($arg:tt)+) => { $crate::log::log_at!($crate::log::Level::Warn, $($arg)+) };
+}
+
+macro_rules! info {
+    ($($arg:tt)+) => { $crate::log::log_at!($crate::log::Level::Info, $($arg)+) };
+}
+
+macro_rules! debug {
+    ($($arg:tt)+) => { $crate::log::log_at!($crate::log::Level::Debug, $($arg)+) };
+}
+
+pub(crate) use {debug, error, info, log_at, log_warn as warn};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // 测试时间戳格式
+    #[test]
+    fn test_timestamp() {
+        assert_eq!(timestamp(Duration::ZERO), "1970-01-01T00:00:00.000Z");
+        assert_eq!(
+            timestamp(Duration::from_millis(951_782_400_123)),
+            "2000-02-29T00:00:00.123Z"
+        );
+        assert_eq!(
+            timestamp(Duration::from_secs(1_792_367_999)),
+            "2026-10-18T23:59:59.000Z"
+        );
+    }
+
+    // 测试时间戳解析与格式化互逆
+    #[test]
+    fn test_parse_timestamp() {
+        for ts in [
+            "1970-01-01T00:00:00.000Z",
+            "2000-02-29T00:00:00.123Z",
+            "2026-10-18T23:59:59.000Z",
+        ] {
+            assert_eq!(timestamp(parse_timestamp(ts).expect("Parse failed")), ts);
+        }
+        assert_eq!(
+            parse_timestamp("2026-10-18T23:59:59Z").unwrap(),
+            Duration::from_secs(1_792_367_999)
+        );
+        assert_eq!(
+            parse_timestamp("2026-10-19").unwrap(),
+            Duration::from_secs(1_792_368_000)
+        );
+        assert!(parse_timestamp("2026-02-30").is_err());
+        assert!(parse_timestamp("2026-10-18T24:00:00Z").is_err());
+        assert!(parse_timestamp("2026-10-18T12:00:00").is_err());
+        assert!(parse_timestamp("yesterday").is_err());
+    }
+
+    // 测试文本与JSON行格式
+    #[cfg(feature = "logging")]
+    #[test]
+    fn test_format_line() {
+        let fields = Fields {
+            client: Some("[::1]:4000".parse().unwrap()),
+            mark: "abc".to_string(),
+            cmd: "READ".to_string(),
+            ..Fields::default()
+        };
+        let text = format_line(
+            Format::Text,
+            Duration::ZERO,
+            Level::Warn,
+            &fields,
+            "Read error",
+        );
+        assert_eq!(
+            text,
+            "1970-01-01T00:00:00.000Z WARN  client=[::1]:4000 mark=abc cmd=READ Read error"
+        );
+
+        let json = format_line(
+            Format::Json,
+            Duration::ZERO,
+            Level::Info,
+            &Fields::default(),
+            "say \"hi\"\n",
+        );
+        assert_eq!(
+            json,
+            r#"{"ts":"1970-01-01T00:00:00.000Z","level":"info","msg":"say \"hi\"\n"}"#
+        );
+    }
+
+    // 测试上下文在任务内传递
+    #[cfg(feature = "logging")]
+    #[tokio::test]
+    async fn test_scope() {
+        assert_eq!(current(), Fields::default());
+        let client = Some("127.0.0.1:1".parse().unwrap());
+        let fields = Fields {
+            client,
+            ..Fields::default()
+        };
+        let inner = scope(fields, async {
+            annotate(Some("red"), "m", "CONNECT");
+            current()
+        })
+        .await;
+        assert_eq!(inner.client, client);
+        assert_eq!(inner.tenant, "red");
+        assert_eq!((inner.mark.as_str(), inner.cmd.as_str()), ("m", "CONNECT"));
+    }
+}
@@ -1,55 +1,796 @@
-use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
 use std::sync::Arc;
-use tiny_http::Server;
-use tokio::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use tokio::task::JoinHandle;
 
+mod acl;
+#[cfg(feature = "aead")]
+mod aead;
+mod audit;
+#[cfg(feature = "auth")]
+mod auth;
+#[cfg(unix)]
+mod cgi;
+mod cli;
 mod codec;
 mod commands;
+#[cfg(feature = "compress")]
+mod compress;
+mod config;
+#[cfg(unix)]
+mod control;
 mod errors;
+mod http;
+#[cfg(unix)]
+mod inetd;
+#[cfg(feature = "aead")]
+mod kex;
+mod log;
+mod metrics;
+mod protocol;
+mod rotation;
 mod session;
-use crate::codec::Codec;
-use crate::commands::handle_request;
+#[cfg(unix)]
+mod systemd;
+mod tenant;
+#[cfg(feature = "tls")]
+mod tls;
+#[cfg(unix)]
+mod upgrade;
+mod window;
+use crate::cli::Action;
+use crate::cli::Cli;
+use crate::commands::{Context, Peer, Runtime};
+use crate::config::Config;
+use crate::errors::NeoError;
+use crate::window::State;
 
 // 自定义Base64编码表
 const EN: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
 const DE: &[u8] = b"dhULNVGsuAk/MxH6ibjcEfRqDWYznXBe9Pl7+SKoZ8pJaICgrQO0mF21yv345wtT";
 const BLV_OFFSET: i32 = 1966546385;
+const REAPER_INTERVAL: Duration = Duration::from_secs(10);
+const WINDOW_INTERVAL: Duration = Duration::from_secs(1);
 const NEO_HELLO: &[u8] = b"6UNI/jhLR7X7fqPmY+m0BofOMNXNbVV2XNbiEVEODRxUbshHWKXC/mQWx0SNYVDFx1bKY0VDjcS3RcS/nGIOzVA0XOdI/cy=";
 
 // 主函数
 #[tokio::main]
 async fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 {
-        eprintln!("Usage: {} <listen-address>", args[0]);
-        std::process::exit(1);
+    // Web服务器直接执行CGI程序时只会传入查询字符串中的检索词，按CGI环境变量识别并忽略这些参数
+    let mut args = std::env::args();
+    let program = args.next();
+    let mut args: Vec<String> = args.collect();
+    let cgi = std::env::var_os("GATEWAY_INTERFACE").is_some()
+        && cli::cgi_invocation(program.as_deref(), &args);
+    if cgi {
+        args.clear();
     }
+    let cli = match cli::parse(args) {
+        Ok(Action::Run(cli)) => *cli,
+        Ok(Action::Help) => {
+            println!("{}", cli::USAGE);
+            return;
+        }
+        Ok(Action::Version) => {
+            println!("neorust {}", env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
 
-    let listen_addr = if args[1].contains(':') {
-        args[1].clone()
-    } else {
-        format!("0.0.0.0:{}", args[1])
+    let cgi = cgi || cli.cgi;
+    let config = match Config::load(&cli) {
+        Ok(config) => config,
+        // CGI模式先向Web服务器返回状态，错误信息写入其错误日志
+        #[cfg(unix)]
+        Err(e) if cgi => {
+            let _ = cgi::respond(&mut io::stdout(), "500 Internal Server Error");
+            eprintln!("Invalid configuration: {}", e);
+            std::process::exit(1);
+        }
+        #[cfg(unix)]
+        Err(e) if cli.inetd => {
+            inetd::syslog(&format!("Invalid configuration: {}", e));
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            std::process::exit(1);
+        }
     };
-    let server: Server = match Server::http(&listen_addr) {
-        Ok(s) => s,
+
+    // 仅检查配置并输出生效的设置
+    if cli.check_config {
+        println!("{}", config);
+        return;
+    }
+
+    // inetd和CGI模式只转交一个请求，不监听端口；标准输出是客户端连接或Web服务器，因此不输出日志
+    if cli.inetd || cgi {
+        run_relay(&config, cgi);
+    }
+    log::set_level(config.log_level);
+    log::set_format(config.log_format);
+
+    #[cfg(not(feature = "aead"))]
+    if config.key.is_some() {
+        log::warn!("Key ignored: built without the aead feature");
+    }
+
+    let listen = config.listen.clone();
+    #[cfg(feature = "tls")]
+    let tls_listen = config.tls.listen.clone();
+    let metrics_listen = config.metrics_listen;
+    let socket_activated = config.socket_activated;
+    #[cfg(feature = "tls")]
+    let activated_https = config.tls.client_ca.is_some();
+    let companion_socket = config.companion_socket.clone();
+    let companion_socket_mode = config.companion_socket_mode;
+    let runtime = match Runtime::new(config, None) {
+        Ok(runtime) => runtime,
         Err(e) => {
-            eprintln!("服务器启动失败: {}", e);
+            eprintln!("Invalid configuration: {}", e);
             std::process::exit(1);
         }
     };
+    let ctx = Context::new(runtime);
+
+    let cli = Arc::new(cli);
+
+    // systemd套接字激活或旧进程交接的监听套接字按地址使用，不重新绑定
+    let mut inherited = Vec::new();
+    #[cfg(unix)]
+    match systemd::listeners() {
+        Ok(listeners) => inherited.extend(listeners),
+        Err(e) => {
+            eprintln!("Socket activation failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // 由旧进程启动时先接管其会话
+    #[cfg(unix)]
+    let mut takeover = match upgrade::inherited() {
+        Ok(Some(channel)) => match take_over(&ctx, &channel).await {
+            Ok(listeners) => {
+                inherited.extend(listeners);
+                Some(channel)
+            }
+            Err(e) => {
+                eprintln!("Failed to take over from the previous process: {}", e);
+                std::process::exit(1);
+            }
+        },
+        Ok(None) => None,
+        Err(e) => {
+            eprintln!("Failed to take over from the previous process: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // 本地控制套接字
+    #[cfg(unix)]
+    let mut control = match control_socket(&ctx, &cli) {
+        Ok(task) => task,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    #[cfg(not(unix))]
+    if ctx.runtime().config.control_socket.is_some() {
+        log::warn!("Control socket ignored: only supported on Unix");
+    }
+
+    // 收到SIGHUP时重新加载配置
+    #[cfg(unix)]
+    {
+        let ctx = ctx.clone();
+        let cli = Arc::clone(&cli);
+        tokio::spawn(async move {
+            use tokio::signal::unix::{SignalKind, signal};
+            let mut hangup = match signal(SignalKind::hangup()) {
+                Ok(s) => s,
+                Err(e) => {
+                    log::error!("Failed to install SIGHUP handler: {}", e);
+                    return;
+                }
+            };
+            while hangup.recv().await.is_some() {
+                let _ = reload(&ctx, &cli);
+            }
+        });
+    }
 
-    // println!("服务器启动成功，监听地址: {}", &listen_addr);
-    let codec = Codec::new();
-    let sessions = Arc::new(Mutex::new(HashMap::new()));
+    // 收到SIGUSR2时启动新的可执行文件，交接监听套接字和会话后退出
+    #[cfg(unix)]
+    {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            use tokio::signal::unix::{SignalKind, signal};
+            let mut user2 = match signal(SignalKind::user_defined2()) {
+                Ok(s) => s,
+                Err(e) => {
+                    log::error!("Failed to install SIGUSR2 handler: {}", e);
+                    return;
+                }
+            };
+            while user2.recv().await.is_some() {
+                if let Err(e) = upgrade::start(&ctx, None).await {
+                    log::error!("Upgrade failed: {}", e);
+                }
+            }
+        });
+    }
 
-    for request in server.incoming_requests() {
-        let codec_clone = codec.clone();
-        let sessions_clone = Arc::clone(&sessions);
-        // println!("request: {:?}", request);
+    // 收到SIGINT或SIGTERM时优雅退出，再次收到时立即退出
+    {
+        let ctx = ctx.clone();
         tokio::spawn(async move {
-            if let Err(e) = handle_request(request, &codec_clone, sessions_clone).await {
-                eprintln!("请求处理错误: {}", e);
+            ctx.request_shutdown(termination().await);
+            let signal = termination().await;
+            log::warn!("Received {} again, exiting immediately", signal);
+            std::process::exit(1);
+        });
+    }
+
+    // 定期回收空闲会话，空闲时长每次从当前配置读取
+    {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            loop {
+                let idle = Duration::from_secs(ctx.runtime().config.session_idle_secs);
+                if idle.is_zero() {
+                    tokio::time::sleep(REAPER_INTERVAL).await;
+                    continue;
+                }
+                tokio::time::sleep(REAPER_INTERVAL.min(idle)).await;
+                let evicted = commands::evict_idle_sessions(&ctx.sessions, idle).await;
+                let runtime = ctx.runtime();
+                for (mark, session) in &evicted {
+                    log::info!(
+                        "Evicted idle session {}: {}",
+                        mark,
+                        session.stats().summary()
+                    );
+                    if let Some(audit) = &runtime.audit {
+                        audit.record(&audit::Event::Evict {
+                            mark,
+                            session,
+                            reason: "idle",
+                        });
+                    }
+                }
+            }
+        });
+    }
+
+    // 活动时间窗口外关闭所有会话，过了结束时间后退出
+    {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            loop {
+                match ctx.runtime().config.window.state(SystemTime::now()) {
+                    State::Active => {}
+                    State::Inactive => {
+                        let closed = commands::close_all_sessions(&ctx.sessions).await;
+                        if !closed.is_empty() {
+                            log::info!(
+                                "Outside the activity window, closed {} sessions",
+                                closed.len()
+                            );
+                            record_evicted(&ctx, &closed, "window");
+                        }
+                    }
+                    State::Expired => {
+                        ctx.request_shutdown("kill-date");
+                        return;
+                    }
+                }
+                tokio::time::sleep(WINDOW_INTERVAL).await;
             }
         });
     }
+
+    // 监听套接字只创建一次，每次开始服务时为其创建接收连接的任务；升级时交接给新进程
+    let mut listeners = Listeners::default();
+    // HTTPS：在本进程内完成TLS握手并处理解密后的请求
+    #[cfg(feature = "tls")]
+    for addr in &tls_listen {
+        listeners.https.push(bind_or_exit(*addr, &mut inherited));
+        log::info!("Listening on {} (HTTPS)", addr);
+    }
+    // 指标使用单独的监听器，不与隧道流量混在一起
+    if let Some(addr) = metrics_listen {
+        listeners.metrics = Some(bind_or_exit(addr, &mut inherited));
+        log::info!("Serving metrics on {}", addr);
+    }
+    for addr in &listen {
+        // 套接字激活时HTTP监听由systemd提供，地址写法不同（如[::]和0.0.0.0）时再绑定会失败
+        if socket_activated
+            && !inherited
+                .iter()
+                .any(|l| l.local_addr().is_ok_and(|a| a == *addr))
+        {
+            log::warn!("Listen address {} is not socket-activated, skipped", addr);
+            continue;
+        }
+        listeners.http.push(bind_or_exit(*addr, &mut inherited));
+        log::info!("Listening on {}", addr);
+    }
+    #[cfg(not(unix))]
+    if companion_socket.is_some() {
+        log::warn!("Companion socket ignored: only supported on Unix");
+    }
+
+    // 套接字激活时其余传入的监听套接字也接收请求，要求客户端证书时使用HTTPS；否则关闭
+    for listener in inherited {
+        let Ok(addr) = listener.local_addr() else {
+            continue;
+        };
+        if !socket_activated {
+            log::warn!("Inherited listener on {} is not configured, closing", addr);
+            continue;
+        }
+        if let Err(e) = listener.set_nonblocking(true) {
+            eprintln!("Failed to listen on {}: {}", addr, e);
+            std::process::exit(1);
+        }
+        #[cfg(feature = "tls")]
+        if activated_https {
+            listeners.https.push(listener);
+            log::info!("Listening on {} (HTTPS, socket-activated)", addr);
+            continue;
+        }
+        listeners.http.push(listener);
+        log::info!("Listening on {} (socket-activated)", addr);
+    }
+
+    let mut resumed = false;
+    loop {
+        let mut accepting = match listeners.spawn(&ctx) {
+            Ok(tasks) => tasks,
+            Err(e) => {
+                eprintln!("Failed to start listeners: {}", e);
+                std::process::exit(1);
+            }
+        };
+        // inetd和CGI模式的进程把请求转交到伴随套接字，会话由本进程保存
+        #[cfg(unix)]
+        if let Some(path) = &companion_socket {
+            match companion(path, companion_socket_mode, &ctx) {
+                Ok(task) => {
+                    log::info!("Companion socket at {}", path.display());
+                    accepting.push(task);
+                }
+                // 恢复服务时不退出，inetd和CGI请求暂时不可用
+                Err(e) if resumed => {
+                    log::error!("Failed to bind companion socket {}: {}", path.display(), e)
+                }
+                Err(e) => {
+                    eprintln!("Failed to bind companion socket {}: {}", path.display(), e);
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        // 旧进程在新进程开始接收请求后退出
+        #[cfg(unix)]
+        if let Some(channel) = takeover.take()
+            && let Err(e) = upgrade::done(&channel)
+        {
+            log::warn!("Failed to notify the previous process: {}", e);
+        }
+
+        // 收到退出请求后先停止接收连接，再等待正在处理的请求完成
+        let reason = ctx.shutdown_requested().await;
+        log::warn!("Shutting down ({}), no longer accepting requests", reason);
+        for task in accepting {
+            let _ = task.await;
+        }
+
+        // 升级时监听套接字留给新进程，交接失败则恢复服务
+        #[cfg(unix)]
+        if reason == "upgrade" {
+            drain(&ctx).await;
+            if ctx.shutdown_reason() == Some("upgrade") {
+                hand_over(&ctx, &listeners).await;
+            }
+            if ctx.cancel_upgrade() {
+                log::warn!("Upgrade failed, resuming service");
+                // 新进程可能已替换控制套接字的路径
+                if let Some(task) = control.take() {
+                    task.abort();
+                }
+                control = control_socket(&ctx, &cli).unwrap_or_else(|e| {
+                    log::error!("{}", e);
+                    None
+                });
+                resumed = true;
+                continue;
+            }
+        }
+
+        // 释放监听端口，之后的连接直接被拒绝
+        drop(listeners);
+        drain(&ctx).await;
+        shutdown(&ctx, ctx.shutdown_reason().unwrap_or(reason)).await
+    }
+}
+
+// 监听套接字：升级时将副本交接给新进程，交接失败后重新接收连接
+#[derive(Default)]
+struct Listeners {
+    http: Vec<TcpListener>,
+    #[cfg(feature = "tls")]
+    https: Vec<TcpListener>,
+    metrics: Option<TcpListener>,
+}
+
+impl Listeners {
+    // 为每个监听套接字启动接收连接的任务，任务在收到退出请求后结束并释放其副本
+    fn spawn(&self, ctx: &Context) -> io::Result<Vec<JoinHandle<()>>> {
+        let mut tasks = Vec::new();
+        for listener in &self.http {
+            tasks.push(tokio::spawn(serve(tokio_listener(listener)?, ctx.clone())));
+        }
+        #[cfg(feature = "tls")]
+        for listener in &self.https {
+            tasks.push(tokio::spawn(tls::serve(
+                tokio_listener(listener)?,
+                ctx.clone(),
+            )));
+        }
+        if let Some(listener) = &self.metrics {
+            tasks.push(tokio::spawn(metrics::serve(
+                tokio_listener(listener)?,
+                ctx.clone(),
+            )));
+        }
+        Ok(tasks)
+    }
+
+    // 所有监听套接字的副本，用于交接
+    #[cfg(unix)]
+    fn handover(&self) -> io::Result<Vec<TcpListener>> {
+        let https: &[TcpListener] = {
+            #[cfg(feature = "tls")]
+            {
+                &self.https
+            }
+            #[cfg(not(feature = "tls"))]
+            {
+                &[]
+            }
+        };
+        self.http
+            .iter()
+            .chain(https)
+            .chain(&self.metrics)
+            .map(TcpListener::try_clone)
+            .collect()
+    }
+}
+
+fn tokio_listener(listener: &TcpListener) -> io::Result<tokio::net::TcpListener> {
+    tokio::net::TcpListener::from_std(listener.try_clone()?)
+}
+
+fn bind_or_exit(addr: SocketAddr, inherited: &mut Vec<TcpListener>) -> TcpListener {
+    bind(addr, inherited).unwrap_or_else(|e| {
+        eprintln!("Failed to listen on {}: {}", addr, e);
+        std::process::exit(1);
+    })
+}
+
+// 创建非阻塞的监听套接字，优先使用旧进程交接或systemd传入的同一地址的套接字
+fn bind(addr: SocketAddr, inherited: &mut Vec<TcpListener>) -> io::Result<TcpListener> {
+    let listener = match inherited
+        .iter()
+        .position(|l| l.local_addr().is_ok_and(|a| a == addr))
+    {
+        Some(i) => inherited.swap_remove(i),
+        None => TcpListener::bind(addr)?,
+    };
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+// 接收HTTP连接，收到退出请求后停止接收；请求来源为连接的对端地址
+async fn serve(listener: tokio::net::TcpListener, ctx: Context) {
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = ctx.shutdown_requested() => return,
+        };
+        let (stream, client) = match accepted {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                log::error!("Accept error: {}", e);
+                continue;
+            }
+        };
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            let peer = Peer {
+                addr: Some(client),
+                identity: None,
+                tenant: None,
+            };
+            let handler = |request| commands::handle_http(request, &ctx, &peer);
+            if let Err(e) = http::serve(stream, &ctx, handler).await {
+                log::debug!("Connection from {} closed: {}", client, e);
+            }
+        });
+    }
+}
+
+// 创建伴随套接字并接收连接；每次开始服务时重新创建，交接失败时新进程可能已占用该路径
+#[cfg(unix)]
+fn companion(path: &std::path::Path, mode: u32, ctx: &Context) -> io::Result<JoinHandle<()>> {
+    let listener = control::socket(path, mode)?;
+    listener.set_nonblocking(true)?;
+    let listener = tokio::net::UnixListener::from_std(listener)?;
+    let ctx = ctx.clone();
+    Ok(tokio::spawn(async move {
+        loop {
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = ctx.shutdown_requested() => return,
+            };
+            let stream = match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    log::error!("Companion socket accept error: {}", e);
+                    continue;
+                }
+            };
+            // 只有允许的用户可以转交请求并指定客户端地址
+            match stream.peer_cred() {
+                Ok(c) if inetd::authorized(c.uid(), c.gid(), &ctx.runtime().config) => {}
+                Ok(c) => {
+                    log::warn!(
+                        "Companion socket connection from uid {} gid {} refused",
+                        c.uid(),
+                        c.gid()
+                    );
+                    continue;
+                }
+                Err(e) => {
+                    log::warn!("Companion socket connection refused: {}", e);
+                    continue;
+                }
+            }
+            let ctx = ctx.clone();
+            tokio::spawn(async move {
+                let ctx = &ctx;
+                let _ = http::serve(stream, ctx, |request| async move {
+                    let peer = inetd::peer(&request);
+                    commands::handle_http(request, ctx, &peer).await
+                })
+                .await;
+            });
+        }
+    }))
+}
+
+// 创建控制套接字并接收控制命令，未配置时返回None
+#[cfg(unix)]
+fn control_socket(ctx: &Context, cli: &Arc<Cli>) -> Result<Option<JoinHandle<()>>, String> {
+    let Some(path) = ctx.runtime().config.control_socket.clone() else {
+        return Ok(None);
+    };
+    let listener = control::bind(&path)
+        .map_err(|e| format!("Failed to bind control socket {}: {}", path.display(), e))?;
+    log::info!("Control socket at {}", path.display());
+    Ok(Some(tokio::spawn(control::serve(
+        listener,
+        ctx.clone(),
+        Arc::clone(cli),
+    ))))
+}
+
+// 等待正在处理的请求完成，超时后不再等待
+async fn drain(ctx: &Context) {
+    let grace = Duration::from_millis(ctx.runtime().config.shutdown_grace_ms);
+    let pending = ctx.drain_requests(grace).await;
+    if pending > 0 {
+        log::warn!("{} requests still running after the grace period", pending);
+    }
+}
+
+// 将服务端主动关闭的会话记入审计日志
+fn record_evicted(ctx: &Context, sessions: &[(String, session::Session)], reason: &str) {
+    if let Some(audit) = &ctx.runtime().audit {
+        for (mark, session) in sessions {
+            audit.record(&audit::Event::Evict {
+                mark,
+                session,
+                reason,
+            });
+        }
+    }
+}
+
+// 以inetd或CGI模式处理一个请求后退出
+//
+// CGI模式的标准错误由Web服务器记入错误日志，inetd模式的标准错误可能是客户端连接，错误写入系统日志。
+#[cfg(unix)]
+fn run_relay(config: &Config, cgi: bool) -> ! {
+    let Some(path) = &config.companion_socket else {
+        let message = "--inetd and --cgi require a companion socket";
+        if cgi {
+            let _ = cgi::respond(&mut io::stdout(), "500 Internal Server Error");
+            eprintln!("{}", message);
+        } else {
+            inetd::syslog(message);
+        }
+        std::process::exit(2);
+    };
+    if !cgi {
+        if let Err(e) = inetd::run(path) {
+            inetd::syslog(&format!("inetd request failed: {}", e));
+            std::process::exit(1);
+        }
+        std::process::exit(0);
+    }
+    if let Err(e) = cgi::run(path) {
+        eprintln!("CGI request failed: {}", e);
+        std::process::exit(1);
+    }
+    std::process::exit(0)
+}
+
+#[cfg(not(unix))]
+fn run_relay(_config: &Config, _cgi: bool) -> ! {
+    eprintln!("--inetd and --cgi are only supported on Unix");
+    std::process::exit(2);
+}
+
+// 接收旧进程交接的监听套接字和会话，会话直接放入注册表
+#[cfg(unix)]
+async fn take_over(
+    ctx: &Context,
+    channel: &std::os::unix::net::UnixStream,
+) -> io::Result<Vec<TcpListener>> {
+    let channel = channel.try_clone()?;
+    let (listeners, detached) = tokio::task::spawn_blocking(move || upgrade::receive(&channel))
+        .await
+        .map_err(io::Error::other)??;
+    let options = ctx.runtime().config.session;
+    let count = detached.len();
+    let mut sessions = ctx.sessions.lock().await;
+    for (mark, stream, state) in detached {
+        sessions.insert(mark, session::Session::resume(stream, &options, state));
+    }
+    log::info!(
+        "Took over {} listeners and {} sessions from the previous process",
+        listeners.len(),
+        count
+    );
+    Ok(listeners)
+}
+
+// 将监听套接字和会话交接给新进程后退出，不关闭会话，控制套接字已由新进程接管
+//
+// 交接失败时返回，会话已放回注册表，随后恢复服务或按正常流程退出。
+#[cfg(unix)]
+async fn hand_over(ctx: &Context, listeners: &Listeners) {
+    let listeners = match listeners.handover() {
+        Ok(listeners) => listeners,
+        Err(e) => {
+            log::error!("Upgrade failed: {}", e);
+            return;
+        }
+    };
+    match upgrade::hand_over(ctx, listeners).await {
+        Ok((pid, sessions)) => {
+            let totals = ctx.metrics.totals();
+            log::warn!(
+                "Handed over {} sessions to process {}, final statistics: {}",
+                sessions,
+                pid,
+                totals.summary()
+            );
+            if let Some(audit) = &ctx.runtime().audit {
+                audit.record(&audit::Event::Shutdown {
+                    reason: "upgrade",
+                    totals: &totals,
+                });
+            }
+            std::process::exit(0)
+        }
+        Err(e) => log::error!("Upgrade failed: {}", e),
+    }
+}
+
+// 关闭所有会话，输出累计统计并写入最后一条审计记录后退出进程
+async fn shutdown(ctx: &Context, reason: &str) -> ! {
+    let closed = commands::close_all_sessions(&ctx.sessions).await;
+    record_evicted(ctx, &closed, reason);
+    let totals = ctx.metrics.totals();
+    log::warn!(
+        "Closed {} sessions, final statistics: {}",
+        closed.len(),
+        totals.summary()
+    );
+    let runtime = ctx.runtime();
+    if let Some(audit) = &runtime.audit {
+        audit.record(&audit::Event::Shutdown {
+            reason,
+            totals: &totals,
+        });
+    }
+    #[cfg(unix)]
+    for path in [
+        &runtime.config.control_socket,
+        &runtime.config.companion_socket,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        let _ = std::fs::remove_file(path);
+    }
+    std::process::exit(0)
+}
+
+// 等待SIGINT或SIGTERM，返回信号名
+#[cfg(unix)]
+async fn termination() -> &'static str {
+    use tokio::signal::unix::{SignalKind, signal};
+    match (
+        signal(SignalKind::terminate()),
+        signal(SignalKind::interrupt()),
+    ) {
+        (Ok(mut terminate), Ok(mut interrupt)) => tokio::select! {
+            _ = terminate.recv() => "SIGTERM",
+            _ = interrupt.recv() => "SIGINT",
+        },
+        (Err(e), _) | (_, Err(e)) => {
+            log::error!("Failed to install SIGTERM/SIGINT handlers: {}", e);
+            std::future::pending().await
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn termination() -> &'static str {
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        log::error!("Failed to install Ctrl-C handler: {}", e);
+        std::future::pending::<()>().await;
+    }
+    "SIGINT"
+}
+
+// 重新加载配置，失败时保留当前配置
+//
+// 命令行参数仍然优先于配置文件；监听地址需要重启或平滑升级才能生效。
+#[cfg_attr(not(unix), allow(dead_code))]
+fn reload(ctx: &Context, cli: &Cli) -> Result<(), NeoError> {
+    let result = Config::load(cli).and_then(|config| {
+        let current = ctx.runtime();
+        if config.listen != current.config.listen
+            || config.metrics_listen != current.config.metrics_listen
+            || config.control_socket != current.config.control_socket
+            || config.companion_socket != current.config.companion_socket
+            || config.companion_socket_mode != current.config.companion_socket_mode
+            || config.tls.listen != current.config.tls.listen
+        {
+            log::warn!("Listen addresses changed, restart or upgrade to apply");
+        }
+        let (level, format) = (config.log_level, config.log_format);
+        ctx.reload(config)?;
+        log::set_level(level);
+        log::set_format(format);
+        Ok(())
+    });
+    match &result {
+        Ok(()) => log::info!("Configuration reloaded"),
+        Err(e) => log::error!("Reload rejected, keeping current configuration: {}", e),
+    }
+    result
 }
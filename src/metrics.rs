@@ -0,0 +1,403 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+
+use crate::commands::{Context, session_stats};
+use crate::http::{self, Request, Response};
+use crate::log;
+
+/// 按命令统计的请求类型，未知命令计入other
+pub const COMMANDS: [&str; 7] = [
+    "CONNECT",
+    "FORWARD",
+    "READ",
+    "DISCONNECT",
+    "NEGOTIATE",
+    "HANDSHAKE",
+    "other",
+];
+
+/// 请求耗时直方图的桶上限（秒）
+const BUCKETS: [f64; 11] = [
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+// 请求解码失败的阶段
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeStage {
+    /// 请求体为空或base64解码失败
+    Base64 = 0,
+    /// AEAD认证失败
+    #[cfg_attr(not(feature = "aead"), allow(dead_code))]
+    Auth = 1,
+    /// Data字段解压失败
+    #[cfg_attr(not(feature = "compress"), allow(dead_code))]
+    Inflate = 2,
+    /// 未知命令
+    Command = 3,
+    /// HMAC认证字段缺失、无效、过期或重放
+    #[cfg_attr(not(feature = "auth"), allow(dead_code))]
+    Hmac = 4,
+}
+
+const DECODE_STAGES: [&str; 5] = ["base64", "auth", "inflate", "command", "hmac"];
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [AtomicU64; BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        for (bucket, le) in self.buckets.iter().zip(BUCKETS) {
+            if secs <= le {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+// 服务端运行指标，配置重新加载时保留
+#[derive(Default)]
+pub struct Metrics {
+    connects_ok: AtomicU64,
+    connects_failed: AtomicU64,
+    bytes_forwarded: AtomicU64,
+    bytes_read: AtomicU64,
+    decode_failures: [AtomicU64; DECODE_STAGES.len()],
+    requests: [Histogram; COMMANDS.len()],
+}
+
+// 采集时的会话状态
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct SessionGauges {
+    pub active: usize,
+    /// 等待写入目标的字节数
+    pub buffered_write: u64,
+    /// 等待客户端读取的字节数
+    pub buffered_read: u64,
+}
+
+// 累计统计，进程退出时写入日志和审计日志
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Totals {
+    pub requests: u64,
+    pub connects_ok: u64,
+    pub connects_failed: u64,
+    pub bytes_forwarded: u64,
+    pub bytes_read: u64,
+    pub decode_failures: u64,
+}
+
+impl Totals {
+    /// 以逗号分隔的key=value形式输出
+    pub fn summary(&self) -> String {
+        format!(
+            "requests={},connects_ok={},connects_failed={},bytes_forwarded={},bytes_read={},decode_failures={}",
+            self.requests,
+            self.connects_ok,
+            self.connects_failed,
+            self.bytes_forwarded,
+            self.bytes_read,
+            self.decode_failures
+        )
+    }
+}
+
+impl Metrics {
+    /// 记录一次请求及其耗时
+    pub fn observe_request(&self, cmd: &str, elapsed: Duration) {
+        let index = COMMANDS
+            .iter()
+            .position(|c| *c == cmd)
+            .unwrap_or(COMMANDS.len() - 1);
+        self.requests[index].observe(elapsed);
+    }
+
+    /// 记录CONNECT结果
+    pub fn connect(&self, ok: bool) {
+        let counter = if ok {
+            &self.connects_ok
+        } else {
+            &self.connects_failed
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 记录FORWARD发往目标的字节数
+    pub fn forwarded(&self, bytes: usize) {
+        self.bytes_forwarded
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// 记录READ返回给客户端的字节数
+    pub fn read(&self, bytes: usize) {
+        self.bytes_read.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// 记录一次解码失败
+    pub fn decode_failure(&self, stage: DecodeStage) {
+        self.decode_failures[stage as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 启动以来的累计统计
+    pub fn totals(&self) -> Totals {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        Totals {
+            requests: self.requests.iter().map(|h| load(&h.count)).sum(),
+            connects_ok: load(&self.connects_ok),
+            connects_failed: load(&self.connects_failed),
+            bytes_forwarded: load(&self.bytes_forwarded),
+            bytes_read: load(&self.bytes_read),
+            decode_failures: self.decode_failures.iter().map(load).sum(),
+        }
+    }
+
+    /// 以Prometheus文本格式输出
+    pub fn render(&self, sessions: SessionGauges) -> String {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let mut out = String::new();
+
+        metric(
+            &mut out,
+            "neorust_sessions_active",
+            "gauge",
+            "Open tunnel sessions",
+        );
+        let _ = writeln!(out, "neorust_sessions_active {}", sessions.active);
+
+        metric(
+            &mut out,
+            "neorust_session_buffered_bytes",
+            "gauge",
+            "Bytes queued in session channels",
+        );
+        let _ = writeln!(
+            out,
+            "neorust_session_buffered_bytes{{direction=\"to_target\"}} {}",
+            sessions.buffered_write
+        );
+        let _ = writeln!(
+            out,
+            "neorust_session_buffered_bytes{{direction=\"to_client\"}} {}",
+            sessions.buffered_read
+        );
+
+        metric(
+            &mut out,
+            "neorust_connects_total",
+            "counter",
+            "CONNECT commands by outcome",
+        );
+        let _ = writeln!(
+            out,
+            "neorust_connects_total{{result=\"ok\"}} {}",
+            load(&self.connects_ok)
+        );
+        let _ = writeln!(
+            out,
+            "neorust_connects_total{{result=\"fail\"}} {}",
+            load(&self.connects_failed)
+        );
+
+        metric(
+            &mut out,
+            "neorust_bytes_forwarded_total",
+            "counter",
+            "Bytes sent to targets by FORWARD",
+        );
+        let _ = writeln!(
+            out,
+            "neorust_bytes_forwarded_total {}",
+            load(&self.bytes_forwarded)
+        );
+        metric(
+            &mut out,
+            "neorust_bytes_read_total",
+            "counter",
+            "Bytes returned to clients by READ",
+        );
+        let _ = writeln!(out, "neorust_bytes_read_total {}", load(&self.bytes_read));
+
+        metric(
+            &mut out,
+            "neorust_decode_failures_total",
+            "counter",
+            "Requests rejected before dispatch",
+        );
+        for (stage, counter) in DECODE_STAGES.iter().zip(&self.decode_failures) {
+            let _ = writeln!(
+                out,
+                "neorust_decode_failures_total{{stage=\"{}\"}} {}",
+                stage,
+                load(counter)
+            );
+        }
+
+        metric(
+            &mut out,
+            "neorust_request_duration_seconds",
+            "histogram",
+            "Request handling time by command",
+        );
+        for (cmd, histogram) in COMMANDS.iter().zip(&self.requests) {
+            for (le, bucket) in BUCKETS.iter().zip(&histogram.buckets) {
+                let _ = writeln!(
+                    out,
+                    "neorust_request_duration_seconds_bucket{{cmd=\"{}\",le=\"{}\"}} {}",
+                    cmd,
+                    le,
+                    load(bucket)
+                );
+            }
+            let count = load(&histogram.count);
+            let _ = writeln!(
+                out,
+                "neorust_request_duration_seconds_bucket{{cmd=\"{}\",le=\"+Inf\"}} {}",
+                cmd, count
+            );
+            let _ = writeln!(
+                out,
+                "neorust_request_duration_seconds_sum{{cmd=\"{}\"}} {}",
+                cmd,
+                load(&histogram.sum_micros) as f64 / 1e6
+            );
+            let _ = writeln!(
+                out,
+                "neorust_request_duration_seconds_count{{cmd=\"{}\"}} {}",
+                cmd, count
+            );
+        }
+        out
+    }
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// 采集当前会话状态
+pub async fn session_gauges(ctx: &Context) -> SessionGauges {
+    let stats = session_stats(&ctx.sessions).await;
+    let mut gauges = SessionGauges {
+        active: stats.len(),
+        ..SessionGauges::default()
+    };
+    for (_, stats) in &stats {
+        gauges.buffered_write += stats.buffered_write;
+        gauges.buffered_read += stats.buffered_read;
+    }
+    gauges
+}
+
+/// 指标监听器：GET /metrics 返回Prometheus文本格式，收到退出请求后停止接收连接
+pub async fn serve(listener: TcpListener, ctx: Context) {
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = ctx.shutdown_requested() => return,
+        };
+        let stream = match accepted {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                log::error!("Metrics accept error: {}", e);
+                continue;
+            }
+        };
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            let _ = http::serve(stream, &ctx, |request| respond(request, &ctx)).await;
+        });
+    }
+}
+
+async fn respond(request: Request, ctx: &Context) -> Response {
+    if request.url != "/metrics" {
+        return Response {
+            body: b"Not Found".to_vec(),
+            ..Response::status("404 Not Found")
+        };
+    }
+    Response {
+        content_type: "text/plain; version=0.0.4",
+        ..Response::text(ctx.metrics.render(session_gauges(ctx).await).into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 测试直方图的累计桶和计数
+    #[test]
+    fn test_histogram() {
+        let metrics = Metrics::default();
+        metrics.observe_request("READ", Duration::from_millis(3));
+        metrics.observe_request("READ", Duration::from_millis(300));
+        metrics.observe_request("BOGUS", Duration::from_secs(10));
+
+        let text = metrics.render(SessionGauges::default());
+        assert!(
+            text.contains("neorust_request_duration_seconds_bucket{cmd=\"READ\",le=\"0.001\"} 0\n")
+        );
+        assert!(
+            text.contains("neorust_request_duration_seconds_bucket{cmd=\"READ\",le=\"0.005\"} 1\n")
+        );
+        assert!(
+            text.contains("neorust_request_duration_seconds_bucket{cmd=\"READ\",le=\"0.5\"} 2\n")
+        );
+        assert!(text.contains("neorust_request_duration_seconds_count{cmd=\"READ\"} 2\n"));
+        assert!(
+            text.contains("neorust_request_duration_seconds_bucket{cmd=\"other\",le=\"5\"} 0\n")
+        );
+        assert!(
+            text.contains("neorust_request_duration_seconds_bucket{cmd=\"other\",le=\"+Inf\"} 1\n")
+        );
+    }
+
+    // 测试计数器与会话指标的输出
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.connect(true);
+        metrics.connect(false);
+        metrics.connect(false);
+        metrics.forwarded(100);
+        metrics.read(42);
+        metrics.decode_failure(DecodeStage::Auth);
+
+        let text = metrics.render(SessionGauges {
+            active: 3,
+            buffered_write: 7,
+            buffered_read: 9,
+        });
+        for line in [
+            "neorust_sessions_active 3",
+            "neorust_session_buffered_bytes{direction=\"to_target\"} 7",
+            "neorust_session_buffered_bytes{direction=\"to_client\"} 9",
+            "neorust_connects_total{result=\"ok\"} 1",
+            "neorust_connects_total{result=\"fail\"} 2",
+            "neorust_bytes_forwarded_total 100",
+            "neorust_bytes_read_total 42",
+            "neorust_decode_failures_total{stage=\"auth\"} 1",
+            "neorust_decode_failures_total{stage=\"base64\"} 0",
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {}", line);
+        }
+
+        metrics.observe_request("READ", Duration::from_millis(1));
+        assert_eq!(
+            metrics.totals().summary(),
+            "requests=1,connects_ok=1,connects_failed=2,bytes_forwarded=100,bytes_read=42,decode_failures=1"
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use crate::codec::{BlvMap, MessageField, read_be_u32};
+
+/// 本服务端实现的协议版本，官方neoreg客户端视为版本0
+pub const PROTOCOL_VERSION: u32 = 4;
+
+/// 能力位：支持AEAD封装层（已配置密钥）
+#[cfg_attr(not(feature = "aead"), allow(dead_code))]
+pub const CAP_AEAD: u32 = 0x01;
+/// 能力位：支持HANDSHAKE前向安全握手
+#[cfg_attr(not(feature = "aead"), allow(dead_code))]
+pub const CAP_KEX: u32 = 0x02;
+/// 能力位：支持Data字段deflate压缩
+#[cfg_attr(not(feature = "compress"), allow(dead_code))]
+pub const CAP_DEFLATE: u32 = 0x04;
+/// 能力位：要求请求携带HMAC认证字段（已配置认证密钥）
+#[cfg_attr(not(feature = "auth"), allow(dead_code))]
+pub const CAP_AUTH: u32 = 0x08;
+
+// 扩展字段登记项
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Extension {
+    pub field: MessageField,
+    pub name: &'static str,
+    /// 引入该字段的协议版本
+    pub since: u32,
+}
+
+/// 扩展字段注册表
+///
+/// 新增字段时在此登记，id需避开neoreg已使用的字段（0-9、39）。
+/// 响应中的扩展字段只发给声明了对应协议版本或自己发送过该字段的客户端，
+/// 因此从不发送扩展字段的旧客户端不受影响。
+pub const EXTENSIONS: &[Extension] = &[
+    Extension {
+        field: MessageField::Flags,
+        name: "flags",
+        since: 1,
+    },
+    Extension {
+        field: MessageField::Version,
+        name: "version",
+        since: 1,
+    },
+    Extension {
+        field: MessageField::Caps,
+        name: "caps",
+        since: 1,
+    },
+    Extension {
+        field: MessageField::Stats,
+        name: "stats",
+        since: 2,
+    },
+    Extension {
+        field: MessageField::Auth,
+        name: "auth",
+        since: 3,
+    },
+    Extension {
+        field: MessageField::Admin,
+        name: "admin",
+        since: 4,
+    },
+];
+
+/// 按字段id查找扩展字段
+pub fn lookup_extension(id: i32) -> Option<&'static Extension> {
+    EXTENSIONS.iter().find(|ext| i32::from(ext.field) == id)
+}
+
+/// 客户端声明的协议版本，未声明时为0
+pub fn peer_version(info: &BlvMap) -> u32 {
+    read_be_u32(info, MessageField::Version)
+}
+
+/// 删除对端不认识的扩展字段
+pub fn retain_supported(info: &BlvMap, rinfo: &mut BlvMap) {
+    let version = peer_version(info);
+    rinfo.retain(|id, _| match lookup_extension(*id) {
+        Some(ext) => version >= ext.since || info.contains_key(id),
+        None => true,
+    });
+}
+
+/// 处理NEGOTIATE命令：交换协议版本和能力位
+///
+/// 返回双方都支持的最高版本，以及客户端能力与服务端能力的交集。
+pub fn handle_negotiate(info: &BlvMap, server_caps: u32, rinfo: &mut BlvMap) {
+    let version = peer_version(info).min(PROTOCOL_VERSION);
+    let caps = read_be_u32(info, MessageField::Caps) & server_caps;
+
+    rinfo.insert(MessageField::Status.into(), b"OK".to_vec());
+    rinfo.insert(MessageField::Version.into(), version.to_be_bytes().to_vec());
+    rinfo.insert(MessageField::Caps.into(), caps.to_be_bytes().to_vec());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 测试扩展字段id唯一，且不与neoreg已使用的字段冲突
+    #[test]
+    fn test_extension_ids() {
+        for (i, ext) in EXTENSIONS.iter().enumerate() {
+            let id = i32::from(ext.field);
+            assert!(!(0..=9).contains(&id) && id != 39, "{} collides", ext.name);
+            assert!(id <= u8::MAX as i32);
+            assert!(
+                EXTENSIONS[i + 1..]
+                    .iter()
+                    .all(|other| other.field != ext.field)
+            );
+            assert_eq!(lookup_extension(id), Some(ext));
+        }
+        assert!(lookup_extension(MessageField::Data.into()).is_none());
+    }
+
+    // 测试版本与能力协商
+    #[test]
+    fn test_negotiate() {
+        let mut info = BlvMap::new();
+        info.insert(MessageField::Version.into(), 7u32.to_be_bytes().to_vec());
+        info.insert(
+            MessageField::Caps.into(),
+            vec![CAP_DEFLATE as u8 | CAP_KEX as u8],
+        );
+
+        let mut rinfo = BlvMap::new();
+        handle_negotiate(&info, CAP_DEFLATE | CAP_AEAD, &mut rinfo);
+        assert_eq!(read_be_u32(&rinfo, MessageField::Version), PROTOCOL_VERSION);
+        assert_eq!(read_be_u32(&rinfo, MessageField::Caps), CAP_DEFLATE);
+    }
+
+    // 测试旧客户端不会收到扩展字段
+    #[test]
+    fn test_retain_supported() {
+        let mut rinfo = BlvMap::new();
+        rinfo.insert(MessageField::Status.into(), b"OK".to_vec());
+        rinfo.insert(MessageField::Flags.into(), vec![1]);
+        rinfo.insert(MessageField::Caps.into(), vec![1]);
+
+        let mut legacy = rinfo.clone();
+        retain_supported(&BlvMap::new(), &mut legacy);
+        assert_eq!(legacy.len(), 1);
+
+        let mut info = BlvMap::new();
+        info.insert(MessageField::Flags.into(), vec![1]);
+        let mut partial = rinfo.clone();
+        retain_supported(&info, &mut partial);
+        assert!(partial.contains_key(&MessageField::Flags.into()));
+        assert!(!partial.contains_key(&MessageField::Caps.into()));
+
+        info.insert(MessageField::Version.into(), vec![1]);
+        let mut current = rinfo.clone();
+        retain_supported(&info, &mut current);
+        assert_eq!(current, rinfo);
+    }
+}
@@ -0,0 +1,129 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::log;
+
+/// 同一个旧值仍被使用时，两次警告日志的最小间隔
+const DEPRECATED_WARN_SECS: u64 = 60;
+
+// 轮换前的值，到期前仍可识别请求
+struct Retired<T> {
+    value: T,
+    not_after: SystemTime,
+    /// 上次输出警告的时间（Unix秒）
+    warned_at: AtomicU64,
+}
+
+/// 轮换中的密码或编码表：当前值和到期前仍被接受的旧值
+///
+/// 请求先用当前值识别，失败时依次尝试未到期的旧值；响应使用识别出请求的那个值。
+#[derive(Clone)]
+pub struct Rotation<T> {
+    /// 写入警告日志的名称，例如`key`
+    label: &'static str,
+    current: T,
+    previous: Vec<Arc<Retired<T>>>,
+}
+
+impl<T> Rotation<T> {
+    pub fn new(label: &'static str, current: T) -> Self {
+        Rotation {
+            label,
+            current,
+            previous: Vec::new(),
+        }
+    }
+
+    /// 同时接受轮换前的旧值，直到not_after
+    pub fn with_previous(mut self, value: T, not_after: SystemTime) -> Self {
+        self.previous.push(Arc::new(Retired {
+            value,
+            not_after,
+            warned_at: AtomicU64::new(0),
+        }));
+        self
+    }
+
+    pub fn current(&self) -> &T {
+        &self.current
+    }
+
+    /// 是否配置了旧值
+    pub fn rotating(&self) -> bool {
+        !self.previous.is_empty()
+    }
+
+    /// 依次用当前值和未到期的旧值尝试，返回第一个成功的结果
+    ///
+    /// 全部失败时返回最后一次尝试的错误。旧值被使用时输出限频的警告。
+    pub fn try_each<'a, R, E>(
+        &'a self,
+        mut attempt: impl FnMut(&'a T) -> Result<R, E>,
+    ) -> Result<R, E> {
+        let mut result = attempt(&self.current);
+        if result.is_ok() {
+            return result;
+        }
+        let now = SystemTime::now();
+        for retired in self.previous.iter().filter(|r| now <= r.not_after) {
+            result = attempt(&retired.value);
+            if result.is_ok() {
+                self.warn(retired, now);
+                return result;
+            }
+        }
+        result
+    }
+
+    // 限制警告频率，避免旧客户端的每个请求都输出一行
+    fn warn(&self, retired: &Retired<T>, now: SystemTime) {
+        let secs = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let last = retired.warned_at.load(Ordering::Relaxed);
+        if secs >= last + DEPRECATED_WARN_SECS
+            && retired
+                .warned_at
+                .compare_exchange(last, secs, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            let until = retired
+                .not_after
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            log::warn!(
+                "Request used a deprecated {}, valid until {}",
+                self.label,
+                log::timestamp(until)
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // 测试依次尝试当前值和未到期的旧值，过期的旧值不再被接受
+    #[test]
+    fn test_try_each() {
+        let now = SystemTime::now();
+        let rotation = Rotation::new("key", "new")
+            .with_previous("old", now + Duration::from_secs(3600))
+            .with_previous("expired", now - Duration::from_secs(1));
+        assert!(rotation.rotating());
+        let matching = |wanted: &str| {
+            rotation.try_each(|value| {
+                if *value == wanted {
+                    Ok(*value)
+                } else {
+                    Err(*value)
+                }
+            })
+        };
+        assert_eq!(matching("new"), Ok("new"));
+        assert_eq!(matching("old"), Ok("old"));
+        assert_eq!(matching("expired"), Err("old"));
+        assert!(!Rotation::new("key", "new").rotating());
+    }
+}
@@ -1,6 +1,7 @@
-use std::net::TcpStream;
+use std::net::{Shutdown, SocketAddr, TcpStream};
 use std::sync::Arc;
-use std::time::Duration;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
@@ -20,9 +21,12 @@ mod tests {
         // 启动服务器接受连接
         tokio::spawn(async move {
             if let Ok((stream, _)) = listener.accept() {
+                stream
+                    .set_nonblocking(true)
+                    .expect("Failed to set nonblocking");
                 let mut stream = tokio::net::TcpStream::from_std(stream)
                     .expect("Failed to convert to async TcpStream");
-                
+
                 // 读取客户端发送的数据
                 let mut buf = [0; 1024];
                 if let Ok(n) = stream.read(&mut buf).await {
@@ -41,7 +45,7 @@ mod tests {
             .expect("Failed to convert to std TcpStream");
 
         // 创建会话
-        let session = Session::new(std_stream);
+        let session = Session::new(std_stream, &SessionOptions::default());
 
         // 测试写入数据
         let test_data = b"Hello, Session!";
@@ -59,6 +63,15 @@ mod tests {
 
         // 验证读取的数据与发送的数据一致
         assert_eq!(read_result, test_data);
+        let len = test_data.len() as u64;
+        assert_eq!(session.traffic(), (len, len));
+        assert_eq!(session.target(), Some(addr));
+        let stats = session.stats();
+        assert_eq!((stats.bytes_sent, stats.bytes_received), (len, len));
+        assert_eq!((stats.messages_sent, stats.messages_received), (1, 1));
+        assert_eq!((stats.buffered_write, stats.buffered_read), (0, 0));
+        assert_eq!(stats.peak_buffered_read, len);
+        assert_eq!(stats.target, Some(addr));
 
         // 测试关闭会话
         session.close().await;
@@ -77,15 +90,14 @@ mod tests {
         let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
         let addr = listener.local_addr().unwrap();
         let stream1 = tokio::net::TcpStream::connect(addr).await.unwrap();
-        let (stream2, _) = listener.accept().await.unwrap();
-            
+        let (_stream2, _) = listener.accept().await.unwrap();
 
         let std_stream1 = stream1
             .into_std()
             .expect("Failed to convert to std TcpStream");
 
         // 创建会话
-        let session = Session::new(std_stream1);
+        let session = Session::new(std_stream1, &SessionOptions::default());
 
         // 确保会话未关闭
         assert!(!session.is_closed().await);
@@ -110,14 +122,13 @@ mod tests {
         let addr = listener.local_addr().unwrap();
         let stream1 = tokio::net::TcpStream::connect(addr).await.unwrap();
         let (_stream2, _) = listener.accept().await.unwrap();
-            
 
         let std_stream1 = stream1
             .into_std()
             .expect("Failed to convert to std TcpStream");
 
         // 创建会话
-        let session = Session::new(std_stream1);
+        let session = Session::new(std_stream1, &SessionOptions::default());
 
         // 关闭会话
         session.close().await;
@@ -130,22 +141,207 @@ mod tests {
         assert!(result.is_err());
         assert!(matches!(result, Err(NeoError::SessionClosed)));
     }
+
+    // 测试关闭会话后目标收到FIN
+    #[tokio::test]
+    async fn test_close_shuts_down_target() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (mut target, _) = listener.accept().await.unwrap();
+
+        let session = Session::new(stream.into_std().unwrap(), &SessionOptions::default());
+        session.close().await;
+
+        let mut buf = [0; 16];
+        let n = tokio::time::timeout(Duration::from_secs(1), target.read(&mut buf))
+            .await
+            .expect("Target was not shut down")
+            .expect("Read failed");
+        assert_eq!(n, 0);
+    }
+
+    // 测试交接会话：目标不会收到FIN，未READ的数据和统计在接管后保留
+    #[tokio::test]
+    async fn test_detach_and_resume() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (mut target, _) = listener.accept().await.unwrap();
+
+        let session = Session::new(stream.into_std().unwrap(), &SessionOptions::default())
+            .with_client(Some("192.0.2.1:4000".parse().unwrap()));
+        session.write_async(b"ping").await.unwrap();
+        target.write_all(b"pong").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let (stream, handover) = session.detach().await.expect("Detach failed");
+        assert_eq!(handover.pending, b"pong");
+        assert_eq!(handover.bytes_sent, 4);
+        let mut buf = [0; 16];
+        let n = target.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"ping");
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), target.read(&mut buf))
+                .await
+                .is_err()
+        );
+
+        let resumed = Session::resume(stream, &SessionOptions::default(), handover);
+        assert_eq!(resumed.read_async().await.unwrap(), b"pong");
+        resumed.write_async(b"again").await.unwrap();
+        let n = target.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"again");
+        let stats = resumed.stats();
+        assert_eq!(stats.client, Some("192.0.2.1:4000".parse().unwrap()));
+        assert_eq!((stats.bytes_sent, stats.bytes_received), (9, 4));
+        assert_eq!(stats.target, Some(addr));
+    }
 }
-use tokio::sync::{Mutex, mpsc};
+use tokio::sync::{Mutex, mpsc, watch};
+use tokio::task::JoinHandle;
 use tokio::time::timeout;
 
 use crate::errors::NeoError;
+use crate::log;
 
 const CHANNEL_CAPACITY: usize = 1024;
 const BUFFER_SIZE: usize = 1024;
 const TIMEOUT_MS: u64 = 10;
 
+// 会话参数
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SessionOptions {
+    /// 读写通道容量（数据块个数）
+    pub channel_capacity: usize,
+    /// 单次从目标读取的缓冲区大小
+    pub buffer_size: usize,
+    /// READ在无数据时的等待时间
+    pub read_timeout_ms: u64,
+}
+
+impl Default for SessionOptions {
+    fn default() -> Self {
+        SessionOptions {
+            channel_capacity: CHANNEL_CAPACITY,
+            buffer_size: BUFFER_SIZE,
+            read_timeout_ms: TIMEOUT_MS,
+        }
+    }
+}
+
+// 会话计数器，由会话句柄和读写任务共享
+#[derive(Default)]
+struct Counters {
+    /// 发往目标的字节数
+    bytes_sent: AtomicU64,
+    /// 从目标收到并交给客户端的字节数
+    bytes_received: AtomicU64,
+    messages_sent: AtomicU64,
+    messages_received: AtomicU64,
+    /// 通道中等待写入目标的字节数
+    pending_write: AtomicU64,
+    /// 通道中等待客户端READ的字节数
+    pending_read: AtomicU64,
+    peak_write: AtomicU64,
+    peak_read: AtomicU64,
+}
+
+// 数据进入通道时增加缓存计数并更新峰值
+fn buffer(pending: &AtomicU64, peak: &AtomicU64, bytes: u64) {
+    let now = pending.fetch_add(bytes, Ordering::Relaxed) + bytes;
+    peak.fetch_max(now, Ordering::Relaxed);
+}
+
+// 会话统计快照
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionStats {
+    pub target: Option<SocketAddr>,
+    /// 发起CONNECT的客户端地址
+    pub client: Option<SocketAddr>,
+    /// 发起CONNECT的客户端证书身份
+    pub identity: Option<String>,
+    /// 会话所属的租户
+    pub tenant: Option<String>,
+    pub created_at: SystemTime,
+    pub age: Duration,
+    /// 距最后一次收发数据的时间
+    pub idle: Duration,
+    /// 连接目标的耗时
+    pub connect_latency: Duration,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// FORWARD写入次数
+    pub messages_sent: u64,
+    /// 返回了数据的READ次数
+    pub messages_received: u64,
+    pub buffered_write: u64,
+    pub buffered_read: u64,
+    pub peak_buffered_write: u64,
+    pub peak_buffered_read: u64,
+}
+
+impl SessionStats {
+    /// 以逗号分隔的key=value形式输出，用于日志和DISCONNECT响应
+    pub fn summary(&self) -> String {
+        format!(
+            "age_ms={},idle_ms={},connect_ms={},bytes_sent={},bytes_received={},messages_sent={},messages_received={},peak_buffered_write={},peak_buffered_read={}",
+            self.age.as_millis(),
+            self.idle.as_millis(),
+            self.connect_latency.as_millis(),
+            self.bytes_sent,
+            self.bytes_received,
+            self.messages_sent,
+            self.messages_received,
+            self.peak_buffered_write,
+            self.peak_buffered_read
+        )
+    }
+}
+
+// 交接给新进程的会话状态，连接本身单独传递
+#[cfg_attr(not(unix), allow(dead_code))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Handover {
+    pub client: Option<SocketAddr>,
+    pub identity: Option<String>,
+    pub tenant: Option<String>,
+    pub created_at: SystemTime,
+    pub connect_latency: Duration,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    /// 已从目标读取、尚未被客户端READ取走的数据
+    pub pending: Vec<u8>,
+}
+
 // 会话结构体
 #[derive(Clone)]
 pub struct Session {
     tx: mpsc::Sender<Vec<u8>>,
     rx_buffer: Arc<Mutex<mpsc::Receiver<Vec<u8>>>>,
     closed: Arc<Mutex<bool>>,
+    /// 关闭会话时用于关闭到目标的连接
+    stream: Arc<TcpStream>,
+    last_active: Arc<std::sync::Mutex<Instant>>,
+    read_timeout: Duration,
+    target: Option<SocketAddr>,
+    /// 发起CONNECT的客户端地址
+    client: Option<SocketAddr>,
+    /// 发起CONNECT的客户端证书身份，会话只能由同一身份操作
+    identity: Option<String>,
+    /// 会话所属的租户，只能由同一租户的请求操作
+    tenant: Option<String>,
+    opened_at: Instant,
+    created_at: SystemTime,
+    connect_latency: Duration,
+    counters: Arc<Counters>,
+    /// 置为true时读写任务停止并保留连接，用于交接给新进程
+    #[cfg_attr(not(unix), allow(dead_code))]
+    detaching: Arc<watch::Sender<bool>>,
+    #[cfg_attr(not(unix), allow(dead_code))]
+    tasks: Arc<std::sync::Mutex<Vec<JoinHandle<()>>>>,
 }
 
 impl Session {
@@ -153,31 +349,111 @@ impl Session {
     ///
     /// 会启动两个异步任务：一个用于从流中读取数据并存储到缓冲区，
     /// 另一个用于从通道接收数据并写入到流中。
-    pub fn new(stream: TcpStream) -> Self {
+    pub fn new(stream: TcpStream, options: &SessionOptions) -> Self {
+        Self::start(stream, options, Vec::new())
+    }
+
+    /// 接管旧进程交接的会话，恢复创建时间、统计和尚未被READ取走的数据
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pub fn resume(stream: TcpStream, options: &SessionOptions, handover: Handover) -> Self {
+        let mut session = Self::start(stream, options, handover.pending)
+            .with_client(handover.client)
+            .with_identity(handover.identity)
+            .with_tenant(handover.tenant)
+            .with_connect_latency(handover.connect_latency);
+        let age = SystemTime::now()
+            .duration_since(handover.created_at)
+            .unwrap_or_default();
+        session.opened_at = Instant::now().checked_sub(age).unwrap_or(session.opened_at);
+        session.created_at = handover.created_at;
+        let counters = &session.counters;
+        counters
+            .bytes_sent
+            .store(handover.bytes_sent, Ordering::Relaxed);
+        counters
+            .bytes_received
+            .store(handover.bytes_received, Ordering::Relaxed);
+        counters
+            .messages_sent
+            .store(handover.messages_sent, Ordering::Relaxed);
+        counters
+            .messages_received
+            .store(handover.messages_received, Ordering::Relaxed);
+        session
+    }
+
+    // 创建会话并启动读写任务，pending先于目标的数据放入读缓冲
+    fn start(stream: TcpStream, options: &SessionOptions, pending: Vec<u8>) -> Self {
+        // tokio要求注册的套接字为非阻塞模式
+        stream
+            .set_nonblocking(true)
+            .expect("Failed to set stream nonblocking");
+        let target = stream.peer_addr().ok();
+
         // 克隆TcpStream，为两个异步任务提供独立实例
         let read_stream = stream
             .try_clone()
-            .map_err(|e| NeoError::Io(e))
+            .map_err(NeoError::Io)
             .expect("Failed to clone stream");
         let write_stream = stream
             .try_clone()
-            .map_err(|e| NeoError::Io(e))
+            .map_err(NeoError::Io)
             .expect("Failed to clone stream");
 
         // 明确指定通道传输类型为Vec<u8>
-        let (tx_write, rx_write) = mpsc::channel::<Vec<u8>>(CHANNEL_CAPACITY);
-        let (tx_buffer, rx_buffer) = mpsc::channel::<Vec<u8>>(CHANNEL_CAPACITY);
+        let (tx_write, rx_write) = mpsc::channel::<Vec<u8>>(options.channel_capacity);
+        let (tx_buffer, rx_buffer) = mpsc::channel::<Vec<u8>>(options.channel_capacity);
         let closed = Arc::new(Mutex::new(false));
         let rx_buffer = Arc::new(Mutex::new(rx_buffer));
+        let counters = Arc::new(Counters::default());
+        let (detaching, _) = watch::channel(false);
+        if !pending.is_empty() {
+            buffer(
+                &counters.pending_read,
+                &counters.peak_read,
+                pending.len() as u64,
+            );
+            tx_buffer
+                .try_send(pending)
+                .expect("new channel has capacity");
+        }
 
         // 启动读写任务
-        Self::start_read_task(read_stream, tx_buffer, Arc::clone(&closed));
-        Self::start_write_task(write_stream, rx_write, Arc::clone(&closed));
+        let tasks = vec![
+            Self::start_read_task(
+                read_stream,
+                tx_buffer,
+                Arc::clone(&closed),
+                options.buffer_size,
+                Arc::clone(&counters),
+                detaching.subscribe(),
+            ),
+            Self::start_write_task(
+                write_stream,
+                rx_write,
+                Arc::clone(&closed),
+                Arc::clone(&counters),
+                detaching.subscribe(),
+            ),
+        ];
 
         Session {
             tx: tx_write,
             rx_buffer,
             closed,
+            stream: Arc::new(stream),
+            last_active: Arc::new(std::sync::Mutex::new(Instant::now())),
+            read_timeout: Duration::from_millis(options.read_timeout_ms),
+            target,
+            client: None,
+            identity: None,
+            tenant: None,
+            opened_at: Instant::now(),
+            created_at: SystemTime::now(),
+            connect_latency: Duration::ZERO,
+            counters,
+            detaching: Arc::new(detaching),
+            tasks: Arc::new(std::sync::Mutex::new(tasks)),
         }
     }
 
@@ -188,15 +464,23 @@ impl Session {
         stream: TcpStream,
         tx_buffer: mpsc::Sender<Vec<u8>>,
         closed: Arc<Mutex<bool>>,
-    ) {
-        tokio::spawn(async move {
+        buffer_size: usize,
+        counters: Arc<Counters>,
+        mut detaching: watch::Receiver<bool>,
+    ) -> JoinHandle<()> {
+        tokio::spawn(log::scope(log::current(), async move {
             let mut stream = tokio::net::TcpStream::from_std(stream)
-                .map_err(|e| NeoError::Io(e))
+                .map_err(NeoError::Io)
                 .expect("Failed to convert to async TcpStream");
-            let mut buf = [0; BUFFER_SIZE];
+            let mut buf = vec![0; buffer_size];
 
             while !*closed.lock().await {
-                match stream.read(&mut buf).await {
+                // 交接给新进程时停止读取，连接保持打开
+                let read = tokio::select! {
+                    read = stream.read(&mut buf) => read,
+                    Ok(_) = detaching.wait_for(|d| *d) => return,
+                };
+                match read {
                     Ok(n) => {
                         if n == 0 {
                             // 连接关闭
@@ -205,14 +489,16 @@ impl Session {
                         }
                         // 发送数据到通道
                         let data = buf[..n].to_vec();
-                        if let Err(_e) = tx_buffer.send(data).await {
-                            // eprintln!("Send to buffer channel error: {}", e);
+                        buffer(&counters.pending_read, &counters.peak_read, n as u64);
+                        if let Err(e) = tx_buffer.send(data).await {
+                            counters.pending_read.fetch_sub(n as u64, Ordering::Relaxed);
+                            log::debug!("Send to buffer channel error: {}", e);
                             *closed.lock().await = true;
                             break;
                         }
                     }
                     Err(e) => {
-                        eprintln!("Read error: {}", e);
+                        log::debug!("Read error: {}", e);
                         *closed.lock().await = true;
                         break;
                     }
@@ -220,9 +506,9 @@ impl Session {
             }
             // 尝试优雅关闭
             if let Err(e) = stream.shutdown().await {
-                eprintln!("Stream shutdown error: {}", e);
+                log::debug!("Stream shutdown error: {}", e);
             }
-        });
+        }))
     }
 
     /// 启动写入任务
@@ -232,13 +518,36 @@ impl Session {
         stream: TcpStream,
         mut rx: mpsc::Receiver<Vec<u8>>,
         closed: Arc<Mutex<bool>>,
-    ) {
-        tokio::spawn(async move {
+        counters: Arc<Counters>,
+        mut detaching: watch::Receiver<bool>,
+    ) -> JoinHandle<()> {
+        tokio::spawn(log::scope(log::current(), async move {
             let mut stream = tokio::net::TcpStream::from_std(stream)
-                .map_err(|e| NeoError::Io(e))
+                .map_err(NeoError::Io)
                 .expect("Failed to convert to async TcpStream");
 
-            while let Some(data) = rx.recv().await {
+            // 交接给新进程时不再接收新数据，写完通道中已有的数据后退出，连接保持打开
+            let mut detached = false;
+            loop {
+                let data = if detached {
+                    rx.recv().await
+                } else {
+                    tokio::select! {
+                        data = rx.recv() => data,
+                        Ok(_) = detaching.wait_for(|d| *d) => {
+                            detached = true;
+                            rx.close();
+                            continue;
+                        }
+                    }
+                };
+                let Some(data) = data else {
+                    break;
+                };
+                counters
+                    .pending_write
+                    .fetch_sub(data.len() as u64, Ordering::Relaxed);
+
                 // 检查关闭状态
                 if *closed.lock().await {
                     break;
@@ -246,16 +555,19 @@ impl Session {
 
                 // 写入数据
                 if let Err(e) = stream.write_all(&data).await {
-                    eprintln!("Write error: {}", e);
+                    log::debug!("Write error: {}", e);
                     *closed.lock().await = true;
                     break;
                 }
             }
+            if detached {
+                return;
+            }
             // 尝试优雅关闭
             if let Err(e) = stream.shutdown().await {
-                eprintln!("Stream shutdown error: {}", e);
+                log::debug!("Stream shutdown error: {}", e);
             }
-        });
+        }))
     }
 
     /// 异步写入方法
@@ -265,17 +577,76 @@ impl Session {
             return Err(NeoError::SessionClosed);
         }
 
+        let counters = &self.counters;
+        buffer(
+            &counters.pending_write,
+            &counters.peak_write,
+            data.len() as u64,
+        );
         match self.tx.send(data.to_vec()).await {
-            Ok(()) => Ok(()),
+            Ok(()) => {
+                self.touch();
+                counters
+                    .bytes_sent
+                    .fetch_add(data.len() as u64, Ordering::Relaxed);
+                counters.messages_sent.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
             Err(_) => {
+                counters
+                    .pending_write
+                    .fetch_sub(data.len() as u64, Ordering::Relaxed);
                 *self.closed.lock().await = true;
                 Err(NeoError::Other("Send failed".to_string()))
             }
         }
     }
 
+    /// 关闭会话，并关闭到目标的连接
+    ///
+    /// 读取任务阻塞在读取上时会立即返回，目标随后收到FIN，而不是等到进程退出时被重置。
     pub async fn close(&self) {
         *self.closed.lock().await = true;
+        if let Err(e) = self.stream.shutdown(Shutdown::Both) {
+            log::debug!("Stream shutdown error: {}", e);
+        }
+    }
+
+    /// 停止读写任务并交出到目标的连接，用于交接给新进程
+    ///
+    /// 目标不会收到FIN；写入通道中的数据先写入目标，已读取但未被READ取走的数据随状态返回。
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pub async fn detach(&self) -> Result<(TcpStream, Handover), NeoError> {
+        self.detaching.send_replace(true);
+        // 读取任务退出后通道关闭，之前读取的数据全部留在通道中
+        let mut pending = Vec::new();
+        {
+            let mut rx = self.rx_buffer.lock().await;
+            while let Some(data) = rx.recv().await {
+                pending.extend(data);
+            }
+        }
+        let tasks = std::mem::take(&mut *self.tasks.lock().expect("tasks poisoned"));
+        for task in tasks {
+            let _ = task.await;
+        }
+        let stream = self.stream.try_clone().map_err(NeoError::Io)?;
+        let stats = self.stats();
+        Ok((
+            stream,
+            Handover {
+                client: self.client,
+                identity: self.identity.clone(),
+                tenant: self.tenant.clone(),
+                created_at: self.created_at,
+                connect_latency: self.connect_latency,
+                bytes_sent: stats.bytes_sent,
+                bytes_received: stats.bytes_received,
+                messages_sent: stats.messages_sent,
+                messages_received: stats.messages_received,
+                pending,
+            },
+        ))
     }
 
     /// 异步读取缓冲区数据
@@ -291,7 +662,7 @@ impl Session {
 
         // 如果没有数据且连接未关闭，尝试异步接收一个数据块
         if all_data.is_empty() && !closed {
-            match timeout(Duration::from_millis(TIMEOUT_MS), rx.recv()).await {
+            match timeout(self.read_timeout, rx.recv()).await {
                 Ok(Some(data)) => {
                     all_data.extend(data);
                 }
@@ -307,10 +678,124 @@ impl Session {
             return Err(NeoError::SessionClosed);
         }
 
+        if !all_data.is_empty() {
+            self.touch();
+            let counters = &self.counters;
+            counters
+                .pending_read
+                .fetch_sub(all_data.len() as u64, Ordering::Relaxed);
+            counters
+                .bytes_received
+                .fetch_add(all_data.len() as u64, Ordering::Relaxed);
+            counters.messages_received.fetch_add(1, Ordering::Relaxed);
+        }
         Ok(all_data)
     }
 
     pub async fn is_closed(&self) -> bool {
         *self.closed.lock().await
     }
+
+    /// 距最后一次收发数据的时间
+    pub fn idle_for(&self) -> Duration {
+        self.last_active
+            .lock()
+            .expect("last_active poisoned")
+            .elapsed()
+    }
+
+    /// 记录发起CONNECT的客户端地址
+    pub fn with_client(mut self, client: Option<SocketAddr>) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// 记录发起CONNECT的客户端证书身份
+    pub fn with_identity(mut self, identity: Option<String>) -> Self {
+        self.identity = identity;
+        self
+    }
+
+    /// 创建会话的客户端证书身份
+    pub fn identity(&self) -> Option<&str> {
+        self.identity.as_deref()
+    }
+
+    /// 记录会话所属的租户
+    pub fn with_tenant(mut self, tenant: Option<String>) -> Self {
+        self.tenant = tenant;
+        self
+    }
+
+    /// 会话所属的租户
+    pub fn tenant(&self) -> Option<&str> {
+        self.tenant.as_deref()
+    }
+
+    /// 记录连接目标的耗时
+    pub fn with_connect_latency(mut self, latency: Duration) -> Self {
+        self.connect_latency = latency;
+        self
+    }
+
+    /// 连接的目标地址
+    pub fn target(&self) -> Option<SocketAddr> {
+        self.target
+    }
+
+    /// 发起CONNECT的客户端地址
+    pub fn client(&self) -> Option<SocketAddr> {
+        self.client
+    }
+
+    /// 会话建立至今的时间
+    pub fn age(&self) -> Duration {
+        self.opened_at.elapsed()
+    }
+
+    /// 累计的（发送, 接收）字节数
+    pub fn traffic(&self) -> (u64, u64) {
+        (
+            self.counters.bytes_sent.load(Ordering::Relaxed),
+            self.counters.bytes_received.load(Ordering::Relaxed),
+        )
+    }
+
+    /// 通道中缓存的（待写入目标, 待客户端读取）字节数
+    pub fn buffered(&self) -> (u64, u64) {
+        (
+            self.counters.pending_write.load(Ordering::Relaxed),
+            self.counters.pending_read.load(Ordering::Relaxed),
+        )
+    }
+
+    /// 当前统计数据的快照
+    pub fn stats(&self) -> SessionStats {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let counters = &self.counters;
+        let (bytes_sent, bytes_received) = self.traffic();
+        let (buffered_write, buffered_read) = self.buffered();
+        SessionStats {
+            target: self.target(),
+            client: self.client(),
+            identity: self.identity.clone(),
+            tenant: self.tenant.clone(),
+            created_at: self.created_at,
+            age: self.age(),
+            idle: self.idle_for(),
+            connect_latency: self.connect_latency,
+            bytes_sent,
+            bytes_received,
+            messages_sent: load(&counters.messages_sent),
+            messages_received: load(&counters.messages_received),
+            buffered_write,
+            buffered_read,
+            peak_buffered_write: load(&counters.peak_write),
+            peak_buffered_read: load(&counters.peak_read),
+        }
+    }
+
+    fn touch(&self) {
+        *self.last_active.lock().expect("last_active poisoned") = Instant::now();
+    }
 }
@@ -0,0 +1,85 @@
+use std::net::TcpListener;
+use std::os::fd::{FromRawFd, RawFd};
+
+use crate::errors::NeoError;
+
+/// systemd传入的第一个文件描述符
+const LISTEN_FDS_START: RawFd = 3;
+
+/// systemd套接字激活传入的监听套接字（`LISTEN_PID`、`LISTEN_FDS`）
+///
+/// 与`[tls] listen`或`metrics_listen`地址相同的套接字用于对应的服务，其余都用于接收隧道请求；
+/// 未设置或不是传给本进程时返回空。
+pub fn listeners() -> Result<Vec<TcpListener>, NeoError> {
+    activated(
+        |name| std::env::var(name).ok(),
+        std::process::id(),
+        LISTEN_FDS_START,
+    )
+}
+
+fn activated(
+    var: impl Fn(&str) -> Option<String>,
+    pid: u32,
+    start: RawFd,
+) -> Result<Vec<TcpListener>, NeoError> {
+    let (Some(listen_pid), Some(fds)) = (var("LISTEN_PID"), var("LISTEN_FDS")) else {
+        return Ok(Vec::new());
+    };
+    // 环境变量从父进程继承而来（例如升级启动的新进程）时不属于本进程
+    if listen_pid.trim().parse::<u32>().ok() != Some(pid) {
+        return Ok(Vec::new());
+    }
+    let count: RawFd = fds
+        .trim()
+        .parse()
+        .map_err(|_| NeoError::Other(format!("Invalid LISTEN_FDS: {}", fds)))?;
+    (start..start + count)
+        .map(|fd| {
+            // 确认文件描述符有效，并避免被之后启动的进程继承
+            if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+                return Err(NeoError::Other(format!(
+                    "Invalid LISTEN_FDS descriptor {}",
+                    fd
+                )));
+            }
+            let listener = unsafe { TcpListener::from_raw_fd(fd) };
+            match listener.local_addr() {
+                Ok(_) => Ok(listener),
+                Err(e) => Err(NeoError::Other(format!(
+                    "LISTEN_FDS descriptor {} is not a TCP socket: {}",
+                    fd, e
+                ))),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::fd::IntoRawFd;
+
+    // 测试只接受传给本进程的套接字
+    #[test]
+    fn test_activated() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let fd = listener.into_raw_fd();
+        let env = |pid: &'static str, fds: &'static str| {
+            move |name: &str| match name {
+                "LISTEN_PID" => Some(pid.to_string()),
+                "LISTEN_FDS" => Some(fds.to_string()),
+                _ => None,
+            }
+        };
+
+        assert!(activated(|_| None, 42, fd).unwrap().is_empty());
+        assert!(activated(env("41", "1"), 42, fd).unwrap().is_empty());
+        assert!(activated(env("42", "many"), 42, fd).is_err());
+
+        let listeners = activated(env("42", "1"), 42, fd).expect("Activation failed");
+        assert_eq!(listeners.len(), 1);
+        assert_eq!(listeners[0].local_addr().unwrap(), addr);
+    }
+}
@@ -0,0 +1,256 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::acl::Acl;
+#[cfg(feature = "auth")]
+use crate::auth::Authenticator;
+use crate::codec::Codec;
+use crate::config::{Config, PreviousAlphabet, PreviousKey};
+use crate::errors::NeoError;
+use crate::rotation::Rotation;
+
+// 租户配置：共用一个服务端的操作人员各自的密钥和策略
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TenantOptions {
+    /// 租户名，写入日志、审计日志和会话列表
+    pub name: String,
+    /// 自定义Base64编码表，未配置时使用默认编码表
+    pub alphabet: Option<String>,
+    /// 轮换前的旧编码表，到期前仍被接受
+    pub previous_alphabets: Vec<PreviousAlphabet>,
+    /// 请求认证密钥，时间偏差和重放缓存沿用`[auth]`的设置
+    pub auth_key: Option<String>,
+    /// 轮换前的旧认证密钥，到期前仍被接受
+    pub previous_auth_keys: Vec<PreviousKey>,
+    /// 在全局ACL之外额外检查
+    pub acl: Acl,
+    /// 最大并发会话数，0表示不限制
+    pub max_sessions: usize,
+    /// 累计转发流量上限（字节），0表示不限制
+    pub quota_bytes: u64,
+}
+
+impl TenantOptions {
+    /// 用于识别请求的编码表
+    pub fn alphabet(&self) -> &str {
+        self.alphabet
+            .as_deref()
+            .unwrap_or(std::str::from_utf8(crate::DE).expect("DE is ASCII"))
+    }
+
+    /// 可识别请求的所有编码表：当前编码表和旧编码表
+    pub fn alphabets(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.alphabet())
+            .chain(self.previous_alphabets.iter().map(|p| p.alphabet.as_str()))
+    }
+
+    /// 可通过认证的所有密钥：当前密钥和旧密钥
+    pub fn auth_keys(&self) -> impl Iterator<Item = &String> {
+        self.auth_key
+            .iter()
+            .chain(self.previous_auth_keys.iter().map(|p| &p.key))
+    }
+}
+
+/// 检查租户配置：名称唯一，且每个请求只能被一个租户识别
+pub fn validate(tenants: &[TenantOptions]) -> Result<(), NeoError> {
+    for (i, tenant) in tenants.iter().enumerate() {
+        if tenant.name.is_empty() {
+            return Err(NeoError::Other("Tenant name must not be empty".to_string()));
+        }
+        for alphabet in tenant.alphabets() {
+            Codec::new()
+                .with_alphabet(alphabet)
+                .map_err(|e| NeoError::Other(format!("Tenant {}: {}", tenant.name, e)))?;
+        }
+        if !tenant.previous_auth_keys.is_empty() && tenant.auth_key.is_none() {
+            return Err(NeoError::Other(format!(
+                "Tenant {}: previous auth keys require a current auth key",
+                tenant.name
+            )));
+        }
+        if tenant.auth_key.is_some() && !cfg!(feature = "auth") {
+            return Err(NeoError::Other(
+                "Request authentication requires the auth feature".to_string(),
+            ));
+        }
+        if !tenant.acl.trusted_proxies.is_empty() {
+            return Err(NeoError::Other(format!(
+                "Tenant {}: trusted_proxies is only valid in the top-level [acl]",
+                tenant.name
+            )));
+        }
+        for other in &tenants[..i] {
+            if other.name == tenant.name {
+                return Err(NeoError::Other(format!(
+                    "Duplicate tenant name: {}",
+                    tenant.name
+                )));
+            }
+            // 编码表（含轮换中的旧编码表）有重叠的租户只能靠各自的认证密钥区分
+            let distinct_keys = tenant.auth_key.is_some()
+                && other.auth_key.is_some()
+                && !tenant
+                    .auth_keys()
+                    .any(|key| other.auth_keys().any(|k| k == key));
+            let shared_alphabet = tenant
+                .alphabets()
+                .any(|alphabet| other.alphabets().any(|a| a == alphabet));
+            if shared_alphabet && !distinct_keys {
+                return Err(NeoError::Other(format!(
+                    "Tenants {} and {} share an alphabet and need distinct auth keys",
+                    other.name, tenant.name
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+// 租户的运行时状态
+pub struct Tenant {
+    pub options: TenantOptions,
+    pub codec: Rotation<Codec>,
+    #[cfg(feature = "auth")]
+    pub auth: Option<Authenticator>,
+    usage: Arc<AtomicU64>,
+}
+
+impl Tenant {
+    /// 根据配置构建，同名租户的流量统计和未变化的重放缓存沿用上一份状态
+    pub fn new(
+        options: &TenantOptions,
+        config: &Config,
+        previous: Option<&Tenant>,
+    ) -> Result<Self, NeoError> {
+        let codec = Codec::rotation(
+            config.blv_profile()?,
+            Some(options.alphabet()),
+            &options.previous_alphabets,
+        )?;
+
+        #[cfg(feature = "auth")]
+        let auth = {
+            let auth_options = crate::config::AuthOptions {
+                key: options.auth_key.clone(),
+                previous_keys: options.previous_auth_keys.clone(),
+                ..config.auth.clone()
+            };
+            match previous.and_then(|p| p.auth.as_ref()) {
+                Some(auth) if *auth.options() == auth_options => Some(auth.clone()),
+                _ => Authenticator::new(&auth_options),
+            }
+        };
+
+        Ok(Tenant {
+            options: options.clone(),
+            codec,
+            #[cfg(feature = "auth")]
+            auth,
+            usage: previous.map(|p| Arc::clone(&p.usage)).unwrap_or_default(),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.options.name
+    }
+
+    /// 累计转发的字节数
+    pub fn usage(&self) -> u64 {
+        self.usage.load(Ordering::Relaxed)
+    }
+
+    /// 是否已用完流量配额
+    pub fn over_quota(&self) -> bool {
+        self.options.quota_bytes > 0 && self.usage() >= self.options.quota_bytes
+    }
+
+    /// 记录转发的字节数
+    pub fn consume(&self, bytes: usize) {
+        self.usage.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tenant(name: &str, alphabet: Option<&str>, auth_key: Option<&str>) -> TenantOptions {
+        TenantOptions {
+            name: name.to_string(),
+            alphabet: alphabet.map(str::to_string),
+            auth_key: auth_key.map(str::to_string),
+            ..TenantOptions::default()
+        }
+    }
+
+    // 测试租户必须能被唯一识别
+    #[test]
+    fn test_validate() {
+        let reversed: String = crate::EN.iter().rev().map(|&b| b as char).collect();
+        assert!(
+            validate(&[
+                tenant("red", None, None),
+                tenant("blue", Some(&reversed), None)
+            ])
+            .is_ok()
+        );
+        assert!(validate(&[tenant("red", None, None), tenant("blue", None, None)]).is_err());
+        assert!(
+            validate(&[
+                tenant("red", None, None),
+                tenant("red", Some(&reversed), None)
+            ])
+            .is_err()
+        );
+        assert!(validate(&[tenant("", None, None)]).is_err());
+
+        // 旧编码表与其他租户的编码表重叠
+        let mut rotated = tenant("blue", Some(&reversed), None);
+        rotated.previous_alphabets = vec![PreviousAlphabet {
+            alphabet: String::from_utf8(crate::DE.to_vec()).unwrap(),
+            not_after: std::time::SystemTime::now(),
+        }];
+        assert!(validate(&[tenant("red", None, None), rotated]).is_err());
+        assert!(validate(&[tenant("red", Some("abc"), None)]).is_err());
+        #[cfg(feature = "auth")]
+        {
+            assert!(
+                validate(&[
+                    tenant("red", None, Some("a")),
+                    tenant("blue", None, Some("b"))
+                ])
+                .is_ok()
+            );
+            assert!(
+                validate(&[
+                    tenant("red", None, Some("a")),
+                    tenant("blue", None, Some("a"))
+                ])
+                .is_err()
+            );
+            assert!(
+                validate(&[tenant("red", None, Some("a")), tenant("blue", None, None)]).is_err()
+            );
+        }
+    }
+
+    // 测试流量配额在重新加载后继续累计
+    #[test]
+    fn test_quota() {
+        let options = TenantOptions {
+            quota_bytes: 100,
+            ..tenant("red", None, None)
+        };
+        let config = Config::default();
+        let first = Tenant::new(&options, &config, None).expect("Tenant failed");
+        first.consume(60);
+        assert!(!first.over_quota());
+
+        let reloaded = Tenant::new(&options, &config, Some(&first)).expect("Tenant failed");
+        reloaded.consume(40);
+        assert!(reloaded.over_quota());
+        assert!(first.over_quota());
+        assert_eq!(reloaded.usage(), 100);
+    }
+}
@@ -0,0 +1,427 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::crypto::{CryptoProvider, ring};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::sign::{CertifiedKey, SingleCertAndKey};
+use tokio_rustls::rustls::{self, RootCertStore, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use x509_parser::extensions::GeneralName;
+
+use crate::commands::{self, Context, Peer};
+use crate::config::TlsOptions;
+use crate::errors::NeoError;
+use crate::http;
+use crate::log;
+
+/// TLS握手的超时时间
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+// 由HTTPS配置派生的TLS状态，随运行时状态一起替换
+pub struct Tls {
+    pub certificate: Arc<CertifiedKey>,
+    pub acceptor: TlsAcceptor,
+    /// 是否要求客户端证书
+    pub client_auth: bool,
+}
+
+/// 按配置加载证书并创建TLS接收器
+///
+/// 配置了证书文件时每次都重新读取，证书续期后重新加载配置即可生效；
+/// 自签名证书只在启动时生成一次，之后沿用`previous`。
+pub fn load(options: &TlsOptions, previous: Option<&Tls>) -> Result<Option<Tls>, NeoError> {
+    if options.listen.is_empty() {
+        return Ok(None);
+    }
+    let certificate = match (&options.cert, &options.key, previous) {
+        (Some(cert), Some(key), _) => Arc::new(load_pem(cert, key)?),
+        (_, _, Some(previous)) => Arc::clone(&previous.certificate),
+        _ => Arc::new(self_signed(options)?),
+    };
+
+    let builder = ServerConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .map_err(|e: rustls::Error| NeoError::Other(e.to_string()))?;
+    // 配置了客户端CA时只接受由其签发证书的客户端
+    let builder = match &options.client_ca {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(path)? {
+                roots.add(cert).map_err(|e| load_error(path, &e))?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider())
+                .build()
+                .map_err(|e| load_error(path, &e))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut config =
+        builder.with_cert_resolver(Arc::new(SingleCertAndKey::from(Arc::clone(&certificate))));
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(Some(Tls {
+        certificate,
+        acceptor: TlsAcceptor::from(Arc::new(config)),
+        client_auth: options.client_ca.is_some(),
+    }))
+}
+
+fn load_error(path: &Path, e: &dyn fmt::Display) -> NeoError {
+    NeoError::Other(format!("Failed to load {}: {}", path.display(), e))
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, NeoError> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| load_error(path, &e))?;
+    if certs.is_empty() {
+        return Err(load_error(path, &"no certificates found"));
+    }
+    Ok(certs)
+}
+
+fn load_pem(cert: &Path, key: &Path) -> Result<CertifiedKey, NeoError> {
+    let chain = load_certs(cert)?;
+    let private_key = PrivateKeyDer::from_pem_file(key).map_err(|e| load_error(key, &e))?;
+    CertifiedKey::from_der(chain, private_key, &provider()).map_err(|e| load_error(key, &e))
+}
+
+// 生成自签名证书，包含localhost和监听的IP地址
+fn self_signed(options: &TlsOptions) -> Result<CertifiedKey, NeoError> {
+    let mut names = vec!["localhost".to_string()];
+    for addr in &options.listen {
+        if !addr.ip().is_unspecified() {
+            names.push(addr.ip().to_string());
+        }
+    }
+    let generated = rcgen::generate_simple_self_signed(names)
+        .map_err(|e| NeoError::Other(format!("Failed to generate certificate: {}", e)))?;
+    let private_key = PrivateKeyDer::try_from(generated.signing_key.serialize_der())
+        .map_err(|e| NeoError::Other(format!("Failed to generate certificate: {}", e)))?;
+    log::warn!("Using a generated self-signed TLS certificate");
+    CertifiedKey::from_der(vec![generated.cert.der().clone()], private_key, &provider())
+        .map_err(|e| NeoError::Other(format!("Failed to generate certificate: {}", e)))
+}
+
+/// 客户端证书的身份：主题的CN，没有时依次取第一个DNS名称、序列号；证书无法解析时为`None`
+pub fn identity(cert: &CertificateDer<'_>) -> Option<String> {
+    let (_, cert) = x509_parser::parse_x509_certificate(cert).ok()?;
+    if let Some(cn) = cert
+        .subject()
+        .iter_common_name()
+        .find_map(|cn| cn.as_str().ok())
+    {
+        return Some(cn.to_string());
+    }
+    let dns_name = cert
+        .subject_alternative_name()
+        .ok()
+        .flatten()
+        .and_then(|san| {
+            san.value.general_names.iter().find_map(|name| match name {
+                GeneralName::DNSName(name) => Some(name.to_string()),
+                _ => None,
+            })
+        });
+    if dns_name.is_some() {
+        return dns_name;
+    }
+    let serial: Vec<String> = cert
+        .raw_serial()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    Some(format!("serial:{}", serial.join("")))
+}
+
+/// 接收TLS连接，在本进程内处理解密后的HTTP请求
+///
+/// 每个连接使用当前运行时状态中的接收器，重新加载配置后新连接即使用新证书和客户端CA。
+pub async fn serve(listener: TcpListener, ctx: Context) {
+    loop {
+        // 退出时停止接收连接并释放监听端口
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = ctx.shutdown_requested() => return,
+        };
+        let (stream, client) = match accepted {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                log::error!("TLS accept error: {}", e);
+                continue;
+            }
+        };
+        let Some((acceptor, client_auth)) = ctx
+            .runtime()
+            .tls
+            .as_ref()
+            .map(|t| (t.acceptor.clone(), t.client_auth))
+        else {
+            continue;
+        };
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            if let Err(e) = connection(stream, client, acceptor, client_auth, &ctx).await {
+                log::debug!("TLS connection from {} closed: {}", client, e);
+            }
+        });
+    }
+}
+
+async fn connection(
+    stream: TcpStream,
+    client: SocketAddr,
+    acceptor: TlsAcceptor,
+    client_auth: bool,
+    ctx: &Context,
+) -> std::io::Result<()> {
+    let (tls, peer) = handshake(stream, client, acceptor, client_auth).await?;
+    http::serve(tls, ctx, |request| {
+        commands::handle_http(request, ctx, &peer)
+    })
+    .await
+}
+
+// 完成TLS握手，返回加密的连接和来源：TLS客户端的地址和客户端证书身份；
+// 要求客户端证书时，得不到身份的连接被拒绝
+async fn handshake(
+    stream: TcpStream,
+    client: SocketAddr,
+    acceptor: TlsAcceptor,
+    client_auth: bool,
+) -> std::io::Result<(TlsStream<TcpStream>, Peer)> {
+    let tls = tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream))
+        .await
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "handshake timed out"))??;
+    let identity = tls
+        .get_ref()
+        .1
+        .peer_certificates()
+        .and_then(|certs| certs.first())
+        .and_then(identity);
+    match &identity {
+        Some(identity) => log::debug!("TLS client {} authenticated as {}", client, identity),
+        None if client_auth => {
+            log::warn!(
+                "TLS client {} rejected: no client certificate identity",
+                client
+            );
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "no client certificate identity",
+            ));
+        }
+        None => {}
+    }
+    Ok((
+        tls,
+        Peer {
+            addr: Some(client),
+            identity,
+            tenant: None,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::Runtime;
+    use crate::config::Config;
+    use rcgen::{CertificateParams, CertifiedIssuer, DnType, IsCa, KeyPair};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn options() -> TlsOptions {
+        TlsOptions {
+            listen: vec!["127.0.0.1:8443".parse().unwrap()],
+            self_signed: true,
+            ..TlsOptions::default()
+        }
+    }
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("neorust-tls-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // 测试自签名证书只生成一次，重新加载后沿用
+    #[test]
+    fn test_self_signed_reused() {
+        let first = load(&options(), None).unwrap().expect("No certificate");
+        let second = load(&options(), Some(&first)).unwrap().unwrap();
+        assert!(Arc::ptr_eq(&first.certificate, &second.certificate));
+        assert!(load(&TlsOptions::default(), None).unwrap().is_none());
+    }
+
+    // 测试从PEM文件加载证书，文件替换后重新加载得到新证书
+    #[test]
+    fn test_load_pem() {
+        let dir = temp_dir("pem");
+        let (cert, key) = (dir.join("cert.pem"), dir.join("key.pem"));
+        let write = || {
+            let generated = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+            std::fs::write(&cert, generated.cert.pem()).unwrap();
+            std::fs::write(&key, generated.signing_key.serialize_pem()).unwrap();
+        };
+        let options = TlsOptions {
+            cert: Some(cert.clone()),
+            key: Some(key.clone()),
+            ..options()
+        };
+
+        write();
+        let first = load(&options, None).unwrap().unwrap();
+        write();
+        let second = load(&options, Some(&first)).unwrap().unwrap();
+        assert_ne!(first.certificate.cert, second.certificate.cert);
+
+        // 证书与私钥不匹配
+        std::fs::write(&key, KeyPair::generate().unwrap().serialize_pem()).unwrap();
+        assert!(load(&options, None).is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    // 测试客户端证书身份依次取CN、DNS名称、序列号，无法解析的证书没有身份
+    #[test]
+    fn test_identity() {
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(vec!["alice.example".into()]).unwrap();
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        let cert = params.self_signed(&key).unwrap();
+        assert_eq!(identity(cert.der()), Some("alice.example".to_string()));
+
+        params.distinguished_name.push(DnType::CommonName, "alice");
+        let cert = params.self_signed(&key).unwrap();
+        assert_eq!(identity(cert.der()), Some("alice".to_string()));
+
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        params.serial_number = Some(vec![0x01, 0xab].into());
+        let cert = params.self_signed(&key).unwrap();
+        assert_eq!(identity(cert.der()), Some("serial:01ab".to_string()));
+
+        assert_eq!(
+            identity(&CertificateDer::from(vec![0x30, 0x03, 0x02])),
+            None
+        );
+    }
+
+    // 测试双向TLS：握手得到客户端地址和证书身份，解密后的请求在本进程内处理，
+    // 明文请求和没有客户端证书的连接不被处理
+    #[tokio::test]
+    async fn test_mutual_tls() {
+        let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca = CertifiedIssuer::self_signed(ca_params, KeyPair::generate().unwrap()).unwrap();
+        let dir = temp_dir("mtls");
+        let ca_path = dir.join("ca.pem");
+        std::fs::write(&ca_path, ca.pem()).unwrap();
+
+        let mut client_params = CertificateParams::new(vec!["alice.example".into()]).unwrap();
+        client_params
+            .distinguished_name
+            .push(DnType::CommonName, "alice");
+        let client_key = KeyPair::generate().unwrap();
+        let client_cert = client_params.signed_by(&client_key, &ca).unwrap();
+
+        let config = Config {
+            tls: TlsOptions {
+                client_ca: Some(ca_path),
+                ..options()
+            },
+            ..Config::default()
+        };
+        let ctx = Context::new(Runtime::new(config, None).unwrap());
+        let server_cert = ctx.runtime().tls.as_ref().unwrap().certificate.cert[0].clone();
+        let acceptor = ctx.runtime().tls.as_ref().unwrap().acceptor.clone();
+
+        let mut roots = RootCertStore::empty();
+        roots.add(server_cert).unwrap();
+        let builder = rustls::ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots);
+        let with_cert = builder
+            .clone()
+            .with_client_auth_cert(
+                vec![client_cert.der().clone()],
+                PrivateKeyDer::try_from(client_key.serialize_der()).unwrap(),
+            )
+            .unwrap();
+        let connect = |addr: SocketAddr, config: rustls::ClientConfig| async move {
+            let stream = TcpStream::connect(addr).await.unwrap();
+            let local = stream.local_addr().unwrap();
+            let connector = tokio_rustls::TlsConnector::from(Arc::new(config));
+            let tls = connector
+                .connect("localhost".try_into().unwrap(), stream)
+                .await;
+            (local, tls)
+        };
+
+        // 握手得到的来源
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accepted = tokio::spawn(async move {
+            let (stream, client) = listener.accept().await.unwrap();
+            handshake(stream, client, acceptor, true)
+                .await
+                .map(|(_, peer)| peer)
+        });
+        let (client_addr, _tls) = connect(addr, with_cert.clone()).await;
+        assert_eq!(
+            accepted.await.unwrap().expect("Handshake failed"),
+            Peer {
+                addr: Some(client_addr),
+                identity: Some("alice".to_string()),
+                tenant: None,
+            }
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, ctx.clone()));
+        let request = b"POST / HTTP/1.1\r\nContent-Length: 3\r\nConnection: close\r\n\r\nabc";
+        let (_, tls) = connect(addr, with_cert).await;
+        let mut tls = tls.unwrap();
+        tls.write_all(request).await.unwrap();
+        let mut response = Vec::new();
+        let _ = tls.read_to_end(&mut response).await;
+        let hello = ctx
+            .runtime()
+            .codec
+            .current()
+            .base64_decode(crate::NEO_HELLO)
+            .unwrap();
+        assert!(response.starts_with(b"HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(&hello));
+
+        // 明文请求不会被当作HTTP处理
+        let mut plain = TcpStream::connect(addr).await.unwrap();
+        plain.write_all(request).await.unwrap();
+        let mut response = Vec::new();
+        let _ = plain.read_to_end(&mut response).await;
+        assert!(!response.starts_with(b"HTTP/"));
+
+        // TLS 1.3下客户端证书在握手完成后才被校验，拒绝体现在之后的读取上
+        let (_, tls) = connect(addr, builder.with_no_client_auth()).await;
+        if let Ok(mut tls) = tls {
+            let _ = tls.write_all(request).await;
+            let mut buf = [0u8; 16];
+            assert!(tls.read(&mut buf).await.is_err());
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,550 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::audit;
+use crate::commands::Context;
+use crate::errors::NeoError;
+use crate::log;
+use crate::session::{Handover, Session};
+
+/// 新进程从该环境变量取得交接通道的文件描述符
+const ENV: &str = "NEORUST_UPGRADE_FD";
+/// 等待新进程加载配置、确认接管的时间
+const REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+/// 新进程已加载配置，可以开始交接
+const READY: u8 = b'R';
+/// 新进程已开始接收请求，旧进程可以退出
+const DONE: u8 = b'D';
+/// 单个会话状态的最大长度
+const MAX_RECORD: usize = 64 * 1024 * 1024;
+
+// 已加载配置、等待交接的新进程：pid和交接通道
+static READY_PROCESS: Mutex<Option<(u32, UnixStream)>> = Mutex::new(None);
+static IN_PROGRESS: AtomicBool = AtomicBool::new(false);
+
+// 交接的会话：标记、到目标的连接和状态
+type Detached = (String, TcpStream, Handover);
+
+/// 启动新的可执行文件，新进程加载配置后请求以"upgrade"为原因退出
+///
+/// program为空时使用当前可执行文件，新进程使用相同的命令行参数；新进程启动失败或配置无效时
+/// 当前进程继续运行。返回新进程的pid。
+pub async fn start(ctx: &Context, program: Option<PathBuf>) -> Result<u32, NeoError> {
+    if IN_PROGRESS.swap(true, Ordering::SeqCst) {
+        return Err(NeoError::Other("Upgrade already in progress".to_string()));
+    }
+    let spawned = tokio::task::spawn_blocking(move || spawn(program))
+        .await
+        .map_err(|e| NeoError::Other(e.to_string()))
+        .and_then(|result| result);
+    match spawned {
+        Ok((pid, channel)) => {
+            log::warn!("New process {} is ready, handing over", pid);
+            *READY_PROCESS.lock().expect("upgrade state poisoned") = Some((pid, channel));
+            ctx.request_shutdown("upgrade");
+            Ok(pid)
+        }
+        Err(e) => {
+            IN_PROGRESS.store(false, Ordering::SeqCst);
+            Err(e)
+        }
+    }
+}
+
+// 启动新进程，等待其加载配置
+fn spawn(program: Option<PathBuf>) -> Result<(u32, UnixStream), NeoError> {
+    let program = match program {
+        Some(program) => program,
+        None => current_exe()?,
+    };
+    let (channel, theirs) = UnixStream::pair().map_err(NeoError::Io)?;
+    let fd = theirs.as_raw_fd();
+    let mut command = Command::new(&program);
+    command
+        .args(std::env::args_os().skip(1))
+        .env(ENV, fd.to_string());
+    // 交接通道只由新进程继承
+    unsafe {
+        command.pre_exec(move || match libc::fcntl(fd, libc::F_SETFD, 0) {
+            -1 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        });
+    }
+    let mut child = command
+        .spawn()
+        .map_err(|e| NeoError::Other(format!("Failed to start {}: {}", program.display(), e)))?;
+    drop(theirs);
+
+    match reply(&channel) {
+        Ok(READY) => Ok((child.id(), channel)),
+        result => {
+            let _ = child.kill();
+            let _ = child.wait();
+            Err(NeoError::Other(match result {
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    "New process exited before it was ready".to_string()
+                }
+                Err(e) => format!("New process was not ready: {}", e),
+                Ok(_) => "Unexpected reply from the new process".to_string(),
+            }))
+        }
+    }
+}
+
+// 当前可执行文件；文件已被新版本替换时去掉Linux附加的" (deleted)"后缀
+fn current_exe() -> Result<PathBuf, NeoError> {
+    let path = std::env::current_exe().map_err(NeoError::Io)?;
+    Ok(
+        match path.to_str().and_then(|p| p.strip_suffix(" (deleted)")) {
+            Some(path) => PathBuf::from(path),
+            None => path,
+        },
+    )
+}
+
+/// 将监听套接字和所有会话交接给新进程，新进程开始接收请求后返回其pid和交接的会话数
+///
+/// 调用前应已停止接收请求，并等待正在处理的请求完成。交接失败时结束新进程，会话放回注册表，
+/// 之后可以再次升级。
+pub async fn hand_over(
+    ctx: &Context,
+    listeners: Vec<TcpListener>,
+) -> Result<(u32, usize), NeoError> {
+    let Some((pid, channel)) = READY_PROCESS.lock().expect("upgrade state poisoned").take() else {
+        IN_PROGRESS.store(false, Ordering::SeqCst);
+        return Err(NeoError::Other("No new process is ready".to_string()));
+    };
+    let sessions: Vec<(String, Session)> = ctx.sessions.lock().await.drain().collect();
+    let mut detached = Vec::with_capacity(sessions.len());
+    for (mark, session) in sessions {
+        match session.detach().await {
+            Ok((stream, state)) => detached.push((mark, stream, state)),
+            Err(e) => {
+                log::warn!("Failed to detach session {}: {}", mark, e);
+                session.close().await;
+                if let Some(audit) = &ctx.runtime().audit {
+                    audit.record(&audit::Event::Evict {
+                        mark: &mark,
+                        session: &session,
+                        reason: "upgrade",
+                    });
+                }
+            }
+        }
+    }
+    let count = detached.len();
+    let (sent, detached) = tokio::task::spawn_blocking(move || {
+        let sent = send(&channel, &listeners, &detached);
+        (sent, detached)
+    })
+    .await
+    .map_err(|e| NeoError::Other(e.to_string()))?;
+    match sent {
+        Ok(()) => Ok((pid, count)),
+        Err(e) => {
+            abort(ctx, pid, detached).await;
+            Err(NeoError::Other(format!(
+                "Handover to process {} failed: {}",
+                pid, e
+            )))
+        }
+    }
+}
+
+// 交接失败：结束可能已收到部分会话的新进程，由本进程恢复这些会话
+async fn abort(ctx: &Context, pid: u32, detached: Vec<Detached>) {
+    unsafe {
+        libc::kill(pid as libc::pid_t, libc::SIGKILL);
+        libc::waitpid(pid as libc::pid_t, std::ptr::null_mut(), 0);
+    }
+    let options = ctx.runtime().config.session;
+    let mut sessions = ctx.sessions.lock().await;
+    for (mark, stream, state) in detached {
+        sessions.insert(mark, Session::resume(stream, &options, state));
+    }
+    IN_PROGRESS.store(false, Ordering::SeqCst);
+}
+
+// 依次发送监听套接字和会话的数量、每个监听套接字、每个会话的连接和状态，然后等待新进程确认
+fn send(channel: &UnixStream, listeners: &[TcpListener], sessions: &[Detached]) -> io::Result<()> {
+    let mut writer = channel;
+    writer.write_all(&(listeners.len() as u32).to_be_bytes())?;
+    writer.write_all(&(sessions.len() as u32).to_be_bytes())?;
+    for listener in listeners {
+        send_fd(channel, listener.as_fd())?;
+    }
+    for (mark, stream, state) in sessions {
+        send_fd(channel, stream.as_fd())?;
+        let record = encode(mark, state);
+        writer.write_all(&(record.len() as u32).to_be_bytes())?;
+        writer.write_all(&record)?;
+    }
+    match reply(channel)? {
+        DONE => Ok(()),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unexpected reply",
+        )),
+    }
+}
+
+fn reply(channel: &UnixStream) -> io::Result<u8> {
+    channel.set_read_timeout(Some(REPLY_TIMEOUT))?;
+    let mut byte = [0];
+    let mut reader = channel;
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+/// 由旧进程启动时取得交接通道
+pub fn inherited() -> Result<Option<UnixStream>, NeoError> {
+    let Ok(fd) = std::env::var(ENV) else {
+        return Ok(None);
+    };
+    let invalid = || NeoError::Other(format!("Invalid {}: {}", ENV, fd));
+    let raw: RawFd = fd.parse().map_err(|_| invalid())?;
+    // 确认文件描述符有效，并避免再被之后启动的进程继承
+    if unsafe { libc::fcntl(raw, libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+        return Err(invalid());
+    }
+    Ok(Some(unsafe { UnixStream::from_raw_fd(raw) }))
+}
+
+/// 通知旧进程已加载配置，接收其交接的监听套接字和会话
+///
+/// 旧进程在等待正在处理的请求完成后才开始发送，因此没有超时。
+pub fn receive(channel: &UnixStream) -> io::Result<(Vec<TcpListener>, Vec<Detached>)> {
+    let mut io = channel;
+    io.write_all(&[READY])?;
+    let mut count = [0; 4];
+    io.read_exact(&mut count)?;
+    let listeners = u32::from_be_bytes(count);
+    io.read_exact(&mut count)?;
+    let sessions = u32::from_be_bytes(count);
+
+    let listeners = (0..listeners)
+        .map(|_| recv_fd(channel).map(TcpListener::from))
+        .collect::<io::Result<Vec<_>>>()?;
+    let mut detached = Vec::new();
+    for _ in 0..sessions {
+        let stream = TcpStream::from(recv_fd(channel)?);
+        io.read_exact(&mut count)?;
+        let len = u32::from_be_bytes(count) as usize;
+        if len > MAX_RECORD {
+            return Err(invalid_data());
+        }
+        let mut record = vec![0; len];
+        io.read_exact(&mut record)?;
+        let (mark, state) = decode(&record)?;
+        detached.push((mark, stream, state));
+    }
+    Ok((listeners, detached))
+}
+
+/// 通知旧进程已开始接收请求，旧进程随后退出
+pub fn done(channel: &UnixStream) -> io::Result<()> {
+    let mut writer = channel;
+    writer.write_all(&[DONE])
+}
+
+// 发送一个字节，附带一个文件描述符
+fn send_fd(channel: &UnixStream, fd: BorrowedFd<'_>) -> io::Result<()> {
+    let mut byte = [0u8];
+    let mut iov = libc::iovec {
+        iov_base: byte.as_mut_ptr().cast(),
+        iov_len: 1,
+    };
+    // 按cmsghdr对齐的控制消息缓冲区
+    let mut control = [0u64; 4];
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    unsafe {
+        msg.msg_controllen = libc::CMSG_SPACE(size_of::<RawFd>() as u32) as _;
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(size_of::<RawFd>() as u32) as _;
+        std::ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast::<RawFd>(), fd.as_raw_fd());
+    }
+    loop {
+        if unsafe { libc::sendmsg(channel.as_raw_fd(), &msg, 0) } != -1 {
+            return Ok(());
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+// 接收send_fd发送的字节和文件描述符
+fn recv_fd(channel: &UnixStream) -> io::Result<OwnedFd> {
+    let mut byte = [0u8];
+    let mut iov = libc::iovec {
+        iov_base: byte.as_mut_ptr().cast(),
+        iov_len: 1,
+    };
+    let mut control = [0u64; 4];
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = size_of_val(&control) as _;
+    let n = loop {
+        let n = unsafe { libc::recvmsg(channel.as_raw_fd(), &mut msg, 0) };
+        if n != -1 {
+            break n;
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    };
+    if n == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        if cmsg.is_null()
+            || (*cmsg).cmsg_level != libc::SOL_SOCKET
+            || (*cmsg).cmsg_type != libc::SCM_RIGHTS
+        {
+            return Err(invalid_data());
+        }
+        let fd = std::ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast::<RawFd>());
+        let fd = OwnedFd::from_raw_fd(fd);
+        if libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(fd)
+    }
+}
+
+fn invalid_data() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid handover data")
+}
+
+// 会话状态的编码：标记、客户端、身份、租户、创建时间、连接耗时、统计和待读数据
+//
+// 整数为大端序；字节串带u32长度前缀，可选值前加一个字节标明是否存在。
+fn encode(mark: &str, state: &Handover) -> Vec<u8> {
+    fn put(out: &mut Vec<u8>, bytes: &[u8]) {
+        out.extend((bytes.len() as u32).to_be_bytes());
+        out.extend(bytes);
+    }
+    fn put_optional(out: &mut Vec<u8>, value: Option<String>) {
+        match value {
+            Some(value) => {
+                out.push(1);
+                put(out, value.as_bytes());
+            }
+            None => out.push(0),
+        }
+    }
+    let created_at = state
+        .created_at
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut out = Vec::with_capacity(128 + mark.len() + state.pending.len());
+    put(&mut out, mark.as_bytes());
+    put_optional(&mut out, state.client.map(|a| a.to_string()));
+    put_optional(&mut out, state.identity.clone());
+    put_optional(&mut out, state.tenant.clone());
+    for n in [
+        created_at.as_nanos() as u64,
+        state.connect_latency.as_nanos() as u64,
+        state.bytes_sent,
+        state.bytes_received,
+        state.messages_sent,
+        state.messages_received,
+    ] {
+        out.extend(n.to_be_bytes());
+    }
+    put(&mut out, &state.pending);
+    out
+}
+
+fn decode(record: &[u8]) -> io::Result<(String, Handover)> {
+    let mut reader = Reader(record);
+    let mark = reader.string()?;
+    let client = reader
+        .optional()?
+        .map(|a| a.parse().map_err(|_| invalid_data()))
+        .transpose()?;
+    let identity = reader.optional()?;
+    let tenant = reader.optional()?;
+    let created_at = UNIX_EPOCH + Duration::from_nanos(reader.u64()?);
+    let connect_latency = Duration::from_nanos(reader.u64()?);
+    let state = Handover {
+        client,
+        identity,
+        tenant,
+        created_at,
+        connect_latency,
+        bytes_sent: reader.u64()?,
+        bytes_received: reader.u64()?,
+        messages_sent: reader.u64()?,
+        messages_received: reader.u64()?,
+        pending: reader.bytes()?.to_vec(),
+    };
+    if !reader.0.is_empty() {
+        return Err(invalid_data());
+    }
+    Ok((mark, state))
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(invalid_data());
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        let bytes = self.take(8)?;
+        Ok(u64::from_be_bytes(bytes.try_into().expect("took 8 bytes")))
+    }
+
+    fn bytes(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.take(4)?;
+        let len = u32::from_be_bytes(len.try_into().expect("took 4 bytes"));
+        self.take(len as usize)
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|_| invalid_data())
+    }
+
+    fn optional(&mut self) -> io::Result<Option<String>> {
+        match self.take(1)?[0] {
+            0 => Ok(None),
+            1 => self.string().map(Some),
+            _ => Err(invalid_data()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+
+    fn state() -> Handover {
+        Handover {
+            client: Some("192.0.2.1:4000".parse().unwrap()),
+            identity: None,
+            tenant: Some("team-a".to_string()),
+            created_at: SystemTime::now(),
+            connect_latency: Duration::from_millis(12),
+            bytes_sent: 100,
+            bytes_received: 200,
+            messages_sent: 3,
+            messages_received: 4,
+            pending: b"pending".to_vec(),
+        }
+    }
+
+    // 测试会话状态的编码和解码
+    #[test]
+    fn test_record() {
+        let state = state();
+        let record = encode("mark-1", &state);
+        let (mark, decoded) = decode(&record).expect("Decode failed");
+        assert_eq!(mark, "mark-1");
+        assert_eq!(decoded, state);
+
+        assert!(decode(&record[..record.len() - 1]).is_err());
+        let mut extra = record.clone();
+        extra.push(0);
+        assert!(decode(&extra).is_err());
+    }
+
+    // 测试通过交接通道传递监听套接字和会话连接
+    #[test]
+    fn test_transfer() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let target = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(target.local_addr().unwrap()).unwrap();
+        let (mut accepted, _) = target.accept().unwrap();
+
+        let expected = state();
+        let sent = expected.clone();
+        let (old, new) = UnixStream::pair().unwrap();
+        let sender = std::thread::spawn(move || {
+            assert_eq!(reply(&old).unwrap(), READY);
+            send(&old, &[listener], &[("mark-1".to_string(), stream, sent)])
+        });
+
+        let (listeners, sessions) = receive(&new).expect("Receive failed");
+        done(&new).unwrap();
+        sender.join().unwrap().expect("Send failed");
+
+        assert_eq!(listeners.len(), 1);
+        assert_eq!(listeners[0].local_addr().unwrap(), addr);
+        let (mark, mut stream, handover) = sessions.into_iter().next().unwrap();
+        assert_eq!(mark, "mark-1");
+        assert_eq!(handover, expected);
+        stream.write_all(b"ping").unwrap();
+        let mut buf = [0; 4];
+        accepted.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+
+    // 测试交接失败时结束新进程，会话放回注册表并继续转发数据
+    #[tokio::test]
+    async fn test_failed_handover() {
+        use crate::commands::Runtime;
+        use crate::config::Config;
+
+        use tokio::io::AsyncReadExt;
+
+        let ctx = Context::new(Runtime::new(Config::default(), None).expect("Runtime failed"));
+        let options = ctx.runtime().config.session;
+        let target = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stream = TcpStream::connect(target.local_addr().unwrap()).unwrap();
+        let (mut accepted, _) = target.accept().await.unwrap();
+        ctx.sessions
+            .lock()
+            .await
+            .insert("mark-1".to_string(), Session::new(stream, &options));
+
+        // 新进程在交接开始前退出
+        let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+        let (channel, theirs) = UnixStream::pair().unwrap();
+        drop(theirs);
+        IN_PROGRESS.store(true, Ordering::SeqCst);
+        *READY_PROCESS.lock().unwrap() = Some((child.id(), channel));
+        ctx.request_shutdown("upgrade");
+
+        assert!(hand_over(&ctx, Vec::new()).await.is_err());
+        assert!(!IN_PROGRESS.load(Ordering::SeqCst));
+        assert!(ctx.cancel_upgrade());
+        assert!(!ctx.shutting_down());
+        // 新进程已被结束并回收
+        assert!(child.try_wait().is_err());
+
+        let sessions = ctx.sessions.lock().await;
+        let session = sessions.get("mark-1").expect("Session not resumed");
+        assert!(!session.is_closed().await);
+        session.write_async(b"ping").await.expect("Write failed");
+        let mut buf = [0; 4];
+        accepted.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+}
@@ -0,0 +1,167 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::errors::NeoError;
+
+// 每天允许活动的时段（UTC，精确到分钟），结束早于开始时跨越午夜
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DailyHours {
+    start: u32,
+    end: u32,
+}
+
+impl DailyHours {
+    /// 一天中的第几分钟是否在时段内
+    pub fn contains(&self, minute: u32) -> bool {
+        if self.start < self.end {
+            self.start <= minute && minute < self.end
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+impl FromStr for DailyHours {
+    type Err = NeoError;
+
+    fn from_str(s: &str) -> Result<Self, NeoError> {
+        let invalid = || NeoError::Other(format!("Invalid daily hours: {}", s));
+        let minute = |t: &str| -> Option<u32> {
+            let (h, m) = t.trim().split_once(':')?;
+            let (h, m) = (h.parse::<u32>().ok()?, m.parse::<u32>().ok()?);
+            // 24:00表示一天结束
+            (h < 24 && m < 60 || h == 24 && m == 0).then_some(h * 60 + m)
+        };
+        let (start, end) = s.split_once('-').ok_or_else(invalid)?;
+        let (start, end) = (
+            minute(start).ok_or_else(invalid)?,
+            minute(end).ok_or_else(invalid)?,
+        );
+        if start == end || start == 24 * 60 {
+            return Err(invalid());
+        }
+        Ok(DailyHours {
+            start,
+            end: end % (24 * 60),
+        })
+    }
+}
+
+impl fmt::Display for DailyHours {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let end = if self.end == 0 { 24 * 60 } else { self.end };
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02}",
+            self.start / 60,
+            self.start % 60,
+            end / 60,
+            end % 60
+        )
+    }
+}
+
+// 时间窗口内的状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Active,
+    /// 尚未开始，或不在每天的活动时段内
+    Inactive,
+    /// 已过结束时间，进程应当退出
+    Expired,
+}
+
+// 活动时间窗口，未配置的部分不限制
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Window {
+    pub not_before: Option<SystemTime>,
+    /// 结束时间，之后进程自行退出
+    pub not_after: Option<SystemTime>,
+    pub daily_hours: Option<DailyHours>,
+}
+
+impl Window {
+    /// 指定时刻的状态
+    pub fn state(&self, now: SystemTime) -> State {
+        if self.not_after.is_some_and(|t| now > t) {
+            return State::Expired;
+        }
+        if self.not_before.is_some_and(|t| now < t) {
+            return State::Inactive;
+        }
+        let minute = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() % 86400 / 60;
+        match self.daily_hours {
+            Some(hours) if !hours.contains(minute as u32) => State::Inactive,
+            _ => State::Active,
+        }
+    }
+
+    /// 开始时间必须早于结束时间
+    pub fn validate(&self) -> Result<(), NeoError> {
+        if let (Some(not_before), Some(not_after)) = (self.not_before, self.not_after)
+            && not_before >= not_after
+        {
+            return Err(NeoError::Other(
+                "Window not_before must be earlier than not_after".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log::parse_timestamp;
+
+    fn at(ts: &str) -> SystemTime {
+        UNIX_EPOCH + parse_timestamp(ts).expect("Parse failed")
+    }
+
+    // 测试每天的时段解析，包括跨越午夜
+    #[test]
+    fn test_daily_hours() {
+        let office: DailyHours = "09:00-17:30".parse().expect("Parse failed");
+        assert!(office.contains(9 * 60));
+        assert!(!office.contains(17 * 60 + 30));
+        assert_eq!(office.to_string(), "09:00-17:30");
+
+        let night: DailyHours = "22:00-06:00".parse().expect("Parse failed");
+        assert!(night.contains(23 * 60));
+        assert!(night.contains(60));
+        assert!(!night.contains(12 * 60));
+
+        let evening: DailyHours = "18:00-24:00".parse().expect("Parse failed");
+        assert!(evening.contains(23 * 60 + 59));
+        assert!(!evening.contains(0));
+        assert_eq!(evening.to_string(), "18:00-24:00");
+
+        for invalid in ["09:00", "09:00-09:00", "25:00-26:00", "9-17", "24:00-06:00"] {
+            assert!(invalid.parse::<DailyHours>().is_err(), "{}", invalid);
+        }
+    }
+
+    // 测试开始前、时段外和结束后的状态
+    #[test]
+    fn test_state() {
+        let window = Window {
+            not_before: Some(at("2026-10-20")),
+            not_after: Some(at("2026-11-20")),
+            daily_hours: Some("08:00-20:00".parse().unwrap()),
+        };
+        assert_eq!(window.state(at("2026-10-19T12:00:00Z")), State::Inactive);
+        assert_eq!(window.state(at("2026-10-21T12:00:00Z")), State::Active);
+        assert_eq!(window.state(at("2026-10-21T21:00:00Z")), State::Inactive);
+        assert_eq!(window.state(at("2026-11-20T00:00:01Z")), State::Expired);
+        assert_eq!(Window::default().state(SystemTime::now()), State::Active);
+
+        assert!(window.validate().is_ok());
+        let reversed = Window {
+            not_before: window.not_after,
+            not_after: window.not_before,
+            daily_hours: None,
+        };
+        assert!(reversed.validate().is_err());
+    }
+}